@@ -0,0 +1,374 @@
+//! The hashing algorithms blocks can be mined with, and the registry listing them.
+//!
+//! Adding an algorithm takes implementing [`PowHash`] for it and listing it in [`ALGORITHMS`].
+//! The seal, the difficulty thresholds, the fork rules, the runtime's difficulty adjustment
+//! and the command line all look algorithms up in the registry.
+
+use core::{fmt, str::FromStr};
+
+use parity_scale_codec::{Decode, Encode, MaxEncodedLen};
+#[cfg(feature = "std")]
+use sha3::{Digest, Keccak256, Sha3_256};
+use sp_core::H256;
+
+/// Most mining algorithms there can be, the number of difficulties a [`crate::Threshold`]
+/// holds
+pub const MAX_ALGOS: usize = 8;
+
+/// Identifier of a mining algorithm, recorded in the pre-digest and seal of the blocks mined
+/// with it. Encodes as a single byte, like the enum it replaces.
+#[derive(
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Encode,
+    Decode,
+    MaxEncodedLen,
+    Debug,
+    scale_info::TypeInfo,
+)]
+pub struct AlgoId(pub u8);
+
+impl AlgoId {
+    /// The registered algorithm with this id, if there is one
+    pub fn algorithm(self) -> Option<&'static Algorithm> {
+        ALGORITHMS.iter().find(|algorithm| algorithm.id == self)
+    }
+
+    /// Position of the algorithm's difficulty in a threshold
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Default for AlgoId {
+    fn default() -> Self {
+        Sha3::ID
+    }
+}
+
+impl fmt::Display for AlgoId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.algorithm() {
+            Some(algorithm) => f.write_str(algorithm.name),
+            None => write!(f, "unknown algorithm {}", self.0),
+        }
+    }
+}
+
+impl FromStr for AlgoId {
+    type Err = ();
+
+    /// The registered algorithm going by the name `s`, in any case
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALGORITHMS
+            .iter()
+            .find(|algorithm| {
+                core::iter::once(&algorithm.name)
+                    .chain(algorithm.aliases)
+                    .any(|name| name.eq_ignore_ascii_case(s))
+            })
+            .map(|algorithm| algorithm.id)
+            .ok_or(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl serde::Serialize for AlgoId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.algorithm() {
+            Some(algorithm) => serializer.serialize_str(algorithm.name),
+            None => serializer.serialize_u8(self.0),
+        }
+    }
+}
+
+/// Reads the name of a registered algorithm, or the id of any, as [`serde::Serialize`] writes
+/// them. Ids as strings are accepted too, as JSON map keys are strings.
+#[cfg(feature = "std")]
+impl<'de> serde::Deserialize<'de> for AlgoId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AlgoIdVisitor;
+
+        impl serde::de::Visitor<'_> for AlgoIdVisitor {
+            type Value = AlgoId;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("the name or id of a mining algorithm")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, name: &str) -> Result<AlgoId, E> {
+                name.parse()
+                    .or_else(|_| name.parse().map(AlgoId))
+                    .map_err(|_| E::custom(format!("unknown algorithm {}", name)))
+            }
+
+            fn visit_u64<E: serde::de::Error>(self, id: u64) -> Result<AlgoId, E> {
+                u8::try_from(id)
+                    .map(AlgoId)
+                    .map_err(|_| E::custom(format!("algorithm id {} out of range", id)))
+            }
+        }
+
+        deserializer.deserialize_any(AlgoIdVisitor)
+    }
+}
+
+/// A hashing algorithm blocks can be mined with
+pub trait PowHash {
+    /// Identifier of the algorithm. Must be below [`MAX_ALGOS`], and never change or be reused
+    /// once blocks are mined with it.
+    const ID: AlgoId;
+    /// Lowercase name of the algorithm, on the command line, in RPC and in chain specs
+    const NAME: &'static str;
+    /// Other names the command line accepts for it
+    const ALIASES: &'static [&'static str] = &[];
+    /// Difficulty of the first blocks mined with it, for new chain specs. Algorithms hashing
+    /// faster start harder, to start out at about the same block time.
+    const INITIAL_DIFFICULTY: u128 = 2_000_000;
+
+    /// Hash of the encoded attempt to solve the proof of work
+    #[cfg(feature = "std")]
+    fn hash(data: &[u8], params: &HashParams) -> H256;
+}
+
+/// Parameters of the algorithms set in the chain spec. Every node of a chain must use the same.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HashParams {
+    /// Memory cost of [`Scrypt`], each hash taking `2^scrypt_log_n` KiB
+    pub scrypt_log_n: u8,
+}
+
+impl HashParams {
+    /// Highest memory cost of [`Scrypt`], 1 MiB per hash. Nodes verify a hash for every block
+    /// they are sent, valid or not, so it is kept low enough for that to stay cheap.
+    pub const MAX_SCRYPT_LOG_N: u8 = 10;
+
+    /// The parameters with a memory cost of `2^scrypt_log_n` KiB for [`Scrypt`], if it is
+    /// between 1 and [`Self::MAX_SCRYPT_LOG_N`]
+    pub fn with_scrypt_log_n(scrypt_log_n: u32) -> Option<Self> {
+        (1..=Self::MAX_SCRYPT_LOG_N as u32)
+            .contains(&scrypt_log_n)
+            .then_some(Self {
+                scrypt_log_n: scrypt_log_n as u8,
+            })
+    }
+}
+
+impl Default for HashParams {
+    /// 128 KiB per [`Scrypt`] hash, as in Litecoin
+    fn default() -> Self {
+        Self { scrypt_log_n: 7 }
+    }
+}
+
+/// A [`PowHash`] as listed in the registry
+#[derive(Clone, Copy, Debug)]
+pub struct Algorithm {
+    pub id: AlgoId,
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    pub initial_difficulty: u128,
+    #[cfg(feature = "std")]
+    hash: fn(&[u8], &HashParams) -> H256,
+}
+
+impl Algorithm {
+    pub const fn of<H: PowHash>() -> Self {
+        Self {
+            id: H::ID,
+            name: H::NAME,
+            aliases: H::ALIASES,
+            initial_difficulty: H::INITIAL_DIFFICULTY,
+            #[cfg(feature = "std")]
+            hash: H::hash,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    pub fn hash(&self, data: &[u8], params: &HashParams) -> H256 {
+        (self.hash)(data, params)
+    }
+}
+
+/// Every algorithm blocks can be mined with, by id
+pub const ALGORITHMS: &[Algorithm] = &[
+    Algorithm::of::<Md5>(),
+    Algorithm::of::<Sha3>(),
+    Algorithm::of::<Keccak>(),
+    Algorithm::of::<Sha256d>(),
+    Algorithm::of::<Blake2b256>(),
+    Algorithm::of::<Scrypt>(),
+];
+
+// Ids must fit in a threshold, and tell the algorithms apart
+const _: () = {
+    let mut i = 0;
+    while i < ALGORITHMS.len() {
+        assert!(ALGORITHMS[i].id.0 < MAX_ALGOS as u8);
+        let mut j = 0;
+        while j < i {
+            assert!(ALGORITHMS[i].id.0 != ALGORITHMS[j].id.0);
+            j += 1;
+        }
+        i += 1;
+    }
+};
+
+pub struct Md5;
+
+impl PowHash for Md5 {
+    const ID: AlgoId = AlgoId(0);
+    const NAME: &'static str = "md5";
+    const ALIASES: &'static [&'static str] = &["md"];
+    // About twice as fast as the others on common CPUs
+    const INITIAL_DIFFICULTY: u128 = 4_000_000;
+
+    #[cfg(feature = "std")]
+    fn hash(data: &[u8], _params: &HashParams) -> H256 {
+        // The md5 is only 16 byte output, so we just concatenate it twice to
+        // get an H256
+        let bytes = *md5::compute(data);
+        let mut doubled = [0u8; 32];
+        doubled[0..16].copy_from_slice(&bytes[0..16]);
+        doubled[16..32].copy_from_slice(&bytes[0..16]);
+
+        H256::from(doubled)
+    }
+}
+
+pub struct Sha3;
+
+impl PowHash for Sha3 {
+    const ID: AlgoId = AlgoId(1);
+    const NAME: &'static str = "sha3";
+    const ALIASES: &'static [&'static str] = &["sha"];
+
+    #[cfg(feature = "std")]
+    fn hash(data: &[u8], _params: &HashParams) -> H256 {
+        H256::from_slice(Sha3_256::digest(data).as_slice())
+    }
+}
+
+pub struct Keccak;
+
+impl PowHash for Keccak {
+    const ID: AlgoId = AlgoId(2);
+    const NAME: &'static str = "keccak";
+
+    #[cfg(feature = "std")]
+    fn hash(data: &[u8], _params: &HashParams) -> H256 {
+        H256::from_slice(Keccak256::digest(data).as_slice())
+    }
+}
+
+/// Bitcoin's double SHA-256
+pub struct Sha256d;
+
+impl PowHash for Sha256d {
+    const ID: AlgoId = AlgoId(3);
+    const NAME: &'static str = "sha256d";
+
+    #[cfg(feature = "std")]
+    fn hash(data: &[u8], _params: &HashParams) -> H256 {
+        H256::from(sp_core::hashing::sha2_256(&sp_core::hashing::sha2_256(
+            data,
+        )))
+    }
+}
+
+/// BLAKE2b with a 256 bit output
+pub struct Blake2b256;
+
+impl PowHash for Blake2b256 {
+    const ID: AlgoId = AlgoId(4);
+    const NAME: &'static str = "blake2b";
+    const ALIASES: &'static [&'static str] = &["blake2b256", "blake2"];
+
+    #[cfg(feature = "std")]
+    fn hash(data: &[u8], _params: &HashParams) -> H256 {
+        H256::from(sp_core::hashing::blake2_256(data))
+    }
+}
+
+/// scrypt, memory hard so that GPUs and ASICs don't outpace CPUs by much. As in Litecoin, the
+/// data is its own salt. The memory cost comes from [`HashParams`], with `r = 8` and `p = 1`.
+pub struct Scrypt;
+
+impl PowHash for Scrypt {
+    const ID: AlgoId = AlgoId(5);
+    const NAME: &'static str = "scrypt";
+    // About a hundred times slower than the others at the default memory cost
+    const INITIAL_DIFFICULTY: u128 = 16_000;
+
+    #[cfg(feature = "std")]
+    fn hash(data: &[u8], params: &HashParams) -> H256 {
+        let mut output = [0u8; 32];
+        crate::scrypt::scrypt(data, data, params.scrypt_log_n, 8, 1, &mut output);
+
+        H256::from(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algo_ids_deserialize_from_what_they_serialize_to() {
+        for id in [Md5::ID, Scrypt::ID, AlgoId(7)] {
+            let json = serde_json::to_string(&id).unwrap();
+            assert_eq!(serde_json::from_str::<AlgoId>(&json).unwrap(), id);
+        }
+        assert_eq!(serde_json::to_string(&AlgoId(7)).unwrap(), "7");
+    }
+
+    #[test]
+    fn algo_ids_deserialize_from_names_aliases_and_ids() {
+        let parse = |json| serde_json::from_str::<AlgoId>(json).ok();
+
+        assert_eq!(parse("\"sha3\""), Some(Sha3::ID));
+        assert_eq!(parse("\"Blake2\""), Some(Blake2b256::ID));
+        assert_eq!(parse("\"7\""), Some(AlgoId(7)));
+        assert_eq!(parse("\"sha512\""), None);
+        assert_eq!(parse("256"), None);
+    }
+
+    #[test]
+    fn thresholds_round_trip_through_json() {
+        let threshold: crate::Threshold = [
+            (Md5::ID, 4_000_000.into()),
+            (Scrypt::ID, 2_000.into()),
+            (AlgoId(7), 1.into()),
+        ]
+        .into_iter()
+        .collect();
+
+        let json = serde_json::to_string(&threshold).unwrap();
+        let decoded: crate::Threshold = serde_json::from_str(&json).unwrap();
+        // Unregistered algorithms are left out of the map
+        assert_eq!(decoded.get(Md5::ID), threshold.get(Md5::ID));
+        assert_eq!(decoded.get(Scrypt::ID), threshold.get(Scrypt::ID));
+        assert_eq!(decoded.get(AlgoId(7)), 0.into());
+    }
+
+    #[test]
+    fn scrypt_memory_cost_is_capped() {
+        assert_eq!(HashParams::with_scrypt_log_n(0), None);
+        assert_eq!(
+            HashParams::with_scrypt_log_n(HashParams::MAX_SCRYPT_LOG_N.into()),
+            Some(HashParams {
+                scrypt_log_n: HashParams::MAX_SCRYPT_LOG_N
+            })
+        );
+        assert_eq!(
+            HashParams::with_scrypt_log_n(u32::from(HashParams::MAX_SCRYPT_LOG_N) + 1),
+            None
+        );
+        assert!(HashParams::default().scrypt_log_n <= HashParams::MAX_SCRYPT_LOG_N);
+    }
+}
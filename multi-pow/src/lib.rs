@@ -9,27 +9,27 @@
 //! In any case, the point is that we want to demonstrate hard and soft forks in an understandable way,
 //! the multiple hashing algorithms achieves that well.
 //!
-//! In the future, the hope is that there will be a dedicated difficulty threshold for each hashing algorithm.
-//! But currently the Substrate PoW crates are not that flexible.
-//! We could solve it by adding a pre-digest that includes information about what hashing algo is being used
-//! for the runtime to use later in the difficulty adjustment.
+//! Each hashing algorithm has a dedicated difficulty threshold. The pre-digest records which one a
+//! block is mined with, for the runtime to use later in the difficulty adjustment.
+//! The algorithms are listed in a registry, see [`hashes`].
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
 use core::str::FromStr;
 #[cfg(feature = "std")]
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use parity_scale_codec::{Decode, Encode};
 #[cfg(feature = "std")]
 use sc_consensus_pow::{Error, PowAlgorithm};
 #[cfg(feature = "std")]
-use sha3::{Digest, Keccak256, Sha3_256};
+use serde::{de::Error as _, ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
 #[cfg(feature = "std")]
 use sp_api::ProvideRuntimeApi;
 #[cfg(feature = "std")]
-use sp_consensus_pow::DifficultyApi;
-#[cfg(feature = "std")]
 use sp_consensus_pow::Seal as RawSeal;
 use sp_consensus_pow::TotalDifficulty;
 use sp_core::{H256, U256};
@@ -38,8 +38,22 @@ use sp_runtime::generic::BlockId;
 #[cfg(feature = "std")]
 use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
 
+pub mod hashes;
+#[cfg(feature = "std")]
+pub mod scrypt;
+
+pub use hashes::{AlgoId, Algorithm, HashParams, PowHash, ALGORITHMS, MAX_ALGOS};
+use hashes::{Keccak, Md5, Sha3};
+
 /// A struct that represents a difficulty threshold.
-/// Unlike a normal PoW algorithm this struct has a separate threshold for each hash
+/// Unlike a normal PoW algorithm this struct has a separate threshold for each hash, a map
+/// from the id of each algorithm to its difficulty. Algorithms without one have 0.
+///
+/// It encodes as `MAX_ALGOS` difficulties, where it used to encode as one for each of md5,
+/// sha3 and keccak. Nodes keep the total difficulty of every block in their database in this
+/// encoding, and ask the runtime for difficulties in it, so neither databases nor runtimes
+/// from before carry over: chains started earlier have to be started anew, and nodes of them
+/// purged with `purge-chain`. The same goes for any change of `MAX_ALGOS`.
 #[derive(
     Clone,
     Copy,
@@ -53,10 +67,58 @@ use sp_runtime::traits::{Block as BlockT, Header as HeaderT};
     Default,
     scale_info::TypeInfo,
 )]
-pub struct Threshold {
-    pub md5: U256,
-    pub sha3: U256,
-    pub keccak: U256,
+pub struct Threshold([U256; MAX_ALGOS]);
+
+impl Threshold {
+    /// The difficulty of `algo`
+    pub fn get(&self, algo: AlgoId) -> U256 {
+        self.0.get(algo.index()).copied().unwrap_or_default()
+    }
+
+    /// Sets the difficulty of `algo`, which must have an id below [`MAX_ALGOS`]
+    pub fn set(&mut self, algo: AlgoId, difficulty: U256) {
+        if let Some(entry) = self.0.get_mut(algo.index()) {
+            *entry = difficulty;
+        }
+    }
+
+    /// The difficulty of each registered algorithm
+    pub fn iter(&self) -> impl Iterator<Item = (AlgoId, U256)> + '_ {
+        ALGORITHMS
+            .iter()
+            .map(|algorithm| (algorithm.id, self.get(algorithm.id)))
+    }
+}
+
+impl FromIterator<(AlgoId, U256)> for Threshold {
+    fn from_iter<I: IntoIterator<Item = (AlgoId, U256)>>(iter: I) -> Self {
+        let mut threshold = Self::default();
+        for (algo, difficulty) in iter {
+            threshold.set(algo, difficulty);
+        }
+        threshold
+    }
+}
+
+/// Serialized as a map from the name of each registered algorithm to its difficulty
+#[cfg(feature = "std")]
+impl Serialize for Threshold {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(ALGORITHMS.len()))?;
+        for (algo, difficulty) in self.iter() {
+            map.serialize_entry(&algo, &difficulty)?;
+        }
+        map.end()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'de> Deserialize<'de> for Threshold {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let map = std::collections::BTreeMap::<AlgoId, U256>::deserialize(deserializer)
+            .map_err(D::Error::custom)?;
+        Ok(map.into_iter().collect())
+    }
 }
 
 // This trait does not seem to be fully baked in the Substrate PoW code
@@ -64,23 +126,22 @@ pub struct Threshold {
 // so I will not use it for now.
 impl TotalDifficulty for Threshold {
     fn increment(&mut self, other: Threshold) {
-        self.md5 += other.md5;
-        self.sha3 += other.sha3;
-        self.keccak += other.keccak;
+        for (total, difficulty) in self.0.iter_mut().zip(other.0) {
+            *total = total.saturating_add(difficulty);
+        }
     }
 }
 
-/// An enum that represents the supported hash types
-#[derive(Clone, Copy, PartialEq, Eq, Encode, Decode, Debug)]
-pub enum SupportedHashes {
-    Md5,
-    Sha3,
-    Keccak,
-}
+sp_api::decl_runtime_apis! {
+    /// The difficulty of the next block depending on its timestamp, for algorithms whose
+    /// difficulty decays when they go without blocks for long
+    pub trait TimedDifficultyApi {
+        /// Difficulty of each algorithm for the next block, if it has timestamp `timestamp`
+        fn difficulty_at(timestamp: u64) -> Threshold;
 
-impl Default for SupportedHashes {
-    fn default() -> Self {
-        Self::Sha3
+        /// Same as `difficulty_at`, except for the algorithms the testnet minimum difficulty
+        /// rule applies to, if the runtime has it on
+        fn min_difficulty_at(timestamp: u64) -> Threshold;
     }
 }
 
@@ -88,7 +149,7 @@ impl Default for SupportedHashes {
 ///  algorithm was used to compute it.
 #[derive(Clone, Copy, PartialEq, Eq, Encode, Decode, Debug, Default)]
 pub struct MultiHash {
-    pub algo: SupportedHashes,
+    pub algo: AlgoId,
     pub value: H256,
 }
 
@@ -104,11 +165,7 @@ pub fn simple_hash_meets_difficulty(hash: &H256, difficulty: U256) -> bool {
 }
 
 pub fn multi_hash_meets_difficulty(hash: &MultiHash, difficulty: Threshold) -> bool {
-    match hash.algo {
-        SupportedHashes::Md5 => simple_hash_meets_difficulty(&hash.value, difficulty.md5),
-        SupportedHashes::Sha3 => simple_hash_meets_difficulty(&hash.value, difficulty.sha3),
-        SupportedHashes::Keccak => simple_hash_meets_difficulty(&hash.value, difficulty.keccak),
-    }
+    simple_hash_meets_difficulty(&hash.value, difficulty.get(hash.algo))
 }
 
 /// A Seal struct that will be encoded to a Vec<u8> as used as the
@@ -131,30 +188,16 @@ pub struct Compute {
 
 #[cfg(feature = "std")]
 impl Compute {
-    pub fn compute(self, algo: SupportedHashes) -> Seal {
-        let value = match algo {
-            SupportedHashes::Md5 => {
-                // The md5 is only 16 byte output, so we just concatenate it twice to
-                // get an H256
-                let bytes = *md5::compute(&self.encode()[..]);
-                let mut doubled = [0u8; 32];
-                doubled[0..16].copy_from_slice(&bytes[0..16]);
-                doubled[16..32].copy_from_slice(&bytes[0..16]);
-
-                H256::from(doubled)
-            }
-            SupportedHashes::Sha3 => {
-                H256::from_slice(Sha3_256::digest(&self.encode()[..]).as_slice())
-            }
-            SupportedHashes::Keccak => {
-                H256::from_slice(Keccak256::digest(&self.encode()[..]).as_slice())
-            }
-        };
+    pub fn compute(self, algorithm: &Algorithm, params: &HashParams) -> Seal {
+        let value = algorithm.hash(&self.encode()[..], params);
 
         Seal {
             nonce: self.nonce,
             difficulty: self.difficulty,
-            work: MultiHash { algo, value },
+            work: MultiHash {
+                algo: algorithm.id,
+                value,
+            },
         }
     }
 }
@@ -165,23 +208,65 @@ impl Compute {
 pub struct MultiPow<C> {
     client: Arc<C>,
     fork_config: ForkingConfig,
+    /// Whether to accept blocks at the minimum difficulty after a while without blocks, as on
+    /// Bitcoin's testnet. The runtime must have the rule on as well.
+    min_difficulty_blocks: bool,
+    /// Parameters of the algorithms, from the chain spec
+    hash_params: HashParams,
 }
 
 #[cfg(feature = "std")]
 impl<C> MultiPow<C> {
-    pub fn new(client: Arc<C>, fork_config: ForkingConfig) -> Self {
+    pub fn new(
+        client: Arc<C>,
+        fork_config: ForkingConfig,
+        min_difficulty_blocks: bool,
+        hash_params: HashParams,
+    ) -> Self {
         Self {
             client,
             fork_config,
+            min_difficulty_blocks,
+            hash_params,
         }
     }
+
+    /// The difficulty of a block on top of `parent` with timestamp `timestamp`
+    pub fn difficulty_at<B: BlockT>(
+        &self,
+        parent: B::Hash,
+        timestamp: u64,
+    ) -> Result<Threshold, Error<B>>
+    where
+        C: ProvideRuntimeApi<B>,
+        C::Api: TimedDifficultyApi<B>,
+    {
+        let runtime_api = self.client.runtime_api();
+        let difficulty = if self.min_difficulty_blocks {
+            runtime_api.min_difficulty_at(parent, timestamp)
+        } else {
+            runtime_api.difficulty_at(parent, timestamp)
+        };
+
+        difficulty.map_err(|err| {
+            sc_consensus_pow::Error::Environment(format!(
+                "Fetching difficulty from runtime failed: {:?}",
+                err
+            ))
+        })
+    }
 }
 
 //TODO could maybe derive clone_no_bound
 #[cfg(feature = "std")]
 impl<C> Clone for MultiPow<C> {
     fn clone(&self) -> Self {
-        Self::new(self.client.clone(), self.fork_config)
+        Self::new(
+            self.client.clone(),
+            self.fork_config,
+            self.min_difficulty_blocks,
+            self.hash_params,
+        )
     }
 }
 
@@ -190,24 +275,20 @@ impl<C> Clone for MultiPow<C> {
 impl<B: BlockT<Hash = H256>, C> PowAlgorithm<B> for MultiPow<C>
 where
     C: ProvideRuntimeApi<B>,
-    C::Api: DifficultyApi<B, Threshold>,
+    C::Api: TimedDifficultyApi<B>,
     C: sc_client_api::HeaderBackend<B>,
 {
     type Difficulty = Threshold;
 
+    /// The difficulty of a block mined now. Imported blocks are checked against the difficulty
+    /// at their own timestamp instead, which the node passes in as their difficulty.
     fn difficulty(&self, parent: B::Hash) -> Result<Self::Difficulty, Error<B>> {
-        let difficulty = self
-            .client
-            .runtime_api()
-            .difficulty(parent)
-            .map_err(|err| {
-                sc_consensus_pow::Error::Environment(format!(
-                    "Fetching difficulty from runtime failed: {:?}",
-                    err
-                ))
-            })?;
-
-        Ok(difficulty)
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        self.difficulty_at(parent, now)
     }
 
     fn verify(
@@ -233,7 +314,7 @@ where
         log::debug!("✅ Checksum digest passed!");
 
         // // TODO idk why this always return md5 only
-        // let algo_from_predigest = match SupportedHashes::decode(&mut &encoded_pre_digest[..]) {
+        // let algo_from_predigest = match AlgoId::decode(&mut &encoded_pre_digest[..]) {
         //     Ok(algo) => algo,
         //     Err(_) => return Ok(false),
         // };
@@ -278,10 +359,20 @@ where
             return Ok(false);
         }
 
+        let Some(algorithm) = seal.work.algo.algorithm() else {
+            return Ok(false);
+        };
+
         log::debug!("✅ Valid algorithm!");
 
+        // The miner may have worked at a higher difficulty than required, when the difficulty
+        // decayed while it was mining.
+        if seal.difficulty.get(seal.work.algo) < difficulty.get(seal.work.algo) {
+            return Ok(false);
+        }
+
         // See whether the hash meets the difficulty requirement. If not, fail fast.
-        if !multi_hash_meets_difficulty(&seal.work, difficulty) {
+        if !multi_hash_meets_difficulty(&seal.work, seal.difficulty) {
             return Ok(false);
         }
 
@@ -289,12 +380,13 @@ where
 
         // Make sure the provided work actually comes from the correct pre_hash
         let compute = Compute {
-            difficulty,
+            difficulty: seal.difficulty,
             pre_hash: *pre_hash,
             nonce: seal.nonce,
         };
 
-        if compute.compute(seal.work.algo) != seal {
+        // The costly part with memory hard algorithms, so it comes last
+        if compute.compute(algorithm, &self.hash_params) != seal {
             return Ok(false);
         }
 
@@ -321,7 +413,7 @@ pub struct ForkHeights {
 /// keccak maxis and sha3 maxis
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum MaxiPosition {
-    /// Allow all blocks, both sha3 and keccak
+    /// Allow all blocks, both sha3 and keccak, and any other algorithm but md5
     NoMaxi,
     /// Only allow sha3 blocks
     Sha3Maxi,
@@ -353,18 +445,23 @@ impl FromStr for MaxiPosition {
     }
 }
 
+impl MaxiPosition {
+    /// The position a node mining `algo` takes when following mining: a maxi of the algorithm
+    /// if the contentious fork is about it, no maxi otherwise
+    pub fn following(algo: AlgoId) -> Self {
+        match algo {
+            Sha3::ID => Self::Sha3Maxi,
+            Keccak::ID => Self::KeccakMaxi,
+            _ => Self::NoMaxi,
+        }
+    }
+}
+
 /// Manual mode, the node operator manually specifies which hashing algorithms are valid through the mining client.
 /// If you would like to do a fork, simply allow, un-allow some algorithms to check it.
-fn manual_fork_validation(_parent_number: u32, algo: SupportedHashes) -> bool {
-    use SupportedHashes::*;
-
-    // To begin with, allow all algorithms.
-    // After the fork height this check is skipped so all the hashes become valid.
-    match algo {
-        Md5 => true,
-        Sha3 => true,
-        Keccak => true,
-    }
+fn manual_fork_validation(_parent_number: u32, algo: AlgoId) -> bool {
+    // To begin with, allow all registered algorithms.
+    algo.algorithm().is_some()
 }
 
 /// In automatic mode, the `ForkHeights` and `MaxiPosition` structs define the forking schedule
@@ -372,14 +469,14 @@ fn manual_fork_validation(_parent_number: u32, algo: SupportedHashes) -> bool {
 /// (where the network splits into two chains supporting different hashing algorithms).
 /// The validation logic considers the parent block height,
 /// forking configuration parameters, and the hashing algorithm used in the PoW solution to determine its validity.
+/// Algorithms beyond md5, sha3 and keccak are valid wherever both sha3 and keccak are.
 fn auto_fork_validation(
     parent_number: u32,
-    algo: SupportedHashes,
+    algo: AlgoId,
     fork_heights: ForkHeights,
     maxi_position: MaxiPosition,
 ) -> bool {
     use MaxiPosition::*;
-    use SupportedHashes::*;
 
     log::debug!("parent_number: {:?}", parent_number);
     log::debug!("fork_heights: {:?}", fork_heights);
@@ -387,35 +484,21 @@ fn auto_fork_validation(
     if parent_number < fork_heights.add_sha3_keccak {
         // To begin with we only allow md5 hashes for our pow.
         // After the fork height this check is skipped so all the hashes become valid.
-        match algo {
-            Md5 => true,
-            Sha3 => false,
-            Keccak => false,
-        }
+        algo == Md5::ID
     } else if parent_number < fork_heights.remove_md5 {
-        // After the first fork, all three algos become valid.
-        match algo {
-            Md5 => true,
-            Sha3 => true,
-            Keccak => true,
-        }
+        // After the first fork, all the algos become valid.
+        true
     } else if parent_number < fork_heights.split_sha3_keccak {
         // After the second fork, md5 is no longer valid.
-        match algo {
-            Md5 => false,
-            Sha3 => true,
-            Keccak => true,
-        }
+        algo != Md5::ID
     } else {
         // Finally we have the contentious fork.
         // Our behavior here depends which maxi position we have taken.
-        #[allow(clippy::match_like_matches_macro)]
-        match (algo, maxi_position) {
-            (Sha3, Sha3Maxi) => true,
-            (Sha3, NoMaxi) => true,
-            (Keccak, KeccakMaxi) => true,
-            (Keccak, NoMaxi) => true,
-            _ => false,
+        match maxi_position {
+            Sha3Maxi => algo == Sha3::ID,
+            KeccakMaxi => algo == Keccak::ID,
+            NoMaxi => algo != Md5::ID,
+            FollowMining => false,
         }
     }
 }
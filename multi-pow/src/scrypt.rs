@@ -0,0 +1,183 @@
+//! The scrypt key derivation function of RFC 7914, in pure Rust, for the memory hard
+//! [`crate::hashes::Scrypt`] algorithm.
+//!
+//! scrypt fills `128 * r * N` bytes of memory with a chain of salsa20/8 mixes of its input,
+//! then reads them back in an order that depends on what it read before. Computing it with
+//! less memory takes far more time, which keeps GPUs and ASICs from outpacing CPUs by much.
+//! Checking a hash costs as much as computing one, so `N` stays small enough for blocks to be
+//! verified quickly.
+//!
+//! ```
+//! use multi_pow::scrypt::scrypt;
+//!
+//! // The test vectors of RFC 7914, section 12
+//! let mut output = [0u8; 64];
+//! scrypt(b"", b"", 4, 1, 1, &mut output);
+//! assert_eq!(
+//!     hex(&output),
+//!     "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+//!      fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+//! );
+//!
+//! scrypt(b"password", b"NaCl", 10, 8, 16, &mut output);
+//! assert_eq!(
+//!     hex(&output),
+//!     "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+//!      2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640"
+//! );
+//!
+//! scrypt(b"pleaseletmein", b"SodiumChloride", 14, 8, 1, &mut output);
+//! assert_eq!(
+//!     hex(&output),
+//!     "7023bdcb3afd7348461c06cd81fd38ebfda8fbba904f8e3ea9b543f6545da1f2\
+//!      d5432955613f0fcf62d49705242a9af9e61e85dc0d651e40dfcf017b45575887"
+//! );
+//!
+//! fn hex(bytes: &[u8]) -> String {
+//!     bytes.iter().map(|b| format!("{:02x}", b)).collect()
+//! }
+//! ```
+
+use sp_core::hashing::sha2_256;
+
+/// Words in a salsa20/8 block of 64 bytes
+const BLOCK_WORDS: usize = 16;
+
+/// HMAC-SHA256 of the concatenation of `message` under `key`
+fn hmac_sha256(key: &[u8], message: &[&[u8]]) -> [u8; 32] {
+    let mut padded = [0u8; 64];
+    if key.len() > padded.len() {
+        padded[..32].copy_from_slice(&sha2_256(key));
+    } else {
+        padded[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner: Vec<u8> = padded.iter().map(|b| b ^ 0x36).collect();
+    for part in message {
+        inner.extend_from_slice(part);
+    }
+    let mut outer: Vec<u8> = padded.iter().map(|b| b ^ 0x5c).collect();
+    outer.extend_from_slice(&sha2_256(&inner));
+
+    sha2_256(&outer)
+}
+
+/// PBKDF2 with HMAC-SHA256 and `iterations` rounds, filling `output`.
+///
+/// ```
+/// use multi_pow::scrypt::pbkdf2_sha256;
+///
+/// // The test vector of RFC 7914, section 11
+/// let mut output = [0u8; 64];
+/// pbkdf2_sha256(b"passwd", b"salt", 1, &mut output);
+/// let hex: String = output.iter().map(|b| format!("{:02x}", b)).collect();
+/// assert_eq!(
+///     hex,
+///     "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc\
+///      49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+/// );
+/// ```
+pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, output: &mut [u8]) {
+    for (i, chunk) in output.chunks_mut(32).enumerate() {
+        let index = (i as u32 + 1).to_be_bytes();
+        let mut u = hmac_sha256(password, &[salt, &index]);
+        let mut t = u;
+        for _ in 1..iterations {
+            u = hmac_sha256(password, &[&u]);
+            t.iter_mut().zip(u).for_each(|(t, u)| *t ^= u);
+        }
+        chunk.copy_from_slice(&t[..chunk.len()]);
+    }
+}
+
+/// Quarter round of salsa20 on the words `a`, `b`, `c` and `d` of `x`
+fn quarter_round(x: &mut [u32; BLOCK_WORDS], a: usize, b: usize, c: usize, d: usize) {
+    x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+    x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+    x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+    x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+}
+
+/// The salsa20/8 core, in place on a block of little-endian words
+pub fn salsa20_8(block: &mut [u32; BLOCK_WORDS]) {
+    let mut x = *block;
+    for _ in 0..4 {
+        // Columns
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 5, 9, 13, 1);
+        quarter_round(&mut x, 10, 14, 2, 6);
+        quarter_round(&mut x, 15, 3, 7, 11);
+        // Rows
+        quarter_round(&mut x, 0, 1, 2, 3);
+        quarter_round(&mut x, 5, 6, 7, 4);
+        quarter_round(&mut x, 10, 11, 8, 9);
+        quarter_round(&mut x, 15, 12, 13, 14);
+    }
+
+    for (word, mixed) in block.iter_mut().zip(x) {
+        *word = word.wrapping_add(mixed);
+    }
+}
+
+/// scryptBlockMix of the `2 * r` salsa20/8 blocks of `input` into `output`
+fn block_mix(input: &[u32], output: &mut [u32]) {
+    let blocks = input.len() / BLOCK_WORDS;
+    let mut x = [0u32; BLOCK_WORDS];
+    x.copy_from_slice(&input[(blocks - 1) * BLOCK_WORDS..]);
+
+    for (i, block) in input.as_chunks::<BLOCK_WORDS>().0.iter().enumerate() {
+        x.iter_mut().zip(block).for_each(|(x, b)| *x ^= b);
+        salsa20_8(&mut x);
+        // Even blocks go to the first half of the output, odd blocks to the second
+        let position = (i / 2 + (i % 2) * blocks / 2) * BLOCK_WORDS;
+        output[position..position + BLOCK_WORDS].copy_from_slice(&x);
+    }
+}
+
+/// scryptROMix of `block`, `128 * r` bytes as words, with `2^log_n` of them in memory
+fn ro_mix(block: &mut [u32], log_n: u8) {
+    let n = 1usize << log_n;
+    let len = block.len();
+    let mut memory = vec![0u32; n * len];
+    let mut scratch = vec![0u32; len];
+
+    for entry in memory.chunks_exact_mut(len) {
+        entry.copy_from_slice(block);
+        block_mix(entry, block);
+    }
+
+    for _ in 0..n {
+        // Integerify: the first words of the last salsa20/8 block
+        let last = len - BLOCK_WORDS;
+        let j = (block[last] as u64 | (block[last + 1] as u64) << 32) as usize & (n - 1);
+        let entry = &memory[j * len..(j + 1) * len];
+        scratch
+            .iter_mut()
+            .zip(block.iter().zip(entry))
+            .for_each(|(s, (b, v))| *s = b ^ v);
+        block_mix(&scratch, block);
+    }
+}
+
+/// scrypt of `password` and `salt` with cost `N = 2^log_n`, block size `r` and
+/// parallelization `p`, filling `output`. Takes `128 * r * N` bytes of memory.
+pub fn scrypt(password: &[u8], salt: &[u8], log_n: u8, r: u32, p: u32, output: &mut [u8]) {
+    let block_len = 128 * r as usize;
+    let mut blocks = vec![0u8; block_len * p as usize];
+    pbkdf2_sha256(password, salt, 1, &mut blocks);
+
+    for block in blocks.chunks_exact_mut(block_len) {
+        let mut words: Vec<u32> = block
+            .as_chunks::<4>()
+            .0
+            .iter()
+            .map(|bytes| u32::from_le_bytes(*bytes))
+            .collect();
+        ro_mix(&mut words, log_n);
+        for (bytes, word) in block.as_chunks_mut::<4>().0.iter_mut().zip(words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    pbkdf2_sha256(password, &blocks, 1, output);
+}
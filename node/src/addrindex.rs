@@ -0,0 +1,336 @@
+//! History of UTXO owners, enabled with `--utxo-addrindex`.
+//!
+//! For every owner, the index records the outputs it received and the outputs it spent, with
+//! the block and height where that happened. Activity is read from the utxo pallet events of
+//! each block, only the outputs being spent are looked up in the state. Like the transaction
+//! index, it lives in the aux DB of the client and follows the canonical chain. Subscribers
+//! are told about activity when a block is indexed and when a reorg takes it out again.
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use academy_pow_runtime::{
+    opaque::Block,
+    utxo::{self, AssetId, CreatedOutput, UtxoApi as UtxoRuntimeApi, Value},
+    BlockNumber, Hash, RuntimeEvent,
+};
+use frame_system::EventRecord;
+use futures::{
+    channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender},
+    StreamExt,
+};
+use parity_scale_codec::{Decode, Encode};
+use sc_client_api::{AuxStore, Backend, BlockchainEvents, StorageProvider};
+use serde::{Deserialize, Serialize};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::{HeaderBackend, HeaderMetadata};
+use sp_core::{storage::StorageKey, twox_128, H256};
+
+use crate::txindex::follow_best_chain;
+
+const OWNER_PREFIX: &[u8] = b"utxo_addrindex:owner:";
+const BLOCK_PREFIX: &[u8] = b"utxo_addrindex:block:";
+const TIP_KEY: &[u8] = b"utxo_addrindex:tip";
+
+/// What happened to an output of an owner
+#[derive(Encode, Decode, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Activity {
+    /// The owner received the output
+    Received,
+    /// The owner spent the output
+    Spent,
+}
+
+/// One entry of the history of an owner
+#[derive(Encode, Decode, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct HistoryEntry {
+    /// Whether the output was received or spent
+    pub activity: Activity,
+    /// The output
+    pub outpoint: H256,
+    /// Value of the output
+    pub value: Value,
+    /// Asset of the output
+    pub asset: AssetId,
+    /// Transaction creating or spending the output. `None` for block rewards and faucet
+    /// deposits.
+    pub txid: Option<H256>,
+    /// Hash of the block
+    pub block_hash: Hash,
+    /// Height of the block
+    pub block_number: BlockNumber,
+}
+
+/// Activity pushed to subscribers
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "event")]
+pub enum AddressEvent {
+    /// The activity was included in a block of the canonical chain
+    Confirmed(HistoryEntry),
+    /// A reorg took the block of the activity out of the canonical chain
+    Reorged(HistoryEntry),
+}
+
+/// Subscribers of the address index.
+#[derive(Default)]
+pub struct AddressIndex {
+    subscribers: Mutex<Vec<(H256, UnboundedSender<AddressEvent>)>>,
+}
+
+impl AddressIndex {
+    /// Stream of the activity of `owner`
+    pub fn subscribe(&self, owner: H256) -> UnboundedReceiver<AddressEvent> {
+        let (sender, receiver) = unbounded();
+        self.subscribers
+            .lock()
+            .expect("address index lock poisoned")
+            .push((owner, sender));
+        receiver
+    }
+
+    fn notify(&self, entries: &[(H256, HistoryEntry)], event: fn(HistoryEntry) -> AddressEvent) {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .expect("address index lock poisoned");
+        for (owner, entry) in entries {
+            subscribers.retain(|(subscribed, sender)| {
+                subscribed != owner || sender.unbounded_send(event(entry.clone())).is_ok()
+            });
+        }
+    }
+}
+
+fn owner_key(owner: &H256) -> Vec<u8> {
+    [OWNER_PREFIX, owner.as_bytes()].concat()
+}
+
+fn block_key(hash: &Hash) -> Vec<u8> {
+    [BLOCK_PREFIX, hash.as_bytes()].concat()
+}
+
+fn read_aux<C: AuxStore, T: Decode>(client: &C, key: &[u8]) -> sp_blockchain::Result<Option<T>> {
+    Ok(client
+        .get_aux(key)?
+        .and_then(|value| T::decode(&mut &value[..]).ok()))
+}
+
+/// History of `owner` on the canonical chain, oldest first, as far as it is indexed
+pub fn history<C: AuxStore>(client: &C, owner: &H256) -> sp_blockchain::Result<Vec<HistoryEntry>> {
+    Ok(read_aux(client, &owner_key(owner))?.unwrap_or_default())
+}
+
+/// Activity of every owner in a block, read from the events of the utxo pallet
+fn block_activity<C, B>(client: &C, hash: Hash) -> sp_blockchain::Result<Vec<(H256, HistoryEntry)>>
+where
+    C: HeaderBackend<Block> + StorageProvider<Block, B> + ProvideRuntimeApi<Block>,
+    C::Api: UtxoRuntimeApi<Block>,
+    B: Backend<Block>,
+{
+    let header = client
+        .header(hash)?
+        .ok_or_else(|| sp_blockchain::Error::UnknownBlock(format!("{:?}", hash)))?;
+    let events_key = StorageKey([twox_128(b"System"), twox_128(b"Events")].concat());
+    let events: Vec<EventRecord<RuntimeEvent, Hash>> = client
+        .storage(hash, &events_key)?
+        .and_then(|events| Decode::decode(&mut &events.0[..]).ok())
+        .unwrap_or_default();
+
+    let api = client.runtime_api();
+    let stored_output = |at, outpoint| {
+        api.utxo(at, outpoint)
+            .map(|output| output.map(|output| CreatedOutput::new(outpoint, &output)))
+            .map_err(|e| sp_blockchain::Error::Application(Box::new(e)))
+    };
+    let entry = |activity, output: &CreatedOutput, txid| {
+        (
+            output.owner,
+            HistoryEntry {
+                activity,
+                outpoint: output.outpoint,
+                value: output.value,
+                asset: output.asset,
+                txid,
+                block_hash: hash,
+                block_number: header.number,
+            },
+        )
+    };
+
+    let mut created: BTreeMap<H256, CreatedOutput> = BTreeMap::new();
+    let mut activity = Vec::new();
+    for record in events {
+        let RuntimeEvent::Utxo(event) = record.event else {
+            continue;
+        };
+        let (txid, spent, outputs) = match event {
+            utxo::Event::Transferred {
+                txid,
+                spent,
+                created,
+                ..
+            } => (Some(txid), spent, created),
+            utxo::Event::Coinbase { output, .. } | utxo::Event::FaucetDeposited { output } => {
+                (None, Vec::new(), vec![output])
+            }
+            // Blocks built by runtimes up to spec version 1 carry the superseded events
+            utxo::Event::TransactionSuccess(transaction) => (
+                Some(utxo::txid(&transaction)),
+                transaction
+                    .inputs
+                    .iter()
+                    .map(|input| input.outpoint)
+                    .collect(),
+                transaction
+                    .outputs
+                    .iter()
+                    .enumerate()
+                    .map(|(index, output)| {
+                        CreatedOutput::new(utxo::outpoint(&transaction, index as u64), output)
+                    })
+                    .collect(),
+            ),
+            utxo::Event::RewardDistributed(_, outpoint) | utxo::Event::Faucet(_, outpoint) => (
+                None,
+                Vec::new(),
+                stored_output(hash, outpoint)?.into_iter().collect(),
+            ),
+            _ => continue,
+        };
+
+        for outpoint in spent {
+            let prevout = match created.get(&outpoint) {
+                Some(output) => Some(output.clone()),
+                None => stored_output(header.parent_hash, outpoint)?,
+            };
+            if let Some(prevout) = prevout {
+                activity.push(entry(Activity::Spent, &prevout, txid));
+            }
+        }
+        for output in outputs {
+            activity.push(entry(Activity::Received, &output, txid));
+            created.insert(output.outpoint, output);
+        }
+    }
+
+    Ok(activity)
+}
+
+fn index_block<C, B>(client: &C, index: &AddressIndex, hash: Hash) -> sp_blockchain::Result<()>
+where
+    C: HeaderBackend<Block> + StorageProvider<Block, B> + ProvideRuntimeApi<Block> + AuxStore,
+    C::Api: UtxoRuntimeApi<Block>,
+    B: Backend<Block>,
+{
+    let activity = block_activity(client, hash)?;
+
+    let mut histories: BTreeMap<H256, Vec<HistoryEntry>> = BTreeMap::new();
+    for (owner, entry) in &activity {
+        if !histories.contains_key(owner) {
+            histories.insert(*owner, history(client, owner)?);
+        }
+        histories
+            .get_mut(owner)
+            .expect("inserted above; qed")
+            .push(entry.clone());
+    }
+    write(client, histories, hash, Some(activity.encode()), hash)?;
+
+    index.notify(&activity, AddressEvent::Confirmed);
+    Ok(())
+}
+
+fn deindex_block<C: AuxStore>(
+    client: &C,
+    index: &AddressIndex,
+    hash: Hash,
+    parent: Hash,
+) -> sp_blockchain::Result<()> {
+    let activity: Vec<(H256, HistoryEntry)> =
+        read_aux(client, &block_key(&hash))?.unwrap_or_default();
+
+    let mut histories: BTreeMap<H256, Vec<HistoryEntry>> = BTreeMap::new();
+    for (owner, _) in &activity {
+        if !histories.contains_key(owner) {
+            let mut history = history(client, owner)?;
+            history.retain(|entry| entry.block_hash != hash);
+            histories.insert(*owner, history);
+        }
+    }
+    write(client, histories, hash, None, parent)?;
+
+    index.notify(&activity, AddressEvent::Reorged);
+    Ok(())
+}
+
+/// Atomically store the histories, the activity of `block` to undo it on a reorg, or its
+/// removal, and the new tip
+fn write<C: AuxStore>(
+    client: &C,
+    histories: BTreeMap<H256, Vec<HistoryEntry>>,
+    block: Hash,
+    block_activity: Option<Vec<u8>>,
+    tip: Hash,
+) -> sp_blockchain::Result<()> {
+    let block_key = block_key(&block);
+    let mut entries: Vec<(Vec<u8>, Vec<u8>)> = histories
+        .iter()
+        .map(|(owner, history)| (owner_key(owner), history.encode()))
+        .collect();
+    entries.push((TIP_KEY.to_vec(), tip.encode()));
+    let mut delete = Vec::new();
+    match block_activity {
+        Some(activity) => entries.push((block_key, activity)),
+        None => delete.push(&block_key[..]),
+    }
+    let insert: Vec<(&[u8], &[u8])> = entries
+        .iter()
+        .map(|(key, value)| (&key[..], &value[..]))
+        .collect();
+
+    client.insert_aux(&insert, &delete)
+}
+
+/// Keep the index in line with the canonical chain until the client shuts down
+pub async fn run<C, B>(client: Arc<C>, index: Arc<AddressIndex>)
+where
+    C: HeaderBackend<Block>
+        + HeaderMetadata<Block, Error = sp_blockchain::Error>
+        + StorageProvider<Block, B>
+        + ProvideRuntimeApi<Block>
+        + BlockchainEvents<Block>
+        + AuxStore,
+    C::Api: UtxoRuntimeApi<Block>,
+    B: Backend<Block>,
+{
+    let update = |best| {
+        follow_best_chain(
+            &*client,
+            TIP_KEY,
+            best,
+            |hash| index_block::<C, B>(&*client, &index, hash),
+            |hash, parent| deindex_block(&*client, &index, hash, parent),
+        )
+    };
+
+    let mut imports = client.import_notification_stream();
+    if let Err(e) = update(client.info().best_hash) {
+        log::error!("Failed to catch up the UTXO address index: {}", e);
+    }
+
+    while let Some(notification) = imports.next().await {
+        if notification.is_new_best {
+            if let Err(e) = update(notification.hash) {
+                log::error!(
+                    "Failed to index UTXO owners of block {:?}: {}",
+                    notification.hash,
+                    e
+                );
+            }
+        }
+    }
+}
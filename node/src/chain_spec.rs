@@ -1,20 +1,18 @@
 use std::str::FromStr;
 
 use academy_pow_runtime::{
-    AccountId,
-    SS58Prefix,
-    Signature,
-    TOKEN_DECIMALS,
-    TOKEN_SYMBOL,
-    WASM_BINARY,
+    address::{DEVNET_HRP, TESTNET_HRP},
     utxo::{GenesisUtxoType, Value},
+    AccountId, SS58Prefix, Signature, TOKEN_DECIMALS, TOKEN_SYMBOL, WASM_BINARY,
+};
+use multi_pow::{
+    AlgoId, ForkHeights, ForkingConfig, HashParams, MaxiPosition, Threshold, ALGORITHMS, MAX_ALGOS,
 };
-use multi_pow::{ForkHeights, ForkingConfig, MaxiPosition};
 use sc_chain_spec::{ChainSpecExtension, ChainSpecGroup};
 use sc_service::ChainType;
 use serde::{Deserialize, Serialize};
 use sp_core::{sr25519, Pair, Public};
-use sp_core::{ByteArray, H256};
+use sp_core::{ByteArray, H256, U256};
 use sp_runtime::traits::{IdentifyAccount, Verify};
 
 /// Specialized `ChainSpec`. This is a specialization of the general Substrate ChainSpec type.
@@ -47,6 +45,15 @@ pub struct ForkingExtensions {
     // Let alone that time I tried to use the forked value feature.
     /// The political position that this node will take at the contentious fork.
     pub maxi_position: String,
+    /// Anything but 0 accepts blocks at the minimum difficulty after twice the target block time
+    /// without blocks, as on Bitcoin's testnet. Set `minDifficultyBlocks` in the genesis of the
+    /// difficulty adjustments to match.
+    #[serde(default)]
+    pub min_difficulty_blocks: u32,
+    /// Memory cost of scrypt, each hash taking `2^scrypt_log_n` KiB, at most 1 MiB. 0 means
+    /// the default of 128 KiB. Changing it on a live chain is a hard fork.
+    #[serde(default)]
+    pub scrypt_log_n: u32,
 }
 
 impl From<&ForkingExtensions> for ForkingConfig {
@@ -73,6 +80,25 @@ impl ForkingExtensions {
     pub fn try_get(chain_spec: &dyn sc_service::ChainSpec) -> Option<&Self> {
         sc_chain_spec::get_extension(chain_spec.extensions())
     }
+
+    /// Whether the given `ChainSpec` turns the testnet minimum difficulty rule on.
+    pub fn min_difficulty_blocks(chain_spec: &dyn sc_service::ChainSpec) -> bool {
+        Self::try_get(chain_spec).is_some_and(|e| e.min_difficulty_blocks > 0)
+    }
+
+    /// Parameters of the mining algorithms set by the given `ChainSpec`, or why they are invalid.
+    pub fn hash_params(chain_spec: &dyn sc_service::ChainSpec) -> Result<HashParams, String> {
+        match Self::try_get(chain_spec).map(|e| e.scrypt_log_n) {
+            None | Some(0) => Ok(HashParams::default()),
+            Some(log_n) => HashParams::with_scrypt_log_n(log_n).ok_or_else(|| {
+                format!(
+                    "Invalid scryptLogN {} in the chain spec, should be at most {}",
+                    log_n,
+                    HashParams::MAX_SCRYPT_LOG_N
+                )
+            }),
+        }
+    }
 }
 
 /// Generate a crypto pair from seed.
@@ -101,6 +127,8 @@ pub fn development_config() -> Result<ChainSpec, String> {
             remove_md5: 20,
             split_sha3_keccak: 30,
             maxi_position: String::from("follow-mining"),
+            min_difficulty_blocks: 0,
+            scrypt_log_n: 0,
         },
     )
     .with_name("Development")
@@ -115,7 +143,9 @@ pub fn development_config() -> Result<ChainSpec, String> {
             get_account_id_from_seed::<sr25519::Public>("Bob//stash"),
         ],
         // Initial Difficulty
-        4_000_000,
+        initial_difficulty(),
+        // Minimum difficulty blocks
+        false,
         // Pre-funded accounts
         vec![
             get_account_id_from_seed::<sr25519::Public>("Alice"),
@@ -124,7 +154,7 @@ pub fn development_config() -> Result<ChainSpec, String> {
             get_account_id_from_seed::<sr25519::Public>("Bob//stash"),
         ],
     ))
-    .with_properties(system_properties())
+    .with_properties(system_properties(DEVNET_HRP))
     .build())
 }
 
@@ -137,6 +167,9 @@ pub fn testnet_config() -> Result<ChainSpec, String> {
             remove_md5: 0,
             split_sha3_keccak: 0,
             maxi_position: String::new(),
+            // Keep the shared testnet moving on laptops once the big miners are gone
+            min_difficulty_blocks: 1,
+            scrypt_log_n: 0,
         },
     )
     .with_name("Testnet")
@@ -149,7 +182,8 @@ pub fn testnet_config() -> Result<ChainSpec, String> {
             get_account_id_from_seed::<sr25519::Public>("Alice//stash"),
             get_account_id_from_seed::<sr25519::Public>("Bob//stash"),
         ],
-        4_000_000,
+        initial_difficulty(),
+        true,
         vec![
             get_account_id_from_seed::<sr25519::Public>("Alice"),
             get_account_id_from_seed::<sr25519::Public>("Bob"),
@@ -157,13 +191,22 @@ pub fn testnet_config() -> Result<ChainSpec, String> {
             get_account_id_from_seed::<sr25519::Public>("Bob//stash"),
         ],
     ))
-    .with_properties(system_properties())
+    .with_properties(system_properties(TESTNET_HRP))
     .build())
 }
 
+/// Initial difficulty of each algorithm, as the registry sets it
+fn initial_difficulty() -> Threshold {
+    ALGORITHMS
+        .iter()
+        .map(|algorithm| (algorithm.id, U256::from(algorithm.initial_difficulty)))
+        .collect()
+}
+
 fn genesis(
     endowed_accounts: Vec<AccountId>,
-    initial_difficulty: u32,
+    initial_difficulty: Threshold,
+    min_difficulty_blocks: bool,
     utxo_genesis_accounts: Vec<AccountId>,
 ) -> serde_json::Value {
     serde_json::json!({
@@ -171,20 +214,18 @@ fn genesis(
             // Configure endowed accounts with initial balance of 1 << 50.
             "balances": endowed_accounts.iter().cloned().map(|k| (k, 1u64 << 50)).collect::<Vec<_>>(),
         },
-        "keccakDifficultyAdjustment": {
-            "initialDifficulty": u32_to_u8_32(initial_difficulty),
-        },
-        "md5DifficultyAdjustment": {
-            "initialDifficulty": u32_to_u8_32(initial_difficulty),
-        },
-        "sha3DifficultyAdjustment": {
-            "initialDifficulty": u32_to_u8_32(initial_difficulty),
+        "difficultyAdjustment": {
+            // By algorithm id
+            "initialDifficulty": (0..MAX_ALGOS as u8)
+                .map(|id| u256_to_u8_32(initial_difficulty.get(AlgoId(id))))
+                .collect::<Vec<_>>(),
+            "minDifficultyBlocks": min_difficulty_blocks,
         },
         "utxo": {
             "genesisUtxos": utxo_genesis_accounts
                 .iter().cloned()
                 .map(|k| {
-                    let hash = H256::from_slice(&k.as_slice()); 
+                    let hash = H256::from_slice(&k.as_slice());
                     let value: Value = (1u64 << 50).into();
                     let genesis_utxo: GenesisUtxoType = (value, hash);
 
@@ -194,21 +235,21 @@ fn genesis(
     })
 }
 
-/// Convert u32 (default value) to [u8;32] (U256)
+/// Convert a difficulty to [u8;32] (U256)
 /// in little-endian format
-pub fn u32_to_u8_32(num: u32) -> [u8; 32] {
+pub fn u256_to_u8_32(num: U256) -> [u8; 32] {
     let mut result = [0u8; 32];
-    let bytes = num.to_le_bytes(); 
-    result[..4].copy_from_slice(&bytes);
+    num.to_little_endian(&mut result);
     result
 }
 
-fn system_properties() -> sc_chain_spec::Properties {
+fn system_properties(utxo_address_hrp: &str) -> sc_chain_spec::Properties {
     let mut properties = sc_chain_spec::Properties::new();
 
     properties.insert("ss58Format".into(), SS58Prefix::get().into());
     properties.insert("tokenSymbol".into(), TOKEN_SYMBOL.into());
     properties.insert("tokenDecimals".into(), TOKEN_DECIMALS.into());
+    properties.insert("utxoAddressHrp".into(), utxo_address_hrp.into());
 
     properties
 }
@@ -1,5 +1,7 @@
-use academy_pow_runtime::AccountId;
-use multi_pow::SupportedHashes;
+use std::path::PathBuf;
+
+use academy_pow_runtime::{address, utxo::Value, AccountId};
+use multi_pow::{AlgoId, ALGORITHMS};
 use sc_cli::{
     clap::{ArgGroup, Parser},
     RunCmd,
@@ -39,11 +41,29 @@ pub struct AcademyPowCli {
 
     /// The mining algorithm to use
     #[clap(long, value_parser = parse_algo, default_value = "md5")]
-    pub mining_algo: multi_pow::SupportedHashes,
+    pub mining_algo: AlgoId,
 
     /// whether to use instant seal
     #[clap(long, default_value = "false")]
     pub instant_seal: bool,
+
+    /// Index UTXO transactions by txid, for `utxo_getTransaction`
+    #[clap(long)]
+    pub utxo_txindex: bool,
+
+    /// Index the outputs received and spent by each UTXO owner, for `utxo_getAddressHistory`
+    /// and `utxo_subscribeAddress`
+    #[clap(long)]
+    pub utxo_addrindex: bool,
+
+    /// Maximum number of future UTXO transactions waiting for the same missing outpoint
+    #[clap(long, default_value = "8")]
+    pub utxo_max_orphans_per_outpoint: usize,
+
+    /// Seconds a block timestamp may be ahead of the local clock, at most 30 as the timestamp
+    /// pallet refuses blocks further ahead regardless
+    #[clap(long, default_value = "15", value_parser = clap::value_parser!(u64).range(..=30))]
+    pub max_future_drift: u64,
 }
 
 impl AcademyPowCli {
@@ -59,6 +79,8 @@ impl AcademyPowCli {
 }
 
 #[derive(Debug, Parser)]
+// The flattened command has the argument group of this name already
+#[group(skip)]
 pub struct BuildSpecCmd {
     #[clap(flatten)]
     pub base: sc_cli::BuildSpecCmd,
@@ -78,20 +100,16 @@ pub struct BuildSpecCmd {
     /// The type of the chain. Possible values: "dev", "local", "live" (default)
     #[arg(long, value_name = "TYPE", value_parser = parse_chaintype, default_value = "live")]
     pub chain_type: ChainType,
-
-    #[arg(long, default_value = "4000000")]
-    pub initial_difficulty: u32,
 }
 
-fn parse_algo(s: &str) -> Result<SupportedHashes, String> {
-    Ok(match s {
-        "md" | "Md" | "md5" | "Md5" => SupportedHashes::Md5,
-        "sha" | "sha3" | "Sha" | "Sha3" => SupportedHashes::Sha3,
-        "keccak" | "Keccak" => SupportedHashes::Keccak,
-        s => panic!(
-            "Wrong mining algo: {}. Possible values: md5, sha3, keccak",
-            s
-        ),
+fn parse_algo(s: &str) -> Result<AlgoId, String> {
+    s.parse().map_err(|_| {
+        let names: Vec<_> = ALGORITHMS.iter().map(|algorithm| algorithm.name).collect();
+        format!(
+            "Wrong mining algo: {}. Possible values: {}",
+            s,
+            names.join(", ")
+        )
     })
 }
 
@@ -110,12 +128,269 @@ fn parse_account_id(s: &str) -> Result<AccountId, String> {
         .expect("Passed string is not a bas58 encoding of a sr25519 public key"))
 }
 
+/// Parse the owner of a UTXO from a checksummed address or a hex encoded sr25519 pubkey
+/// passed on the command line.
+fn parse_utxo_owner(s: &str) -> Result<sr25519::Public, String> {
+    address::parse_pubkey(s)
+        .map(sr25519::Public::from_h256)
+        .map_err(|e| e.to_string())
+}
+
 /// Parse sr25519 pubkey from a string argument passed on the command line.
 fn parse_sr25519_public_key(s: &str) -> Result<sr25519::Public, String> {
     Ok(sr25519::Public::from_string(s)
         .expect("Passed string is not a hex encoding of a sr25519 public key"))
 }
 
+/// Parse a `<ADDRESS>:<VALUE>` payment passed on the command line.
+fn parse_payment(s: &str) -> Result<(sr25519::Public, Value), String> {
+    let (pubkey, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("Payment {} is not of the form <ADDRESS>:<VALUE>", s))?;
+    let value = value
+        .parse()
+        .map_err(|e| format!("Invalid payment value {}: {}", value, e))?;
+
+    Ok((parse_utxo_owner(pubkey)?, value))
+}
+
+/// Connection to the node serving the UTXO RPCs.
+#[derive(Debug, Clone, Parser)]
+pub struct RpcArgs {
+    /// HTTP RPC endpoint of the node
+    #[arg(long, default_value = "http://127.0.0.1:9944")]
+    pub url: String,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum WalletCmd {
+    /// Create an HD wallet file from a seed. Works offline.
+    Init(WalletInitCmd),
+
+    /// Hand out a fresh key of an HD wallet. Works offline.
+    NewAddress(WalletNewAddressCmd),
+
+    /// Rediscover the funds of an HD wallet, scanning its keys up to the gap limit. Needs a
+    /// node running with --utxo-addrindex.
+    Scan(WalletScanCmd),
+
+    /// Show the balance of an owner or HD wallet, queried from a node
+    Balance(WalletQueryCmd),
+
+    /// List the unspent outputs of an owner or HD wallet, queried from a node
+    ListUtxos(WalletQueryCmd),
+
+    /// Build an unsigned transaction funded by the UTXOs of an owner or HD wallet
+    Build(WalletBuildCmd),
+
+    /// Sign a transaction built by `wallet build`. Works offline.
+    Sign(WalletSignCmd),
+
+    /// Submit a transaction signed by `wallet sign` as an unsigned `spend` extrinsic
+    Send(WalletSendCmd),
+
+    /// Partially signed transactions, to spend inputs owned by several signers
+    #[command(subcommand)]
+    Pst(WalletPstCmd),
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum WalletPstCmd {
+    /// Create a PST from a transaction built by `wallet build`. Works offline.
+    Create(WalletPstCreateCmd),
+
+    /// Add signatures to a PST. Works offline.
+    Sign(WalletPstSignCmd),
+
+    /// Merge the signatures of PSTs of the same transaction. Works offline.
+    Combine(WalletPstCombineCmd),
+
+    /// Check the signatures of a complete PST and move them into the transaction.
+    /// Works offline.
+    Finalize(WalletPstFileCmd),
+
+    /// Print the signed transaction of a finalized PST, for `wallet send`. Works offline.
+    Extract(WalletPstFileCmd),
+
+    /// Print the contents of a PST as JSON. Works offline.
+    Decode(WalletPstFileCmd),
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletPstCreateCmd {
+    /// JSON file of the unsigned transaction
+    #[arg(long)]
+    pub file: PathBuf,
+
+    /// File of the HD wallet owning inputs, to add derivation hints for its keys
+    #[arg(long)]
+    pub wallet_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletPstSignCmd {
+    /// File of the PST
+    #[arg(long)]
+    pub pst: PathBuf,
+
+    /// Secret URI of a key owning inputs, or the seed of an HD wallet whose keys are
+    /// found through the derivation hints
+    #[arg(
+        long,
+        conflicts_with = "keystore_path",
+        required_unless_present = "keystore_path"
+    )]
+    pub suri: Option<String>,
+
+    /// Keystore holding keys owning inputs, under the `utxo` key type
+    #[arg(long)]
+    pub keystore_path: Option<PathBuf>,
+
+    /// Password of the keystore
+    #[arg(long, requires = "keystore_path")]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletPstCombineCmd {
+    /// Files of the PSTs
+    #[arg(required = true)]
+    pub psts: Vec<PathBuf>,
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletPstFileCmd {
+    /// File of the PST
+    #[arg(long)]
+    pub pst: PathBuf,
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletInitCmd {
+    /// Secret URI of the seed. Wallet keys derive from `<SURI>//utxo`.
+    #[arg(long)]
+    pub suri: String,
+
+    /// File the wallet state is written to
+    #[arg(long)]
+    pub wallet_file: PathBuf,
+
+    /// Number of consecutive unused keys after which scanning stops
+    #[arg(long, default_value_t = academy_pow_runtime::wallet::DEFAULT_GAP_LIMIT)]
+    pub gap_limit: u32,
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletNewAddressCmd {
+    /// File of the wallet state
+    #[arg(long)]
+    pub wallet_file: PathBuf,
+
+    /// Hand out a change key instead of a receive key
+    #[arg(long)]
+    pub change: bool,
+
+    /// Human-readable part of the printed address, naming the chain
+    #[arg(long, default_value = address::DEVNET_HRP)]
+    pub address_hrp: String,
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletScanCmd {
+    /// File of the wallet state
+    #[arg(long)]
+    pub wallet_file: PathBuf,
+
+    #[command(flatten)]
+    pub rpc: RpcArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletQueryCmd {
+    /// Address or hex encoding of the owner's SR25519 public key
+    #[arg(long, value_parser = parse_utxo_owner, required_unless_present = "wallet_file")]
+    pub owner: Option<sr25519::Public>,
+
+    /// File of an HD wallet, to query all of its keys
+    #[arg(long, conflicts_with = "owner")]
+    pub wallet_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub rpc: RpcArgs,
+}
+
+/// Coin selection strategies of the wallet
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+pub enum CoinSelectionKind {
+    LargestFirst,
+    BranchAndBound,
+    Random,
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletBuildCmd {
+    /// Address or hex encoding of the SR25519 public key owning the UTXOs to spend
+    #[arg(long, value_parser = parse_utxo_owner, required_unless_present = "wallet_file")]
+    pub from: Option<sr25519::Public>,
+
+    /// File of an HD wallet, to spend the UTXOs of all of its keys
+    #[arg(long, conflicts_with = "from")]
+    pub wallet_file: Option<PathBuf>,
+
+    /// Payment as `<ADDRESS>:<VALUE>`. Can be repeated.
+    #[arg(long = "pay", value_parser = parse_payment, required = true)]
+    pub payments: Vec<(sr25519::Public, Value)>,
+
+    /// Key receiving the change. Defaults to `--from`, or a fresh change key of the
+    /// HD wallet.
+    #[arg(long, value_parser = parse_utxo_owner)]
+    pub change: Option<sr25519::Public>,
+
+    /// Fee rate, in native value per encoded byte
+    #[arg(long, default_value = "1")]
+    pub fee_rate: Value,
+
+    /// How to pick the UTXOs to spend
+    #[arg(long, value_enum, default_value = "largest-first")]
+    pub coin_selection: CoinSelectionKind,
+
+    #[command(flatten)]
+    pub rpc: RpcArgs,
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletSignCmd {
+    /// JSON file of the unsigned transaction
+    #[arg(long)]
+    pub file: PathBuf,
+
+    /// Secret URI of the key owning the inputs, or the seed of `--wallet-file`
+    #[arg(long, conflicts_with = "keystore_path")]
+    pub suri: Option<String>,
+
+    /// File of the HD wallet owning the inputs
+    #[arg(long, requires = "suri")]
+    pub wallet_file: Option<PathBuf>,
+
+    /// Keystore holding the keys owning the inputs, under the `utxo` key type
+    #[arg(long)]
+    pub keystore_path: Option<PathBuf>,
+
+    /// Password of the keystore
+    #[arg(long, requires = "keystore_path")]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Parser)]
+pub struct WalletSendCmd {
+    /// JSON file of the signed transaction
+    #[arg(long)]
+    pub file: PathBuf,
+
+    #[command(flatten)]
+    pub rpc: RpcArgs,
+}
+
 #[derive(Debug, clap::Subcommand)]
 pub enum Subcommand {
     /// Key management cli utilities
@@ -145,4 +420,8 @@ pub enum Subcommand {
 
     /// Db meta columns information.
     ChainInfo(sc_cli::ChainInfoCmd),
+
+    /// UTXO wallet cli utilities
+    #[command(subcommand)]
+    Wallet(WalletCmd),
 }
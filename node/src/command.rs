@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::time::Duration;
+
 use academy_pow_runtime::Block;
 use multi_pow::{ForkingConfig, MaxiPosition};
 use sc_cli::SubstrateCli;
@@ -56,7 +58,7 @@ impl SubstrateCli for Cli {
     }
 
     fn load_spec(&self, id: &str) -> Result<Box<dyn sc_service::ChainSpec>, String> {
-        Ok(match id {
+        let spec: Box<dyn sc_service::ChainSpec> = match id {
             "" => Box::new(chain_spec::ChainSpec::from_json_bytes(
                 &include_bytes!("../../spec.json")[..],
             )?),
@@ -65,13 +67,18 @@ impl SubstrateCli for Cli {
             path => Box::new(chain_spec::ChainSpec::from_json_file(
                 std::path::PathBuf::from(path),
             )?),
-        })
+        };
+
+        // Refuse mining parameters the node can't work with before it starts on them
+        ForkingExtensions::hash_params(&*spec)?;
+        Ok(spec)
     }
 }
 
 /// Parse and run command line arguments
 pub fn run() -> sc_cli::Result<()> {
     let cli = Cli::from_args();
+    let max_future_drift = Duration::from_secs(cli.pow.max_future_drift);
 
     match &cli.subcommand {
         Some(Subcommand::Key(cmd)) => cmd.run(&cli),
@@ -87,7 +94,7 @@ pub fn run() -> sc_cli::Result<()> {
                     task_manager,
                     import_queue,
                     ..
-                } = service::new_partial(&config, ForkingConfig::Manual)?;
+                } = service::new_partial(&config, ForkingConfig::Manual, max_future_drift)?;
                 Ok((cmd.run(client, import_queue), task_manager))
             })
         }
@@ -98,7 +105,7 @@ pub fn run() -> sc_cli::Result<()> {
                     client,
                     task_manager,
                     ..
-                } = service::new_partial(&config, ForkingConfig::Manual)?;
+                } = service::new_partial(&config, ForkingConfig::Manual, max_future_drift)?;
                 Ok((cmd.run(client, config.database), task_manager))
             })
         }
@@ -109,7 +116,7 @@ pub fn run() -> sc_cli::Result<()> {
                     client,
                     task_manager,
                     ..
-                } = service::new_partial(&config, ForkingConfig::Manual)?;
+                } = service::new_partial(&config, ForkingConfig::Manual, max_future_drift)?;
                 Ok((cmd.run(client, config.chain_spec), task_manager))
             })
         }
@@ -121,7 +128,7 @@ pub fn run() -> sc_cli::Result<()> {
                     task_manager,
                     import_queue,
                     ..
-                } = service::new_partial(&config, ForkingConfig::Manual)?;
+                } = service::new_partial(&config, ForkingConfig::Manual, max_future_drift)?;
                 Ok((cmd.run(client, import_queue), task_manager))
             })
         }
@@ -137,7 +144,7 @@ pub fn run() -> sc_cli::Result<()> {
                     task_manager,
                     backend,
                     ..
-                } = service::new_partial(&config, ForkingConfig::Manual)?;
+                } = service::new_partial(&config, ForkingConfig::Manual, max_future_drift)?;
                 Ok((cmd.run(client, backend, None), task_manager))
             })
         }
@@ -145,6 +152,7 @@ pub fn run() -> sc_cli::Result<()> {
             let runner = cli.create_runner(cmd)?;
             runner.sync_run(|config| cmd.run::<Block>(&config))
         }
+        Some(Subcommand::Wallet(cmd)) => cmd.run().map_err(sc_cli::Error::Application),
         None => {
             // Get the mining account from the cli
             let bytes: [u8; 32] = cli.pow.public_key_bytes(cli.run.get_keyring());
@@ -158,11 +166,7 @@ pub fn run() -> sc_cli::Result<()> {
                     .expect("Should be able to get the fork config from the extension");
                 let forking_config = match ForkingConfig::from(forking_extension) {
                     ForkingConfig::Automatic(fork_heights, MaxiPosition::FollowMining) => {
-                        let maxi_position = match cli.pow.mining_algo {
-                            multi_pow::SupportedHashes::Md5 => MaxiPosition::NoMaxi,
-                            multi_pow::SupportedHashes::Sha3 => MaxiPosition::Sha3Maxi,
-                            multi_pow::SupportedHashes::Keccak => MaxiPosition::KeccakMaxi,
-                        };
+                        let maxi_position = MaxiPosition::following(cli.pow.mining_algo);
                         ForkingConfig::Automatic(fork_heights, maxi_position)
                     }
                     old_config => old_config,
@@ -175,6 +179,12 @@ pub fn run() -> sc_cli::Result<()> {
                     sr25519_public_key,
                     cli.pow.instant_seal,
                     cli.pow.mining_algo,
+                    service::UtxoOptions {
+                        txindex: cli.pow.utxo_txindex,
+                        addrindex: cli.pow.utxo_addrindex,
+                        max_orphans_per_outpoint: cli.pow.utxo_max_orphans_per_outpoint,
+                    },
+                    max_future_drift,
                 )
                 .map_err(sc_cli::Error::Service)
             })
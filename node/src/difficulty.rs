@@ -0,0 +1,82 @@
+//! The difficulty of imported blocks, at their own timestamp.
+//!
+//! The difficulty of an algorithm decays when it goes without blocks for long, so the difficulty
+//! a block has to meet depends on its timestamp. `MultiPow` only knows the parent of the block
+//! it verifies, and would use the local clock. Here the difficulty at the block's timestamp is
+//! handed to it instead, so every node requires the same difficulty of a block, whenever it
+//! imports it. On a testnet with the minimum difficulty rule, the same goes for that rule.
+//!
+//! A miner could date a block ahead to have the difficulty decay sooner. `FutureDriftCheck`
+//! bounds how far ahead, and the runtime never lets the timestamps of later blocks go back, so
+//! the decay only ever depends on the block itself.
+
+use academy_pow_runtime::opaque::Block;
+use multi_pow::{MultiPow, Threshold, TimedDifficultyApi};
+use sc_consensus::{BlockCheckParams, BlockImport, BlockImportParams, ImportResult};
+use sc_consensus_pow::{PowIntermediate, INTERMEDIATE_KEY};
+use sp_api::ProvideRuntimeApi;
+use sp_consensus::Error as ConsensusError;
+
+use crate::timestamp::timestamp;
+
+/// Block import setting the difficulty of blocks to the one at their timestamp, before passing
+/// them on to `inner`, the PoW block import
+pub struct TimedDifficulty<I, C> {
+    inner: I,
+    algorithm: MultiPow<C>,
+}
+
+impl<I, C> TimedDifficulty<I, C> {
+    pub fn new(inner: I, algorithm: MultiPow<C>) -> Self {
+        Self { inner, algorithm }
+    }
+}
+
+impl<I: Clone, C> Clone for TimedDifficulty<I, C> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone(), self.algorithm.clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl<I, C> BlockImport<Block> for TimedDifficulty<I, C>
+where
+    I: BlockImport<Block, Error = ConsensusError> + Send + Sync,
+    C: ProvideRuntimeApi<Block> + Send + Sync,
+    C::Api: TimedDifficultyApi<Block>,
+{
+    type Error = ConsensusError;
+
+    async fn check_block(
+        &self,
+        block: BlockCheckParams<Block>,
+    ) -> Result<ImportResult, Self::Error> {
+        self.inner.check_block(block).await
+    }
+
+    async fn import_block(
+        &mut self,
+        mut block: BlockImportParams<Block>,
+    ) -> Result<ImportResult, Self::Error> {
+        let block_time = block
+            .body
+            .as_ref()
+            .and_then(|body| body.iter().find_map(timestamp));
+
+        // Without a body, the PoW block import falls back to the difficulty now
+        if let Some(block_time) = block_time {
+            let difficulty = self
+                .algorithm
+                .difficulty_at(block.header.parent_hash, block_time)
+                .map_err(|e| ConsensusError::ClientImport(e.to_string()))?;
+            block.insert_intermediate(
+                INTERMEDIATE_KEY,
+                PowIntermediate::<Threshold> {
+                    difficulty: Some(difficulty),
+                },
+            );
+        }
+
+        self.inner.import_block(block).await
+    }
+}
@@ -1,12 +1,18 @@
 //! Substrate Node Template CLI library.
 #![warn(missing_docs)]
 
+mod addrindex;
 mod chain_spec;
 #[macro_use]
 mod service;
 mod cli;
 mod command;
+mod difficulty;
+mod orphans;
 mod rpc;
+mod txindex;
+mod timestamp;
+mod wallet;
 
 fn main() -> sc_cli::Result<()> {
     command::run()
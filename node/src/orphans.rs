@@ -0,0 +1,342 @@
+//! Cap on the future UTXO transactions waiting for the same outpoint.
+//!
+//! The utxo pallet accepts spends of outpoints that don't exist yet, so the pool keeps them
+//! in its future queue until the outpoints are created or their `OrphanLongevity` runs out.
+//! Nothing stops anyone from sending many such spends of one made-up outpoint, so every
+//! submission goes through [`OrphanCappedPool`]: a submitted transaction that lands in the
+//! future queue while the cap of earlier transactions already wait for one of its missing
+//! outpoints is removed again, which also bans it from the pool for a while, and refused.
+//! The pool only knows the outpoints a transaction misses once it validated it, so the cap is
+//! checked right after each batch, against one snapshot of the future queue. Transactions
+//! queued before are never removed, within the batch the first ones are kept.
+
+use std::{
+    collections::{HashMap, HashSet},
+    pin::Pin,
+    sync::Arc,
+};
+
+use async_trait::async_trait;
+use sc_transaction_pool_api::{
+    error::Error as PoolError, ChainEvent, ImportNotificationStream, InPoolTransaction,
+    MaintainedTransactionPool, PoolFuture, PoolStatus, ReadyTransactions, TransactionFor,
+    TransactionPool, TransactionSource, TransactionStatusStreamFor, TxHash,
+};
+use sp_runtime::{
+    traits::{Block as BlockT, NumberFor},
+    transaction_validity::TransactionTag,
+};
+
+/// Transaction pool refusing future transactions beyond `max_per_outpoint` requiring the same
+/// outpoint, the oldest ones are kept
+pub struct OrphanCappedPool<P> {
+    inner: Arc<P>,
+    max_per_outpoint: usize,
+}
+
+impl<P> OrphanCappedPool<P> {
+    /// Caps the future transactions of `inner`
+    pub fn new(inner: Arc<P>, max_per_outpoint: usize) -> Self {
+        Self {
+            inner,
+            max_per_outpoint,
+        }
+    }
+}
+
+/// The outpoints `tx` waits for. UTXO spends require the outpoints they miss, other tags are
+/// not outpoints.
+fn missing_outpoints<T: InPoolTransaction>(tx: &T) -> impl Iterator<Item = &TransactionTag> {
+    tx.requires().iter().filter(|tag| tag.len() == 32)
+}
+
+/// The transactions of `submitted`, in submission order, that wait in `futures` for an outpoint
+/// `max_per_outpoint` earlier transactions already wait for. The transactions of `futures` not
+/// in `submitted` were queued before and are never over the cap.
+fn over_cap<T: InPoolTransaction>(
+    futures: &[T],
+    submitted: &[T::Hash],
+    max_per_outpoint: usize,
+) -> Vec<T::Hash>
+where
+    T::Hash: std::hash::Hash + Eq + Clone,
+{
+    let batch: HashSet<&T::Hash> = submitted.iter().collect();
+    let mut waiting = HashMap::<&TransactionTag, usize>::new();
+    let mut queued = HashMap::new();
+    for tx in futures {
+        if batch.contains(tx.hash()) {
+            queued.insert(tx.hash(), tx);
+        } else {
+            for outpoint in missing_outpoints(tx) {
+                *waiting.entry(outpoint).or_default() += 1;
+            }
+        }
+    }
+
+    let mut refused = Vec::new();
+    for hash in submitted {
+        // Ready transactions are not in the future queue
+        let Some(tx) = queued.get(hash) else {
+            continue;
+        };
+
+        if missing_outpoints(*tx)
+            .any(|outpoint| waiting.get(outpoint).copied().unwrap_or_default() >= max_per_outpoint)
+        {
+            refused.push(hash.clone());
+        } else {
+            for outpoint in missing_outpoints(*tx) {
+                *waiting.entry(outpoint).or_default() += 1;
+            }
+        }
+    }
+
+    refused
+}
+
+/// Remove the transactions of `results` over the cap from `pool` again and refuse them
+fn enforce_cap<P: TransactionPool>(
+    pool: &P,
+    max_per_outpoint: usize,
+    results: Vec<Result<TxHash<P>, P::Error>>,
+) -> Vec<Result<TxHash<P>, P::Error>> {
+    let submitted: Vec<_> = results
+        .iter()
+        .filter_map(|result| result.as_ref().ok().cloned())
+        .collect();
+    let refused = over_cap(&pool.futures(), &submitted, max_per_outpoint);
+    if refused.is_empty() {
+        return results;
+    }
+
+    log::debug!(
+        "Refusing future UTXO transactions {:?} over the cap of {} per outpoint",
+        refused,
+        max_per_outpoint
+    );
+    pool.remove_invalid(&refused);
+    let refused: HashSet<_> = refused.into_iter().collect();
+    results
+        .into_iter()
+        .map(|result| match result {
+            Ok(hash) if refused.contains(&hash) => Err(PoolError::ImmediatelyDropped.into()),
+            result => result,
+        })
+        .collect()
+}
+
+/// [`enforce_cap`] on a single submitted transaction
+fn enforce_cap_one<P: TransactionPool>(
+    pool: &P,
+    max_per_outpoint: usize,
+    hash: TxHash<P>,
+) -> Result<TxHash<P>, P::Error> {
+    enforce_cap(pool, max_per_outpoint, vec![Ok(hash)])
+        .pop()
+        .expect("one result per submitted transaction")
+}
+
+impl<P: TransactionPool + 'static> TransactionPool for OrphanCappedPool<P> {
+    type Block = P::Block;
+    type Hash = P::Hash;
+    type InPoolTransaction = P::InPoolTransaction;
+    type Error = P::Error;
+
+    fn submit_at(
+        &self,
+        at: <Self::Block as BlockT>::Hash,
+        source: TransactionSource,
+        xts: Vec<TransactionFor<Self>>,
+    ) -> PoolFuture<Vec<Result<TxHash<Self>, Self::Error>>, Self::Error> {
+        let (pool, max_per_outpoint) = (self.inner.clone(), self.max_per_outpoint);
+        let submitted = self.inner.submit_at(at, source, xts);
+        Box::pin(async move { Ok(enforce_cap(&*pool, max_per_outpoint, submitted.await?)) })
+    }
+
+    fn submit_one(
+        &self,
+        at: <Self::Block as BlockT>::Hash,
+        source: TransactionSource,
+        xt: TransactionFor<Self>,
+    ) -> PoolFuture<TxHash<Self>, Self::Error> {
+        let (pool, max_per_outpoint) = (self.inner.clone(), self.max_per_outpoint);
+        let submitted = self.inner.submit_one(at, source, xt);
+        Box::pin(async move { enforce_cap_one(&*pool, max_per_outpoint, submitted.await?) })
+    }
+
+    fn submit_and_watch(
+        &self,
+        at: <Self::Block as BlockT>::Hash,
+        source: TransactionSource,
+        xt: TransactionFor<Self>,
+    ) -> PoolFuture<Pin<Box<TransactionStatusStreamFor<Self>>>, Self::Error> {
+        let (pool, max_per_outpoint) = (self.inner.clone(), self.max_per_outpoint);
+        let hash = self.inner.hash_of(&xt);
+        let submitted = self.inner.submit_and_watch(at, source, xt);
+        Box::pin(async move {
+            let watcher = submitted.await?;
+            enforce_cap_one(&*pool, max_per_outpoint, hash)?;
+            Ok(watcher)
+        })
+    }
+
+    fn ready_at(
+        &self,
+        at: NumberFor<Self::Block>,
+    ) -> Pin<
+        Box<
+            dyn futures::Future<
+                    Output = Box<dyn ReadyTransactions<Item = Arc<Self::InPoolTransaction>> + Send>,
+                > + Send,
+        >,
+    > {
+        self.inner.ready_at(at)
+    }
+
+    fn ready(&self) -> Box<dyn ReadyTransactions<Item = Arc<Self::InPoolTransaction>> + Send> {
+        self.inner.ready()
+    }
+
+    fn remove_invalid(&self, hashes: &[TxHash<Self>]) -> Vec<Arc<Self::InPoolTransaction>> {
+        self.inner.remove_invalid(hashes)
+    }
+
+    fn futures(&self) -> Vec<Self::InPoolTransaction> {
+        self.inner.futures()
+    }
+
+    fn status(&self) -> PoolStatus {
+        self.inner.status()
+    }
+
+    fn import_notification_stream(&self) -> ImportNotificationStream<TxHash<Self>> {
+        self.inner.import_notification_stream()
+    }
+
+    fn on_broadcasted(&self, propagations: HashMap<TxHash<Self>, Vec<String>>) {
+        self.inner.on_broadcasted(propagations)
+    }
+
+    fn hash_of(&self, xt: &TransactionFor<Self>) -> TxHash<Self> {
+        self.inner.hash_of(xt)
+    }
+
+    fn ready_transaction(&self, hash: &TxHash<Self>) -> Option<Arc<Self::InPoolTransaction>> {
+        self.inner.ready_transaction(hash)
+    }
+}
+
+#[async_trait]
+impl<P: MaintainedTransactionPool + 'static> MaintainedTransactionPool for OrphanCappedPool<P> {
+    async fn maintain(&self, event: ChainEvent<Self::Block>) {
+        self.inner.maintain(event).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sp_runtime::transaction_validity::{
+        TransactionLongevity, TransactionPriority, TransactionTag,
+    };
+
+    use super::*;
+
+    struct Future {
+        hash: u64,
+        requires: Vec<TransactionTag>,
+    }
+
+    impl InPoolTransaction for Future {
+        type Transaction = ();
+        type Hash = u64;
+
+        fn data(&self) -> &() {
+            &()
+        }
+
+        fn hash(&self) -> &u64 {
+            &self.hash
+        }
+
+        fn priority(&self) -> &TransactionPriority {
+            &0
+        }
+
+        fn longevity(&self) -> &TransactionLongevity {
+            &0
+        }
+
+        fn requires(&self) -> &[TransactionTag] {
+            &self.requires
+        }
+
+        fn provides(&self) -> &[TransactionTag] {
+            &[]
+        }
+
+        fn is_propagable(&self) -> bool {
+            true
+        }
+    }
+
+    fn waiting_for(hash: u64, outpoints: &[u8]) -> Future {
+        Future {
+            hash,
+            requires: outpoints.iter().map(|byte| vec![*byte; 32]).collect(),
+        }
+    }
+
+    #[test]
+    fn transactions_beyond_the_cap_of_an_outpoint_are_refused() {
+        let futures = [
+            waiting_for(1, &[7]),
+            waiting_for(2, &[7]),
+            waiting_for(3, &[7]),
+        ];
+
+        assert!(over_cap(&futures[..2], &[2], 2).is_empty());
+        assert_eq!(over_cap(&futures, &[3], 2), vec![3]);
+    }
+
+    #[test]
+    fn any_missing_outpoint_over_the_cap_refuses_the_transaction() {
+        let futures = [waiting_for(1, &[7]), waiting_for(2, &[8, 7])];
+
+        assert!(over_cap(&futures, &[2], 2).is_empty());
+        assert_eq!(over_cap(&futures, &[2], 1), vec![2]);
+    }
+
+    #[test]
+    fn only_outpoints_count_towards_the_cap() {
+        let short_tag = |hash| Future {
+            hash,
+            requires: vec![vec![7; 8]],
+        };
+        let futures = [short_tag(1), short_tag(2)];
+
+        assert!(over_cap(&futures, &[2], 1).is_empty());
+    }
+
+    #[test]
+    fn ready_transactions_are_never_refused() {
+        let futures = [waiting_for(1, &[7])];
+
+        assert!(over_cap(&futures, &[2], 0).is_empty());
+    }
+
+    #[test]
+    fn the_oldest_orphans_of_a_batch_survive() {
+        let futures = [
+            waiting_for(1, &[7]),
+            waiting_for(2, &[7]),
+            waiting_for(3, &[8]),
+            waiting_for(4, &[7]),
+            waiting_for(5, &[8]),
+        ];
+
+        // 1 was queued before the batch, 2 and 3 are the first of it
+        assert_eq!(over_cap(&futures, &[2, 3, 4, 5], 2), vec![4]);
+        assert_eq!(over_cap(&futures, &[2, 3, 4, 5], 1), vec![2, 4, 5]);
+    }
+}
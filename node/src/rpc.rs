@@ -7,13 +7,23 @@
 
 use std::sync::Arc;
 
-use academy_pow_runtime::{opaque::Block, AccountId, Balance, Index};
+use academy_pow_runtime::{opaque::Block, AccountId, Balance, Hash, Index};
 use jsonrpsee::RpcModule;
+use sc_client_api::{AuxStore, BlockBackend};
 pub use sc_rpc_api::DenyUnsafe;
 use sc_transaction_pool_api::TransactionPool;
 use sp_api::ProvideRuntimeApi;
 use sp_block_builder::BlockBuilder;
 use sp_blockchain::{Error as BlockChainError, HeaderBackend, HeaderMetadata};
+use sp_keystore::KeystorePtr;
+
+pub mod pow;
+// `async_trait` marks the async methods of the server traits `#[must_use]`, on top of the
+// boxed future they return
+#[allow(clippy::double_must_use)]
+pub mod utxo;
+#[allow(clippy::double_must_use)]
+pub mod wallet;
 
 /// Full client dependencies.
 pub struct FullDeps<C, P> {
@@ -23,6 +33,16 @@ pub struct FullDeps<C, P> {
     pub pool: Arc<P>,
     /// Whether to deny unsafe calls
     pub deny_unsafe: DenyUnsafe,
+    /// Keystore holding the keys of the node wallet.
+    pub keystore: KeystorePtr,
+    /// Outputs owned by the node wallet.
+    pub wallet: Arc<wallet::WalletState>,
+    /// Human-readable part of the addresses of the chain.
+    pub address_hrp: String,
+    /// Whether UTXO transactions are indexed by txid.
+    pub utxo_txindex: bool,
+    /// Subscribers of the UTXO address index, if it is maintained.
+    pub utxo_addrindex: Option<Arc<crate::addrindex::AddressIndex>>,
 }
 
 /// Instantiate all full RPC extensions.
@@ -32,24 +52,42 @@ pub fn create_full<C, P>(
 where
     C: ProvideRuntimeApi<Block>,
     C: HeaderBackend<Block> + HeaderMetadata<Block, Error = BlockChainError> + 'static,
+    C: AuxStore + BlockBackend<Block>,
     C: Send + Sync + 'static,
     C::Api: substrate_frame_rpc_system::AccountNonceApi<Block, AccountId, Index>,
     C::Api: pallet_transaction_payment_rpc::TransactionPaymentRuntimeApi<Block, Balance>,
     C::Api: BlockBuilder<Block>,
-    P: TransactionPool + 'static,
+    C::Api: academy_pow_runtime::utxo::UtxoApi<Block>,
+    C::Api: academy_pow_runtime::difficulty::DifficultyHistoryApi<Block>,
+    C::Api: sp_consensus_pow::DifficultyApi<Block, multi_pow::Threshold>,
+    C::Api: sp_transaction_pool::runtime_api::TaggedTransactionQueue<Block>,
+    P: TransactionPool<Block = Block, Hash = Hash> + 'static,
 {
     use pallet_transaction_payment_rpc::{TransactionPayment, TransactionPaymentApiServer};
+    use pow::{Pow, PowApiServer};
     use substrate_frame_rpc_system::{System, SystemApiServer};
+    use utxo::{Utxo, UtxoApiServer};
+    use wallet::{Wallet, WalletApiServer};
 
     let mut module = RpcModule::new(());
     let FullDeps {
         client,
         pool,
         deny_unsafe,
+        keystore,
+        wallet,
+        address_hrp,
+        utxo_txindex,
+        utxo_addrindex,
     } = deps;
 
-    module.merge(System::new(client.clone(), pool, deny_unsafe).into_rpc())?;
-    module.merge(TransactionPayment::new(client).into_rpc())?;
+    module.merge(System::new(client.clone(), pool.clone(), deny_unsafe).into_rpc())?;
+    module.merge(TransactionPayment::new(client.clone()).into_rpc())?;
+    module.merge(Pow::new(client.clone()).into_rpc())?;
+    module
+        .merge(Utxo::new(client.clone(), pool.clone(), utxo_txindex, utxo_addrindex).into_rpc())?;
+    module
+        .merge(Wallet::new(client, pool, keystore, wallet, address_hrp, deny_unsafe).into_rpc())?;
 
     Ok(module)
 }
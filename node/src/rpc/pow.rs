@@ -0,0 +1,258 @@
+//! RPC methods to follow the difficulty of each mining algorithm, estimate the hashrate behind
+//! it and see how the recent blocks split between the algorithms.
+
+use std::sync::Arc;
+
+use academy_pow_runtime::{
+    difficulty::{DifficultyAndTimestamp, DifficultyHistoryApi},
+    opaque::Block,
+    DifficultyWindow, Hash, PreDigest,
+};
+use jsonrpsee::{
+    core::RpcResult,
+    proc_macros::rpc,
+    types::{error::ErrorObject, ErrorObjectOwned},
+};
+use multi_pow::{AlgoId, Threshold, ALGORITHMS};
+use parity_scale_codec::Decode;
+use serde::{Deserialize, Serialize};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_consensus_pow::{DifficultyApi, POW_ENGINE_ID};
+use sp_core::U256;
+use sp_runtime::{traits::Header as _, DigestItem};
+
+/// Difficulty of an algorithm at a past block
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DifficultySample {
+    /// Difficulty the block was mined at
+    pub difficulty: U256,
+    /// Timestamp of the block, in milliseconds
+    pub timestamp: u64,
+}
+
+impl From<DifficultyAndTimestamp<u128>> for DifficultySample {
+    fn from(sample: DifficultyAndTimestamp<u128>) -> Self {
+        Self {
+            difficulty: sample.difficulty,
+            timestamp: sample.timestamp as u64,
+        }
+    }
+}
+
+/// Hashrate mining an algorithm, estimated from the difficulty of its recent blocks and the
+/// time they took
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkHashrate {
+    /// Number of blocks the estimate is based on
+    pub blocks: u32,
+    /// Time the blocks took, in milliseconds
+    pub timespan: u64,
+    /// Expected number of hashes computed per second
+    pub hashes_per_second: U256,
+}
+
+/// How many of the recent blocks an algorithm mined
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AlgoShare {
+    /// The algorithm
+    pub algo: AlgoId,
+    /// Number of blocks it mined
+    pub blocks: u32,
+    /// Fraction of the blocks it mined
+    pub share: f64,
+}
+
+/// PoW RPC methods.
+#[rpc(client, server)]
+pub trait PowApi<BlockHash> {
+    /// Difficulty of each algorithm for the block after `at`
+    #[method(name = "pow_getDifficulty")]
+    fn difficulty(&self, at: Option<BlockHash>) -> RpcResult<Threshold>;
+
+    /// Difficulty and timestamp of the `n` latest blocks mined with `algo`, earliest first.
+    /// At most the difficulty window of the runtime is available, and until `algo` has mined
+    /// a full window it starts with the initial difficulty at on-target timestamps.
+    #[method(name = "pow_getDifficultyHistory")]
+    fn difficulty_history(
+        &self,
+        algo: AlgoId,
+        n: u32,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Vec<DifficultySample>>;
+
+    /// Hashrate mining `algo`, estimated over its `window` latest blocks
+    #[method(name = "pow_getNetworkHashrate")]
+    fn network_hashrate(
+        &self,
+        algo: AlgoId,
+        window: u32,
+        at: Option<BlockHash>,
+    ) -> RpcResult<NetworkHashrate>;
+
+    /// Blocks mined with each algorithm among the `window` latest blocks, at most the
+    /// difficulty window of the runtime
+    #[method(name = "pow_getAlgoShare")]
+    fn algo_share(&self, window: u32, at: Option<BlockHash>) -> RpcResult<Vec<AlgoShare>>;
+}
+
+/// Provides RPC methods to follow the difficulty adjustment.
+pub struct Pow<C> {
+    /// Shared reference to the client.
+    client: Arc<C>,
+}
+
+impl<C> Pow<C> {
+    /// Creates a new instance of the Pow Rpc helper.
+    pub fn new(client: Arc<C>) -> Self {
+        Self { client }
+    }
+}
+
+/// Error type of this RPC api.
+pub enum Error {
+    /// The call to runtime failed.
+    RuntimeError,
+    /// A block header couldn't be read.
+    BlockchainError,
+}
+
+impl From<Error> for i32 {
+    fn from(e: Error) -> i32 {
+        match e {
+            Error::RuntimeError => 1,
+            Error::BlockchainError => 2,
+        }
+    }
+}
+
+fn runtime_error(error: impl ToString) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        Error::RuntimeError.into(),
+        "Unable to query the difficulty.",
+        Some(error.to_string()),
+    )
+}
+
+fn blockchain_error(error: impl ToString) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        Error::BlockchainError.into(),
+        "Unable to read the chain.",
+        Some(error.to_string()),
+    )
+}
+
+/// The algorithm a block was mined with, from its pre-runtime digest
+fn mining_algo(digest: &sp_runtime::Digest) -> Option<AlgoId> {
+    digest.logs().iter().find_map(|item| match item {
+        DigestItem::PreRuntime(POW_ENGINE_ID, pre_digest) => {
+            PreDigest::decode(&mut &pre_digest[..])
+                .map(|(_, algo)| algo)
+                .ok()
+        }
+        _ => None,
+    })
+}
+
+impl<C> PowApiServer<Hash> for Pow<C>
+where
+    C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+    C::Api: DifficultyApi<Block, Threshold> + DifficultyHistoryApi<Block>,
+{
+    fn difficulty(&self, at: Option<Hash>) -> RpcResult<Threshold> {
+        let at = at.unwrap_or_else(|| self.client.info().best_hash);
+        self.client
+            .runtime_api()
+            .difficulty(at)
+            .map_err(runtime_error)
+    }
+
+    fn difficulty_history(
+        &self,
+        algo: AlgoId,
+        n: u32,
+        at: Option<Hash>,
+    ) -> RpcResult<Vec<DifficultySample>> {
+        let at = at.unwrap_or_else(|| self.client.info().best_hash);
+        let history = self
+            .client
+            .runtime_api()
+            .difficulty_history(at, algo)
+            .map_err(runtime_error)?;
+        let skip = history.len().saturating_sub(n as usize);
+
+        Ok(history.into_iter().skip(skip).map(Into::into).collect())
+    }
+
+    fn network_hashrate(
+        &self,
+        algo: AlgoId,
+        window: u32,
+        at: Option<Hash>,
+    ) -> RpcResult<NetworkHashrate> {
+        // The time of the first block is measured from the one before it
+        let samples = self.difficulty_history(algo, window.saturating_add(1), at)?;
+        let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+            return Ok(NetworkHashrate {
+                blocks: 0,
+                timespan: 0,
+                hashes_per_second: U256::zero(),
+            });
+        };
+
+        // A block of difficulty `d` takes `d` hashes on average
+        let work = samples[1..].iter().fold(U256::zero(), |work, sample| {
+            work.saturating_add(sample.difficulty)
+        });
+        let timespan = last.timestamp.saturating_sub(first.timestamp);
+
+        Ok(NetworkHashrate {
+            blocks: samples.len() as u32 - 1,
+            timespan,
+            hashes_per_second: work.saturating_mul(U256::from(1_000)) / timespan.max(1),
+        })
+    }
+
+    fn algo_share(&self, window: u32, at: Option<Hash>) -> RpcResult<Vec<AlgoShare>> {
+        // Every header is read from the database, don't let a caller walk the whole chain
+        let window = window.min(DifficultyWindow::get());
+        let mut hash = at.unwrap_or_else(|| self.client.info().best_hash);
+        let mut shares: Vec<_> = ALGORITHMS
+            .iter()
+            .map(|algorithm| AlgoShare {
+                algo: algorithm.id,
+                blocks: 0,
+                share: 0.0,
+            })
+            .collect();
+
+        let mut total = 0;
+        while total < window {
+            let header = self
+                .client
+                .header(hash)
+                .map_err(blockchain_error)?
+                .ok_or_else(|| blockchain_error(format!("unknown block {:?}", hash)))?;
+            // The genesis block isn't mined
+            if header.number == 0 {
+                break;
+            }
+            if let Some(algo) = mining_algo(header.digest()) {
+                if let Some(share) = shares.iter_mut().find(|share| share.algo == algo) {
+                    share.blocks += 1;
+                }
+            }
+            total += 1;
+            hash = header.parent_hash;
+        }
+
+        for share in shares.iter_mut() {
+            share.share = share.blocks as f64 / total.max(1) as f64;
+        }
+
+        Ok(shares)
+    }
+}
@@ -0,0 +1,768 @@
+//! RPC methods to query the UTXO set, UTXO transactions, pending spends and the history of
+//! owners, to test transactions against the pool rules, and to handle partially signed
+//! transactions, used by wallets such as the `wallet` subcommand.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use academy_pow_runtime::{
+    opaque::Block,
+    pst::Pst,
+    utxo::{
+        self, AssetId, AssetIssuance, Transaction, TransactionOutput, UtxoApi as UtxoRuntimeApi,
+        Value, NATIVE_ASSET,
+    },
+    wallet, BlockNumber, Hash, Runtime, RuntimeCall, UncheckedExtrinsic,
+};
+use futures::{future, StreamExt};
+use jsonrpsee::{
+    core::{async_trait, RpcResult, SubscriptionResult},
+    proc_macros::rpc,
+    types::{error::ErrorObject, ErrorObjectOwned},
+    PendingSubscriptionSink, SubscriptionMessage,
+};
+use parity_scale_codec::Encode;
+use sc_client_api::{AuxStore, BlockBackend};
+use sc_transaction_pool_api::{
+    error::{Error as PoolError, IntoPoolError},
+    InPoolTransaction, TransactionPool,
+};
+use serde::{Deserialize, Serialize};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_core::H256;
+use sp_runtime::transaction_validity::{
+    InvalidTransaction, TransactionSource, TransactionValidityError,
+};
+use sp_transaction_pool::runtime_api::TaggedTransactionQueue;
+
+use crate::{
+    addrindex::{self, AddressEvent, AddressIndex, HistoryEntry},
+    txindex,
+};
+
+/// Where a UTXO transaction stands
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "status")]
+pub enum TransactionStatus {
+    /// Waiting in the transaction pool
+    Pending,
+    /// Included in a block of the canonical chain
+    #[serde(rename_all = "camelCase")]
+    Confirmed {
+        /// Hash of the including block
+        block_hash: Hash,
+        /// Number of the including block
+        block_number: BlockNumber,
+        /// Index of the extrinsic in the block body
+        extrinsic_index: u32,
+        /// Number of blocks on top of the including one, itself included
+        confirmations: BlockNumber,
+    },
+}
+
+/// A UTXO transaction along with where it stands
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TransactionInfo {
+    /// The transaction
+    pub transaction: Transaction,
+    /// Where it stands
+    #[serde(flatten)]
+    pub status: TransactionStatus,
+}
+
+/// Where a transaction stands in the transaction pool
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum PoolStatus {
+    /// Every spent outpoint exists, possibly created by another ready transaction
+    Ready,
+    /// Waiting for outpoints no pending transaction creates yet
+    Future,
+}
+
+/// A UTXO transaction waiting in the transaction pool
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingTransaction {
+    /// Id of the transaction
+    pub txid: H256,
+    /// The transaction
+    pub transaction: Transaction,
+    /// Where it stands in the pool
+    pub status: PoolStatus,
+    /// Spent outpoints missing from the UTXO set when the transaction was validated
+    pub missing_outpoints: Vec<H256>,
+    /// Fee per encoded byte, unknown while some spent outpoint is neither in the UTXO set
+    /// nor created by another pending transaction
+    pub fee_rate: Option<Value>,
+}
+
+/// Unconfirmed value moving to and from an owner
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingBalance {
+    /// Value of the outputs pending transactions create for the owner
+    pub incoming: Value,
+    /// Value of the outputs of the owner pending transactions spend
+    pub outgoing: Value,
+    /// Ids of the pending transactions involving the owner
+    pub transactions: Vec<H256>,
+}
+
+/// Why the transaction pool refuses a transaction
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Rejection {
+    /// Stable code of the `utxo::Error`, when the utxo pallet refused the transaction
+    pub code: Option<u8>,
+    /// Name of the error
+    pub reason: String,
+}
+
+impl From<TransactionValidityError> for Rejection {
+    fn from(error: TransactionValidityError) -> Self {
+        if let TransactionValidityError::Invalid(InvalidTransaction::Custom(code)) = error {
+            if let Some(utxo_error) = utxo::Error::<Runtime>::from_code(code) {
+                return Rejection {
+                    code: Some(code),
+                    reason: <&'static str>::from(utxo_error).into(),
+                };
+            }
+        }
+
+        Rejection {
+            code: None,
+            reason: format!("{:?}", error),
+        }
+    }
+}
+
+/// Outcome of checking a transaction against the pool rules without submitting it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TestAccept {
+    /// Id of the transaction
+    pub txid: H256,
+    /// Whether the pool would accept the transaction
+    pub allowed: bool,
+    /// Spent outpoints missing from the UTXO set. The pool would keep the transaction in its
+    /// future queue until they are created.
+    pub missing_outpoints: Vec<H256>,
+    /// Fee paid by the transaction, known once no outpoint is missing
+    pub fee: Option<Value>,
+    /// Why the pool would refuse the transaction
+    pub rejection: Option<Rejection>,
+}
+
+/// Error for a transaction the pool refused, carrying the `Rejection` as data when the pool
+/// tells why
+pub fn pool_error(code: i32, error: impl IntoPoolError) -> ErrorObjectOwned {
+    const MESSAGE: &str = "Transaction rejected by the pool.";
+    let rejection = match error.into_pool_error() {
+        Ok(PoolError::InvalidTransaction(invalid)) => TransactionValidityError::Invalid(invalid),
+        Ok(PoolError::UnknownTransaction(unknown)) => TransactionValidityError::Unknown(unknown),
+        Ok(error) => return ErrorObject::owned(code, MESSAGE, Some(error.to_string())),
+        Err(error) => return ErrorObject::owned(code, MESSAGE, Some(error.to_string())),
+    };
+
+    ErrorObject::owned(code, MESSAGE, Some(Rejection::from(rejection)))
+}
+
+/// UTXO RPC methods.
+#[rpc(client, server)]
+pub trait UtxoApi<BlockHash> {
+    /// Unspent outputs locked to `owner`
+    #[method(name = "utxo_listUnspent")]
+    fn list_unspent(&self, owner: H256, at: Option<BlockHash>) -> RpcResult<Vec<wallet::Utxo>>;
+
+    /// Total value of the unspent outputs locked to `owner`, in the native asset
+    /// unless `asset` is given
+    #[method(name = "utxo_getBalance")]
+    fn balance(
+        &self,
+        owner: H256,
+        asset: Option<AssetId>,
+        at: Option<BlockHash>,
+    ) -> RpcResult<Value>;
+
+    /// Partially signed transaction spending the inputs of `transaction`, with their
+    /// previous outputs looked up in the UTXO set
+    #[method(name = "utxo_createPst")]
+    fn create_pst(&self, transaction: Transaction, at: Option<BlockHash>) -> RpcResult<String>;
+
+    /// Contents of a partially signed transaction
+    #[method(name = "utxo_decodePst")]
+    fn decode_pst(&self, pst: String) -> RpcResult<Pst>;
+
+    /// Merge the signatures of partially signed transactions of the same transaction
+    #[method(name = "utxo_combinePsts")]
+    fn combine_psts(&self, psts: Vec<String>) -> RpcResult<String>;
+
+    /// Check the signatures of a partially signed transaction and move them into the
+    /// transaction
+    #[method(name = "utxo_finalizePst")]
+    fn finalize_pst(&self, pst: String) -> RpcResult<String>;
+
+    /// The signed transaction of a finalized partially signed transaction
+    #[method(name = "utxo_extractPst")]
+    fn extract_pst(&self, pst: String) -> RpcResult<Transaction>;
+
+    /// The transaction `txid`, whether pending or confirmed. Confirmed transactions are only
+    /// found when the node runs with `--utxo-txindex`.
+    #[method(name = "utxo_getTransaction")]
+    fn get_transaction(&self, txid: H256) -> RpcResult<Option<TransactionInfo>>;
+
+    /// Check `transaction`, along with `issuance` if it mints an asset, against the rules
+    /// of the transaction pool without submitting it
+    #[method(name = "utxo_testAccept")]
+    fn test_accept(
+        &self,
+        transaction: Transaction,
+        issuance: Option<AssetIssuance>,
+        at: Option<BlockHash>,
+    ) -> RpcResult<TestAccept>;
+
+    /// UTXO transactions in the transaction pool, ready or waiting for missing outpoints
+    #[method(name = "utxo_pendingTransactions")]
+    fn pending_transactions(&self) -> RpcResult<Vec<PendingTransaction>>;
+
+    /// Value pending transactions move to and from `owner`, in the native asset unless
+    /// `asset` is given
+    #[method(name = "utxo_pendingForOwner")]
+    fn pending_for_owner(&self, owner: H256, asset: Option<AssetId>) -> RpcResult<PendingBalance>;
+
+    /// Outputs received and spent by `owner` on the canonical chain, oldest first. Only
+    /// available when the node runs with `--utxo-addrindex`.
+    #[method(name = "utxo_getAddressHistory")]
+    fn address_history(&self, owner: H256) -> RpcResult<Vec<HistoryEntry>>;
+
+    /// Activity of `owner` as blocks are indexed, and as reorgs take them out again. Only
+    /// available when the node runs with `--utxo-addrindex`.
+    #[subscription(
+        name = "utxo_subscribeAddress" => "utxo_addressEvent",
+        unsubscribe = "utxo_unsubscribeAddress",
+        item = AddressEvent
+    )]
+    async fn subscribe_address(&self, owner: H256) -> SubscriptionResult;
+}
+
+/// Provides RPC methods to query the UTXO set.
+pub struct Utxo<C, P> {
+    /// Shared reference to the client.
+    client: Arc<C>,
+    /// Transaction pool, to find pending transactions.
+    pool: Arc<P>,
+    /// Whether the transaction index is maintained.
+    txindex: bool,
+    /// Subscribers of the address index, if it is maintained.
+    addrindex: Option<Arc<AddressIndex>>,
+}
+
+impl<C, P> Utxo<C, P> {
+    /// Creates a new instance of the Utxo Rpc helper.
+    pub fn new(
+        client: Arc<C>,
+        pool: Arc<P>,
+        txindex: bool,
+        addrindex: Option<Arc<AddressIndex>>,
+    ) -> Self {
+        Self {
+            client,
+            pool,
+            txindex,
+            addrindex,
+        }
+    }
+}
+
+/// Error type of this RPC api.
+pub enum Error {
+    /// The call to runtime failed.
+    Runtime,
+    /// An input spends an outpoint missing from the UTXO set.
+    UnknownOutpoint,
+    /// The partially signed transaction is malformed or can't be processed.
+    InvalidPst,
+    /// The transaction index is disabled or unreadable.
+    TxIndex,
+    /// The address index is disabled or unreadable.
+    AddrIndex,
+}
+
+impl From<Error> for i32 {
+    fn from(e: Error) -> i32 {
+        match e {
+            Error::Runtime => 1,
+            Error::UnknownOutpoint => 2,
+            Error::InvalidPst => 3,
+            Error::TxIndex => 4,
+            Error::AddrIndex => 5,
+        }
+    }
+}
+
+fn runtime_error(error: impl ToString) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        Error::Runtime.into(),
+        "Unable to query the UTXO set.",
+        Some(error.to_string()),
+    )
+}
+
+fn pst_error(error: impl ToString) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        Error::InvalidPst.into(),
+        "Invalid partially signed transaction.",
+        Some(error.to_string()),
+    )
+}
+
+fn txindex_error(error: impl ToString) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        Error::TxIndex.into(),
+        "Unable to query the transaction index.",
+        Some(error.to_string()),
+    )
+}
+
+fn addrindex_error(error: impl ToString) -> ErrorObjectOwned {
+    ErrorObject::owned(
+        Error::AddrIndex.into(),
+        "Unable to query the address index.",
+        Some(error.to_string()),
+    )
+}
+
+const ADDRINDEX_DISABLED: &str = "the node runs without --utxo-addrindex";
+
+impl<C, P> Utxo<C, P>
+where
+    C: HeaderBackend<Block> + BlockBackend<Block> + AuxStore,
+    P: TransactionPool<Block = Block>,
+{
+    /// UTXO transactions in the pool, with their status and the outpoints they require
+    fn pool_transactions(&self) -> Vec<(Transaction, PoolStatus, Vec<H256>)> {
+        let pending = |tx: &P::InPoolTransaction, status| {
+            let transaction = txindex::utxo_transaction(tx.data())?;
+            let missing = tx
+                .requires()
+                .iter()
+                .filter(|tag| tag.len() == 32)
+                .map(|tag| H256::from_slice(tag))
+                .collect();
+            Some((transaction, status, missing))
+        };
+
+        self.pool
+            .ready()
+            .filter_map(|tx| pending(&tx, PoolStatus::Ready))
+            .chain(
+                self.pool
+                    .futures()
+                    .into_iter()
+                    .filter_map(|tx| pending(&tx, PoolStatus::Future)),
+            )
+            .collect()
+    }
+
+    fn pending_transaction(&self, txid: &H256) -> Option<Transaction> {
+        self.pool_transactions()
+            .into_iter()
+            .map(|(transaction, _, _)| transaction)
+            .find(|transaction| utxo::txid(transaction) == *txid)
+    }
+
+    fn confirmed_transaction(&self, txid: &H256) -> RpcResult<Option<TransactionInfo>> {
+        let Some(location) = txindex::lookup(&*self.client, txid).map_err(txindex_error)? else {
+            return Ok(None);
+        };
+        let block_number = match self.client.number(location.block_hash) {
+            Ok(Some(number)) => number,
+            _ => return Ok(None),
+        };
+        // The index follows the best chain asynchronously, make sure the block is still in
+        if self.client.hash(block_number).ok().flatten() != Some(location.block_hash) {
+            return Ok(None);
+        }
+        let transaction = self
+            .client
+            .block_body(location.block_hash)
+            .map_err(txindex_error)?
+            .and_then(|body| body.get(location.extrinsic_index as usize).cloned())
+            .and_then(|extrinsic| txindex::utxo_transaction(&extrinsic));
+
+        Ok(transaction.map(|transaction| TransactionInfo {
+            transaction,
+            status: TransactionStatus::Confirmed {
+                block_hash: location.block_hash,
+                block_number,
+                extrinsic_index: location.extrinsic_index,
+                confirmations: self.client.info().best_number.saturating_sub(block_number) + 1,
+            },
+        }))
+    }
+}
+
+impl<C, P> Utxo<C, P>
+where
+    C: ProvideRuntimeApi<Block> + HeaderBackend<Block>,
+    C::Api: UtxoRuntimeApi<Block>,
+    P: TransactionPool<Block = Block>,
+{
+    /// The outputs spent by `transactions`, looked up among the outputs they create and in
+    /// the UTXO set of the best block
+    fn prevouts(
+        &self,
+        transactions: &[(Transaction, PoolStatus, Vec<H256>)],
+    ) -> RpcResult<BTreeMap<H256, TransactionOutput>> {
+        let created: BTreeMap<H256, &TransactionOutput> = transactions
+            .iter()
+            .flat_map(|(transaction, _, _)| {
+                transaction
+                    .outputs
+                    .iter()
+                    .enumerate()
+                    .map(|(index, output)| (utxo::outpoint(transaction, index as u64), output))
+            })
+            .collect();
+
+        let best = self.client.info().best_hash;
+        let api = self.client.runtime_api();
+        let mut prevouts = BTreeMap::new();
+        for input in transactions.iter().flat_map(|(tx, _, _)| tx.inputs.iter()) {
+            let prevout = match created.get(&input.outpoint) {
+                Some(output) => Some((*output).clone()),
+                None => api.utxo(best, input.outpoint).map_err(runtime_error)?,
+            };
+            if let Some(prevout) = prevout {
+                prevouts.insert(input.outpoint, prevout);
+            }
+        }
+
+        Ok(prevouts)
+    }
+}
+
+/// Native value `transaction` leaves to the block author, if the outputs it spends are all
+/// known
+fn fee(transaction: &Transaction, prevouts: &BTreeMap<H256, TransactionOutput>) -> Option<Value> {
+    let mut input: Value = 0;
+    for spent in &transaction.inputs {
+        let prevout = prevouts.get(&spent.outpoint)?;
+        if prevout.asset == NATIVE_ASSET {
+            input = input.saturating_add(prevout.value);
+        }
+    }
+    let output: Value = transaction
+        .outputs
+        .iter()
+        .filter(|output| output.asset == NATIVE_ASSET)
+        .map(|output| output.value)
+        .sum();
+
+    Some(input.saturating_sub(output))
+}
+
+/// Fee per encoded byte of `transaction`, if the outputs it spends are all known
+fn fee_rate(
+    transaction: &Transaction,
+    prevouts: &BTreeMap<H256, TransactionOutput>,
+) -> Option<Value> {
+    Some(fee(transaction, prevouts)? / transaction.encoded_size() as Value)
+}
+
+#[async_trait]
+impl<C, P> UtxoApiServer<Hash> for Utxo<C, P>
+where
+    C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + Send + Sync + 'static,
+    C: BlockBackend<Block> + AuxStore,
+    C::Api: UtxoRuntimeApi<Block> + TaggedTransactionQueue<Block>,
+    P: TransactionPool<Block = Block> + 'static,
+{
+    fn list_unspent(&self, owner: H256, at: Option<Hash>) -> RpcResult<Vec<wallet::Utxo>> {
+        let at = at.unwrap_or_else(|| self.client.info().best_hash);
+        let utxos = self
+            .client
+            .runtime_api()
+            .utxos_of(at, owner)
+            .map_err(runtime_error)?;
+
+        Ok(utxos
+            .into_iter()
+            .map(|(outpoint, output)| wallet::Utxo { outpoint, output })
+            .collect())
+    }
+
+    fn balance(&self, owner: H256, asset: Option<AssetId>, at: Option<Hash>) -> RpcResult<Value> {
+        let asset = asset.unwrap_or(NATIVE_ASSET);
+        Ok(self
+            .list_unspent(owner, at)?
+            .iter()
+            .filter(|utxo| utxo.output.asset == asset)
+            .map(|utxo| utxo.output.value)
+            .sum())
+    }
+
+    fn create_pst(&self, transaction: Transaction, at: Option<Hash>) -> RpcResult<String> {
+        let at = at.unwrap_or_else(|| self.client.info().best_hash);
+        let api = self.client.runtime_api();
+        let mut prevouts = Vec::with_capacity(transaction.inputs.len());
+        for input in transaction.inputs.iter() {
+            let prevout = api
+                .utxo(at, input.outpoint)
+                .map_err(runtime_error)?
+                .ok_or_else(|| {
+                    ErrorObject::owned(
+                        Error::UnknownOutpoint.into(),
+                        "Input spends an unknown outpoint.",
+                        Some(format!("{:?}", input.outpoint)),
+                    )
+                })?;
+            prevouts.push(prevout);
+        }
+
+        Ok(Pst::from(wallet::UnsignedTransaction {
+            transaction,
+            prevouts,
+        })
+        .to_hex())
+    }
+
+    fn decode_pst(&self, pst: String) -> RpcResult<Pst> {
+        Pst::from_hex(&pst).map_err(pst_error)
+    }
+
+    fn combine_psts(&self, psts: Vec<String>) -> RpcResult<String> {
+        let mut psts = psts.iter().map(|pst| Pst::from_hex(pst));
+        let mut combined = psts
+            .next()
+            .ok_or_else(|| pst_error("no PST given"))?
+            .map_err(pst_error)?;
+        for pst in psts {
+            combined
+                .combine(pst.map_err(pst_error)?)
+                .map_err(pst_error)?;
+        }
+
+        Ok(combined.to_hex())
+    }
+
+    fn finalize_pst(&self, pst: String) -> RpcResult<String> {
+        let mut pst = Pst::from_hex(&pst).map_err(pst_error)?;
+        pst.finalize().map_err(pst_error)?;
+        Ok(pst.to_hex())
+    }
+
+    fn extract_pst(&self, pst: String) -> RpcResult<Transaction> {
+        Pst::from_hex(&pst)
+            .and_then(Pst::extract)
+            .map_err(pst_error)
+    }
+
+    fn get_transaction(&self, txid: H256) -> RpcResult<Option<TransactionInfo>> {
+        if self.txindex {
+            if let Some(info) = self.confirmed_transaction(&txid)? {
+                return Ok(Some(info));
+            }
+        }
+
+        match self.pending_transaction(&txid) {
+            Some(transaction) => Ok(Some(TransactionInfo {
+                transaction,
+                status: TransactionStatus::Pending,
+            })),
+            None if !self.txindex => Err(txindex_error(
+                "not in the transaction pool and the node runs without --utxo-txindex",
+            )),
+            None => Ok(None),
+        }
+    }
+
+    fn test_accept(
+        &self,
+        transaction: Transaction,
+        issuance: Option<AssetIssuance>,
+        at: Option<Hash>,
+    ) -> RpcResult<TestAccept> {
+        let at = at.unwrap_or_else(|| self.client.info().best_hash);
+        let txid = utxo::txid(&transaction);
+        let api = self.client.runtime_api();
+        let mut prevouts = BTreeMap::new();
+        for input in &transaction.inputs {
+            if let Some(prevout) = api.utxo(at, input.outpoint).map_err(runtime_error)? {
+                prevouts.insert(input.outpoint, prevout);
+            }
+        }
+        let fee = fee(&transaction, &prevouts);
+        let call = match issuance {
+            Some(issuance) => utxo::Call::issue {
+                transaction,
+                issuance,
+            },
+            None => utxo::Call::spend { transaction },
+        };
+        let extrinsic = UncheckedExtrinsic::new_unsigned(RuntimeCall::Utxo(call));
+
+        let validity = api
+            .validate_transaction(at, TransactionSource::External, extrinsic.into(), at)
+            .map_err(runtime_error)?;
+
+        Ok(match validity {
+            Ok(valid) => {
+                let missing_outpoints: Vec<H256> = valid
+                    .requires
+                    .iter()
+                    .filter(|tag| tag.len() == 32)
+                    .map(|tag| H256::from_slice(tag))
+                    .collect();
+                TestAccept {
+                    txid,
+                    allowed: true,
+                    fee,
+                    missing_outpoints,
+                    rejection: None,
+                }
+            }
+            Err(error) => TestAccept {
+                txid,
+                allowed: false,
+                missing_outpoints: Vec::new(),
+                fee: None,
+                rejection: Some(error.into()),
+            },
+        })
+    }
+
+    fn pending_transactions(&self) -> RpcResult<Vec<PendingTransaction>> {
+        let transactions = self.pool_transactions();
+        let prevouts = self.prevouts(&transactions)?;
+
+        Ok(transactions
+            .into_iter()
+            .map(
+                |(transaction, status, missing_outpoints)| PendingTransaction {
+                    txid: utxo::txid(&transaction),
+                    fee_rate: fee_rate(&transaction, &prevouts),
+                    transaction,
+                    status,
+                    missing_outpoints,
+                },
+            )
+            .collect())
+    }
+
+    fn pending_for_owner(&self, owner: H256, asset: Option<AssetId>) -> RpcResult<PendingBalance> {
+        let asset = asset.unwrap_or(NATIVE_ASSET);
+        let transactions = self.pool_transactions();
+        let prevouts = self.prevouts(&transactions)?;
+
+        let mut balance = PendingBalance::default();
+        for (transaction, _, _) in &transactions {
+            let outgoing: Value = transaction
+                .inputs
+                .iter()
+                .filter_map(|input| prevouts.get(&input.outpoint))
+                .filter(|prevout| prevout.pubkey == owner && prevout.asset == asset)
+                .map(|prevout| prevout.value)
+                .sum();
+            let incoming: Value = transaction
+                .outputs
+                .iter()
+                .filter(|output| output.pubkey == owner && output.asset == asset)
+                .map(|output| output.value)
+                .sum();
+
+            if incoming > 0 || outgoing > 0 {
+                balance.incoming += incoming;
+                balance.outgoing += outgoing;
+                balance.transactions.push(utxo::txid(transaction));
+            }
+        }
+
+        Ok(balance)
+    }
+
+    fn address_history(&self, owner: H256) -> RpcResult<Vec<HistoryEntry>> {
+        if self.addrindex.is_none() {
+            return Err(addrindex_error(ADDRINDEX_DISABLED));
+        }
+
+        addrindex::history(&*self.client, &owner).map_err(addrindex_error)
+    }
+
+    async fn subscribe_address(
+        &self,
+        pending: PendingSubscriptionSink,
+        owner: H256,
+    ) -> SubscriptionResult {
+        let Some(index) = &self.addrindex else {
+            pending.reject(addrindex_error(ADDRINDEX_DISABLED)).await;
+            return Ok(());
+        };
+        let mut events = index.subscribe(owner);
+        let sink = pending.accept().await?;
+
+        loop {
+            match future::select(Box::pin(sink.closed()), events.next()).await {
+                future::Either::Right((Some(event), _)) => {
+                    sink.send(SubscriptionMessage::from_json(&event)?).await?;
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use academy_pow_runtime::utxo::TransactionInput;
+    use sp_core::H512;
+
+    use super::*;
+
+    fn output(value: Value, asset: AssetId) -> TransactionOutput {
+        TransactionOutput {
+            value,
+            pubkey: H256::repeat_byte(1),
+            asset,
+            nft: None,
+        }
+    }
+
+    fn spending(outpoints: &[u64], outputs: Vec<TransactionOutput>) -> Transaction {
+        Transaction {
+            inputs: outpoints
+                .iter()
+                .map(|outpoint| TransactionInput {
+                    outpoint: H256::from_low_u64_be(*outpoint),
+                    sigscript: H512::zero(),
+                })
+                .collect(),
+            outputs,
+        }
+    }
+
+    #[test]
+    fn fee_is_the_native_value_left_by_the_outputs() {
+        let asset = H256::repeat_byte(9);
+        let prevouts = BTreeMap::from([
+            (H256::from_low_u64_be(1), output(100, NATIVE_ASSET)),
+            (H256::from_low_u64_be(2), output(50, NATIVE_ASSET)),
+            (H256::from_low_u64_be(3), output(1_000, asset)),
+        ]);
+        let transaction = spending(
+            &[1, 2, 3],
+            vec![output(120, NATIVE_ASSET), output(1_000, asset)],
+        );
+
+        assert_eq!(fee(&transaction, &prevouts), Some(30));
+    }
+
+    #[test]
+    fn fee_is_unknown_while_an_outpoint_is_missing() {
+        let prevouts = BTreeMap::from([(H256::from_low_u64_be(1), output(100, NATIVE_ASSET))]);
+        let transaction = spending(&[1, 2], vec![output(10, NATIVE_ASSET)]);
+
+        assert_eq!(fee(&transaction, &prevouts), None);
+    }
+}
@@ -0,0 +1,314 @@
+//! A wallet run by the node itself, holding its keys in the node keystore.
+//!
+//! Every method is unsafe: they hand out keys and spend funds of the node operator, so they
+//! are only reachable when unsafe RPCs are allowed.
+
+use std::{
+    collections::BTreeSet,
+    sync::{Arc, Mutex},
+};
+
+use academy_pow_runtime::{
+    address::{self, Address},
+    opaque::Block,
+    utxo::{self, UtxoApi as UtxoRuntimeApi, Value, KEY_TYPE, NATIVE_ASSET},
+    wallet::{LargestFirst, TransactionBuilder, Utxo},
+    Hash, RuntimeCall, UncheckedExtrinsic,
+};
+use futures::StreamExt;
+use jsonrpsee::{
+    core::{async_trait, RpcResult},
+    proc_macros::rpc,
+    types::{error::ErrorObject, ErrorObjectOwned},
+};
+use sc_client_api::BlockchainEvents;
+use sc_rpc_api::DenyUnsafe;
+use sc_transaction_pool_api::{TransactionPool, TransactionSource};
+use sp_api::ProvideRuntimeApi;
+use sp_blockchain::HeaderBackend;
+use sp_core::{sr25519, H256, H512};
+use sp_keystore::KeystorePtr;
+
+/// Node wallet RPC methods.
+#[rpc(client, server)]
+pub trait WalletApi {
+    /// Generate a key in the node keystore and return its address
+    #[method(name = "wallet_getNewAddress")]
+    fn get_new_address(&self) -> RpcResult<String>;
+
+    /// Native value of the outputs owned by the node keystore, minus those spent by
+    /// transactions the wallet has sent
+    #[method(name = "wallet_getBalance")]
+    fn get_balance(&self) -> RpcResult<Value>;
+
+    /// Outputs owned by the node keystore as of the last imported best block, minus those
+    /// spent by transactions the wallet has sent
+    #[method(name = "wallet_listUnspent")]
+    fn list_unspent(&self) -> RpcResult<Vec<Utxo>>;
+
+    /// Pay `value` to `address` at `fee_rate`, returning the hash of the transaction
+    #[method(name = "wallet_sendTo")]
+    async fn send_to(&self, address: String, value: Value, fee_rate: Value) -> RpcResult<Hash>;
+}
+
+/// Outputs owned by the node keystore, shared between the tracking task and the RPC.
+#[derive(Default)]
+pub struct WalletState {
+    utxos: Mutex<Vec<Utxo>>,
+    pending: Mutex<BTreeSet<H256>>,
+}
+
+impl WalletState {
+    /// Owned outputs not in `pending`, spent by a transaction the wallet has sent
+    fn spendable(&self, pending: &BTreeSet<H256>) -> Vec<Utxo> {
+        self.utxos
+            .lock()
+            .expect("wallet lock poisoned")
+            .iter()
+            .filter(|utxo| !pending.contains(&utxo.outpoint))
+            .cloned()
+            .collect()
+    }
+
+    /// Owned outputs not spent by a transaction the wallet has sent
+    fn unspent(&self) -> Vec<Utxo> {
+        self.spendable(&self.pending.lock().expect("wallet lock poisoned"))
+    }
+
+    fn refresh<C>(&self, client: &C, keystore: &KeystorePtr, at: Hash)
+    where
+        C: ProvideRuntimeApi<Block>,
+        C::Api: UtxoRuntimeApi<Block>,
+    {
+        let api = client.runtime_api();
+        let mut utxos = Vec::new();
+        for public in keystore.sr25519_public_keys(KEY_TYPE) {
+            match api.utxos_of(at, H256::from(public)) {
+                Ok(owned) => utxos.extend(
+                    owned
+                        .into_iter()
+                        .map(|(outpoint, output)| Utxo { outpoint, output }),
+                ),
+                Err(e) => log::warn!("Failed to fetch the UTXOs of {:?}: {}", public, e),
+            }
+        }
+
+        // Outpoints gone from the UTXO set are no longer pending, they are spent
+        self.pending
+            .lock()
+            .expect("wallet lock poisoned")
+            .retain(|outpoint| utxos.iter().any(|utxo| utxo.outpoint == *outpoint));
+        *self.utxos.lock().expect("wallet lock poisoned") = utxos;
+    }
+}
+
+/// Keep `state` up to date with the outputs owned by `keystore`, re-reading them from the
+/// state of every new best block. This covers reorgs, as the state is the one of the new
+/// best chain.
+pub async fn track_owned_outputs<C>(client: Arc<C>, keystore: KeystorePtr, state: Arc<WalletState>)
+where
+    C: ProvideRuntimeApi<Block> + HeaderBackend<Block> + BlockchainEvents<Block>,
+    C::Api: UtxoRuntimeApi<Block>,
+{
+    let mut imports = client.import_notification_stream();
+    state.refresh(&*client, &keystore, client.info().best_hash);
+
+    while let Some(notification) = imports.next().await {
+        if notification.is_new_best {
+            state.refresh(&*client, &keystore, notification.hash);
+        }
+    }
+}
+
+/// Provides the node wallet RPC methods.
+pub struct Wallet<C, P> {
+    client: Arc<C>,
+    pool: Arc<P>,
+    keystore: KeystorePtr,
+    state: Arc<WalletState>,
+    address_hrp: String,
+    deny_unsafe: DenyUnsafe,
+}
+
+impl<C, P> Wallet<C, P> {
+    /// Creates a new instance of the Wallet Rpc helper.
+    pub fn new(
+        client: Arc<C>,
+        pool: Arc<P>,
+        keystore: KeystorePtr,
+        state: Arc<WalletState>,
+        address_hrp: String,
+        deny_unsafe: DenyUnsafe,
+    ) -> Self {
+        Self {
+            client,
+            pool,
+            keystore,
+            state,
+            address_hrp,
+            deny_unsafe,
+        }
+    }
+}
+
+/// Error type of this RPC api.
+pub enum Error {
+    /// The keystore failed.
+    Keystore,
+    /// The address is malformed or belongs to another chain.
+    InvalidAddress,
+    /// The transaction couldn't be built or signed.
+    Wallet,
+    /// The transaction pool rejected the transaction.
+    Pool,
+}
+
+impl From<Error> for i32 {
+    fn from(e: Error) -> i32 {
+        match e {
+            Error::Keystore => 1,
+            Error::InvalidAddress => 2,
+            Error::Wallet => 3,
+            Error::Pool => 4,
+        }
+    }
+}
+
+fn error(code: Error, message: &str, data: impl ToString) -> ErrorObjectOwned {
+    ErrorObject::owned(code.into(), message, Some(data.to_string()))
+}
+
+impl<C, P> Wallet<C, P> {
+    fn new_key(&self) -> RpcResult<H256> {
+        self.keystore
+            .sr25519_generate_new(KEY_TYPE, None)
+            .map(H256::from)
+            .map_err(|e| error(Error::Keystore, "Unable to generate a key.", e))
+    }
+}
+
+#[async_trait]
+impl<C, P> WalletApiServer for Wallet<C, P>
+where
+    C: HeaderBackend<Block> + Send + Sync + 'static,
+    P: TransactionPool<Block = Block, Hash = Hash> + 'static,
+{
+    fn get_new_address(&self) -> RpcResult<String> {
+        self.deny_unsafe.check_if_safe()?;
+
+        Address::Pubkey(self.new_key()?)
+            .to_string_with_hrp(&self.address_hrp)
+            .map_err(|e| error(Error::InvalidAddress, "Unable to format the address.", e))
+    }
+
+    fn get_balance(&self) -> RpcResult<Value> {
+        self.deny_unsafe.check_if_safe()?;
+
+        Ok(self
+            .state
+            .unspent()
+            .iter()
+            .filter(|utxo| utxo.output.asset == NATIVE_ASSET)
+            .map(|utxo| utxo.output.value)
+            .sum())
+    }
+
+    fn list_unspent(&self) -> RpcResult<Vec<Utxo>> {
+        self.deny_unsafe.check_if_safe()?;
+
+        Ok(self.state.unspent())
+    }
+
+    async fn send_to(&self, address: String, value: Value, fee_rate: Value) -> RpcResult<Hash> {
+        self.deny_unsafe.check_if_safe()?;
+
+        let pubkey = Address::parse_with_hrp(&address, &self.address_hrp)
+            .and_then(|parsed| parsed.pubkey().ok_or(address::Error::NotAPubkey))
+            .map_err(|e| error(Error::InvalidAddress, "Invalid address.", e))?;
+
+        // Coin selection and the reservation of the spent outputs happen under the same lock,
+        // so concurrent calls never pick the same outputs
+        let transaction = {
+            let mut pending = self.state.pending.lock().expect("wallet lock poisoned");
+            let unsigned = TransactionBuilder::new()
+                .coin_selection(LargestFirst)
+                .utxos(self.state.spendable(&pending))
+                .pay_to(pubkey, value)
+                .change_to(self.new_key()?)
+                .fee_rate(fee_rate)
+                .build()
+                .map_err(|e| error(Error::Wallet, "Unable to build the transaction.", e))?;
+            let transaction = unsigned
+                .sign_with(|owner, payload| {
+                    self.keystore
+                        .sr25519_sign(KEY_TYPE, &sr25519::Public::from_h256(*owner), payload)
+                        .ok()
+                        .flatten()
+                        .map(|signature| H512::from_slice(signature.as_ref()))
+                })
+                .map_err(|e| error(Error::Wallet, "Unable to sign the transaction.", e))?;
+            pending.extend(transaction.inputs.iter().map(|input| input.outpoint));
+            transaction
+        };
+
+        let spent: Vec<H256> = transaction
+            .inputs
+            .iter()
+            .map(|input| input.outpoint)
+            .collect();
+        let extrinsic =
+            UncheckedExtrinsic::new_unsigned(RuntimeCall::Utxo(utxo::Call::spend { transaction }));
+        let submitted = self
+            .pool
+            .submit_one(
+                self.client.info().best_hash,
+                TransactionSource::Local,
+                extrinsic.into(),
+            )
+            .await;
+
+        let hash = submitted.map_err(|e| {
+            // Release the outputs the pool refused to spend
+            self.state
+                .pending
+                .lock()
+                .expect("wallet lock poisoned")
+                .retain(|outpoint| !spent.contains(outpoint));
+            super::utxo::pool_error(Error::Pool.into(), e)
+        })?;
+
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use academy_pow_runtime::utxo::TransactionOutput;
+
+    use super::*;
+
+    fn utxo(outpoint: u64, value: Value) -> Utxo {
+        Utxo {
+            outpoint: H256::from_low_u64_be(outpoint),
+            output: TransactionOutput {
+                value,
+                pubkey: H256::repeat_byte(1),
+                asset: NATIVE_ASSET,
+                nft: None,
+            },
+        }
+    }
+
+    #[test]
+    fn outputs_spent_by_sent_transactions_are_not_listed() {
+        let state = WalletState::default();
+        *state.utxos.lock().unwrap() = vec![utxo(1, 10), utxo(2, 20)];
+        state
+            .pending
+            .lock()
+            .unwrap()
+            .insert(H256::from_low_u64_be(1));
+
+        assert_eq!(state.unspent(), vec![utxo(2, 20)]);
+    }
+}
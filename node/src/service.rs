@@ -1,20 +1,28 @@
 //! Service and ServiceFactory implementation. Specialized wrapper over substrate service.
 
 use core::clone::Clone;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use academy_pow_runtime::{self, opaque::Block, PreDigest, RuntimeApi};
-use multi_pow::{ForkingConfig, MultiPow, SupportedHashes};
+use academy_pow_runtime::{self, address::DEVNET_HRP, opaque::Block, PreDigest, RuntimeApi};
+use multi_pow::{AlgoId, ForkingConfig, MultiPow};
 use parity_scale_codec::Encode;
-use sc_consensus::LongestChain;
-use sc_service::{error::Error as ServiceError, Configuration, PartialComponents, TaskManager};
+use sc_consensus::{BlockImportParams, LongestChain};
+use sc_consensus_manual_seal::consensus::ConsensusDataProvider;
+use sc_service::{
+    config::RpcMethods, error::Error as ServiceError, Configuration, PartialComponents, TaskManager,
+};
 use sc_telemetry::{Telemetry, TelemetryWorker};
+use sp_consensus_pow::POW_ENGINE_ID;
 use sp_core::sr25519;
+use sp_inherents::InherentData;
+use sp_runtime::{generic::Digest, traits::Block as BlockT, DigestItem};
+
+use crate::chain_spec::ForkingExtensions;
 
 pub(crate) type FullClient = sc_service::TFullClient<
-	Block,
-	RuntimeApi,
-	sc_executor::WasmExecutor<sp_io::SubstrateHostFunctions>,
+    Block,
+    RuntimeApi,
+    sc_executor::WasmExecutor<sp_io::SubstrateHostFunctions>,
 >;
 type FullBackend = sc_service::TFullBackend<Block>;
 type FullSelectChain = sc_consensus::LongestChain<FullBackend, Block>;
@@ -37,6 +45,7 @@ pub type Service = PartialComponents<
 pub fn new_partial(
     config: &Configuration,
     fork_config: ForkingConfig,
+    max_future_drift: Duration,
 ) -> Result<Service, ServiceError> {
     let telemetry = config
         .telemetry_endpoints
@@ -57,6 +66,9 @@ pub fn new_partial(
             executor,
         )?;
     let client = Arc::new(client);
+    let min_difficulty_blocks = ForkingExtensions::min_difficulty_blocks(&*config.chain_spec);
+    let hash_params =
+        ForkingExtensions::hash_params(&*config.chain_spec).map_err(ServiceError::Other)?;
 
     let telemetry = telemetry.map(|(worker, telemetry)| {
         task_manager
@@ -78,7 +90,12 @@ pub fn new_partial(
     let block_import = sc_consensus_pow::PowBlockImport::new(
         client.clone(),
         client.clone(),
-        MultiPow::new(client.clone(), fork_config),
+        MultiPow::new(
+            client.clone(),
+            fork_config,
+            min_difficulty_blocks,
+            hash_params,
+        ),
         0, // check inherents starting at block 0
         select_chain.clone(),
         move |_, ()| async move {
@@ -93,11 +110,26 @@ pub fn new_partial(
             Ok((timestamp, author))
         },
     );
+    let block_import = crate::difficulty::TimedDifficulty::new(
+        block_import,
+        MultiPow::new(
+            client.clone(),
+            fork_config,
+            min_difficulty_blocks,
+            hash_params,
+        ),
+    );
+    let block_import = crate::timestamp::FutureDriftCheck::new(block_import, max_future_drift);
 
     let import_queue = sc_consensus_pow::import_queue(
         Box::new(block_import.clone()),
         None,
-        MultiPow::new(client.clone(), fork_config),
+        MultiPow::new(
+            client.clone(),
+            fork_config,
+            min_difficulty_blocks,
+            hash_params,
+        ),
         &task_manager.spawn_essential_handle(),
         config.prometheus_registry(),
     )?;
@@ -114,6 +146,45 @@ pub fn new_partial(
     })
 }
 
+/// Puts the pre-runtime digest of the miner in blocks sealed instantly, as the runtime reads
+/// the mining algorithm of every block from it.
+struct InstantSealDigest(Vec<u8>);
+
+impl ConsensusDataProvider<Block> for InstantSealDigest {
+    type Proof = ();
+
+    fn create_digest(
+        &self,
+        _parent: &<Block as BlockT>::Header,
+        _inherents: &InherentData,
+    ) -> Result<Digest, sc_consensus_manual_seal::Error> {
+        Ok(Digest {
+            logs: vec![DigestItem::PreRuntime(POW_ENGINE_ID, self.0.clone())],
+        })
+    }
+
+    fn append_block_import(
+        &self,
+        _parent: &<Block as BlockT>::Header,
+        _params: &mut BlockImportParams<Block>,
+        _inherents: &InherentData,
+        _proof: Self::Proof,
+    ) -> Result<(), sc_consensus_manual_seal::Error> {
+        Ok(())
+    }
+}
+
+/// What a full node keeps track of for UTXO transactions, besides the UTXO set
+#[derive(Debug, Clone, Copy)]
+pub struct UtxoOptions {
+    /// Index UTXO transactions by txid
+    pub txindex: bool,
+    /// Index the outputs received and spent by each owner
+    pub addrindex: bool,
+    /// Maximum number of future transactions waiting for the same missing outpoint
+    pub max_orphans_per_outpoint: usize,
+}
+
 /// Builds a new service for a full client.
 pub fn new_full<
     N: sc_network::NetworkBackend<Block, <Block as sp_runtime::traits::Block>::Hash>,
@@ -122,7 +193,9 @@ pub fn new_full<
     fork_config: ForkingConfig,
     sr25519_public_key: sr25519::Public,
     instant_seal: bool,
-    mining_algo: SupportedHashes,
+    mining_algo: AlgoId,
+    utxo_options: UtxoOptions,
+    max_future_drift: Duration,
 ) -> Result<TaskManager, ServiceError> {
     let sc_service::PartialComponents {
         client,
@@ -133,14 +206,21 @@ pub fn new_full<
         select_chain,
         transaction_pool,
         other: (pow_block_import, mut telemetry),
-    } = new_partial(&config, fork_config)?;
+    } = new_partial(&config, fork_config, max_future_drift)?;
+    let transaction_pool = Arc::new(crate::orphans::OrphanCappedPool::new(
+        transaction_pool,
+        utxo_options.max_orphans_per_outpoint,
+    ));
+    let min_difficulty_blocks = ForkingExtensions::min_difficulty_blocks(&*config.chain_spec);
+    let hash_params =
+        ForkingExtensions::hash_params(&*config.chain_spec).map_err(ServiceError::Other)?;
 
     let net_config = sc_network::config::FullNetworkConfiguration::<
         Block,
         <Block as sp_runtime::traits::Block>::Hash,
         N,
     >::new(&config.network);
-	let metrics = sc_network::NotificationMetrics::new(None);
+    let metrics = sc_network::NotificationMetrics::new(None);
 
     let (network, system_rpc_tx, tx_handler_controller, network_starter, sync_service) =
         sc_service::build_network(sc_service::BuildNetworkParams {
@@ -153,21 +233,65 @@ pub fn new_full<
             block_announce_validator_builder: None,
             warp_sync_params: None,
             block_relay: None,
-            metrics: metrics,
+            metrics,
         })?;
 
     let role = config.role.clone();
     let prometheus_registry = config.prometheus_registry().cloned();
 
+    let wallet = Arc::new(crate::rpc::wallet::WalletState::default());
+    // The node wallet methods are unsafe, don't track its outputs if they are never served
+    if !matches!(config.rpc_methods, RpcMethods::Safe) {
+        task_manager.spawn_handle().spawn(
+            "utxo-wallet",
+            None,
+            crate::rpc::wallet::track_owned_outputs(
+                client.clone(),
+                keystore_container.keystore(),
+                wallet.clone(),
+            ),
+        );
+    }
+    if utxo_options.txindex {
+        task_manager.spawn_handle().spawn(
+            "utxo-txindex",
+            None,
+            crate::txindex::run(client.clone()),
+        );
+    }
+    let addrindex = utxo_options.addrindex.then(|| {
+        let index = Arc::new(crate::addrindex::AddressIndex::default());
+        task_manager.spawn_handle().spawn(
+            "utxo-addrindex",
+            None,
+            crate::addrindex::run::<_, FullBackend>(client.clone(), index.clone()),
+        );
+        index
+    });
+
+    let address_hrp = config
+        .chain_spec
+        .properties()
+        .get("utxoAddressHrp")
+        .and_then(|hrp| hrp.as_str())
+        .unwrap_or(DEVNET_HRP)
+        .to_string();
+
     let rpc_extensions_builder = {
         let client = client.clone();
         let pool = transaction_pool.clone();
+        let keystore = keystore_container.keystore();
 
         Box::new(move |deny_unsafe, _| {
             let deps = crate::rpc::FullDeps {
                 client: client.clone(),
                 pool: pool.clone(),
                 deny_unsafe,
+                keystore: keystore.clone(),
+                wallet: wallet.clone(),
+                address_hrp: address_hrp.clone(),
+                utxo_txindex: utxo_options.txindex,
+                utxo_addrindex: addrindex.clone(),
             };
             crate::rpc::create_full(deps).map_err(Into::into)
         })
@@ -205,7 +329,9 @@ pub fn new_full<
                 client,
                 pool: transaction_pool,
                 select_chain,
-                consensus_data_provider: None,
+                consensus_data_provider: Some(Box::new(InstantSealDigest(
+                    PreDigest::from((sr25519_public_key.into(), mining_algo)).encode(),
+                ))),
                 create_inherent_data_providers: move |_, ()| async move {
                     Ok(sp_timestamp::InherentDataProvider::from_system_time())
                 },
@@ -223,7 +349,7 @@ pub fn new_full<
                 Box::new(pow_block_import),
                 client.clone(),
                 select_chain,
-                MultiPow::new(client, fork_config),
+                MultiPow::new(client, fork_config, min_difficulty_blocks, hash_params),
                 proposer,
                 sync_service.clone(),
                 sync_service,
@@ -234,7 +360,7 @@ pub fn new_full<
                 Some(PreDigest::from((sr25519_public_key.into(), mining_algo)).encode()),
                 // This code is copied from above. Would be better to not repeat it.
                 move |_, ()| async move {
-                    let timestamp = sp_timestamp::InherentDataProvider::from_system_time();            
+                    let timestamp = sp_timestamp::InherentDataProvider::from_system_time();
                     // set default `author` following miner specified in CLI
                     let author = academy_pow_runtime::block_author::InherentDataProvider(
                         sr25519_public_key.encode(),
@@ -256,6 +382,9 @@ pub fn new_full<
             //TODO Some of this should move into the multi_pow crate.
             use multi_pow::{multi_hash_meets_difficulty, Compute};
             use sp_core::U256;
+            let algorithm = mining_algo
+                .algorithm()
+                .expect("The command line only accepts registered algorithms");
             let mut nonce: U256 = U256::from(0);
             std::thread::spawn(move || loop {
                 let worker = mining_worker.clone();
@@ -266,7 +395,7 @@ pub fn new_full<
                         pre_hash: metadata.pre_hash,
                         nonce,
                     };
-                    let seal = compute.compute(mining_algo);
+                    let seal = compute.compute(algorithm, &hash_params);
                     if multi_hash_meets_difficulty(&seal.work, seal.difficulty) {
                         nonce = U256::from(0);
                         let _ = futures::executor::block_on(worker.submit(seal.encode()));
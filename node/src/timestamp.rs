@@ -0,0 +1,78 @@
+//! The future drift rule for block timestamps.
+//!
+//! The timestamp pallet makes every timestamp exceed the previous one by its minimum period,
+//! and its inherent check refuses timestamps more than 30 seconds ahead of the clock of the
+//! importing node. Blocks whose timestamp is more than a tighter drift ahead of the node's
+//! clock are refused here, before they are imported. Such blocks can still be imported once
+//! the clock has caught up with them.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use academy_pow_runtime::{opaque::Block, RuntimeCall, TimestampCall, UncheckedExtrinsic};
+use parity_scale_codec::{Decode, Encode};
+use sc_consensus::{BlockCheckParams, BlockImport, BlockImportParams, ImportResult};
+use sp_consensus::Error as ConsensusError;
+use sp_runtime::OpaqueExtrinsic;
+
+/// The timestamp set by `extrinsic`, in milliseconds, if it is the timestamp inherent
+pub(crate) fn timestamp(extrinsic: &OpaqueExtrinsic) -> Option<u64> {
+    let extrinsic = UncheckedExtrinsic::decode(&mut &extrinsic.encode()[..]).ok()?;
+    match extrinsic.function {
+        RuntimeCall::Timestamp(TimestampCall::set { now }) => Some(now),
+        _ => None,
+    }
+}
+
+/// Block import refusing blocks whose timestamp is more than `max_drift` ahead of the node's
+/// clock, and passing the others on to `inner`
+#[derive(Clone)]
+pub struct FutureDriftCheck<I> {
+    inner: I,
+    max_drift: Duration,
+}
+
+impl<I> FutureDriftCheck<I> {
+    pub fn new(inner: I, max_drift: Duration) -> Self {
+        Self { inner, max_drift }
+    }
+}
+
+#[async_trait::async_trait]
+impl<I> BlockImport<Block> for FutureDriftCheck<I>
+where
+    I: BlockImport<Block, Error = ConsensusError> + Send + Sync,
+{
+    type Error = ConsensusError;
+
+    async fn check_block(
+        &self,
+        block: BlockCheckParams<Block>,
+    ) -> Result<ImportResult, Self::Error> {
+        self.inner.check_block(block).await
+    }
+
+    async fn import_block(
+        &mut self,
+        block: BlockImportParams<Block>,
+    ) -> Result<ImportResult, Self::Error> {
+        let block_time = block
+            .body
+            .as_ref()
+            .and_then(|body| body.iter().find_map(timestamp));
+
+        if let Some(block_time) = block_time {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let ahead = Duration::from_millis(block_time).saturating_sub(now);
+            if ahead > self.max_drift {
+                return Err(ConsensusError::ClientImport(format!(
+                    "Block timestamp is {:?} ahead of the local clock, more than the allowed {:?}",
+                    ahead, self.max_drift
+                )));
+            }
+        }
+
+        self.inner.import_block(block).await
+    }
+}
@@ -0,0 +1,192 @@
+//! Index of UTXO transactions by txid, enabled with `--utxo-txindex`.
+//!
+//! Entries map a txid to the block including the transaction and its extrinsic index. They
+//! live in the aux DB of the client and follow the canonical chain: when the best block
+//! changes, the transactions of retracted blocks are removed and those of enacted blocks
+//! are added. The last indexed block is recorded too, so the index catches up on restart.
+
+use std::sync::Arc;
+
+use academy_pow_runtime::{
+    opaque::Block,
+    utxo::{self, Transaction},
+    Hash, RuntimeCall, UncheckedExtrinsic,
+};
+use futures::StreamExt;
+use parity_scale_codec::{Decode, Encode};
+use sc_client_api::{AuxStore, BlockBackend, BlockchainEvents};
+use sp_blockchain::{HeaderBackend, HeaderMetadata};
+use sp_runtime::OpaqueExtrinsic;
+
+const TX_PREFIX: &[u8] = b"utxo_txindex:tx:";
+const TIP_KEY: &[u8] = b"utxo_txindex:tip";
+
+/// Where a transaction was included
+#[derive(Encode, Decode, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxLocation {
+    /// Hash of the including block
+    pub block_hash: Hash,
+    /// Index of the extrinsic in the block body
+    pub extrinsic_index: u32,
+}
+
+/// The UTXO transaction carried by an extrinsic, if any
+pub fn utxo_transaction(extrinsic: &OpaqueExtrinsic) -> Option<Transaction> {
+    let extrinsic = UncheckedExtrinsic::decode(&mut &extrinsic.encode()[..]).ok()?;
+    match extrinsic.function {
+        RuntimeCall::Utxo(utxo::Call::spend { transaction })
+        | RuntimeCall::Utxo(utxo::Call::issue { transaction, .. }) => Some(transaction),
+        _ => None,
+    }
+}
+
+fn tx_key(txid: &Hash) -> Vec<u8> {
+    [TX_PREFIX, txid.as_bytes()].concat()
+}
+
+/// Where the transaction `txid` was included on the canonical chain, as far as it is indexed
+pub fn lookup<C: AuxStore>(client: &C, txid: &Hash) -> sp_blockchain::Result<Option<TxLocation>> {
+    Ok(client
+        .get_aux(&tx_key(txid))?
+        .and_then(|location| TxLocation::decode(&mut &location[..]).ok()))
+}
+
+/// The UTXO transactions of a block, with their txid and extrinsic index
+fn block_transactions<C>(client: &C, hash: Hash) -> sp_blockchain::Result<Vec<(Hash, TxLocation)>>
+where
+    C: BlockBackend<Block>,
+{
+    let body = client.block_body(hash)?.unwrap_or_default();
+    Ok(body
+        .iter()
+        .enumerate()
+        .filter_map(|(index, extrinsic)| {
+            utxo_transaction(extrinsic).map(|transaction| {
+                (
+                    utxo::txid(&transaction),
+                    TxLocation {
+                        block_hash: hash,
+                        extrinsic_index: index as u32,
+                    },
+                )
+            })
+        })
+        .collect())
+}
+
+fn index_block<C>(client: &C, hash: Hash) -> sp_blockchain::Result<()>
+where
+    C: BlockBackend<Block> + AuxStore,
+{
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = block_transactions(client, hash)?
+        .into_iter()
+        .map(|(txid, location)| (tx_key(&txid), location.encode()))
+        .chain(std::iter::once((TIP_KEY.to_vec(), hash.encode())))
+        .collect();
+    let insert: Vec<(&[u8], &[u8])> = entries
+        .iter()
+        .map(|(key, value)| (&key[..], &value[..]))
+        .collect();
+
+    client.insert_aux(&insert, &[])
+}
+
+fn deindex_block<C>(client: &C, hash: Hash, parent: Hash) -> sp_blockchain::Result<()>
+where
+    C: BlockBackend<Block> + AuxStore,
+{
+    let keys: Vec<Vec<u8>> = block_transactions(client, hash)?
+        .into_iter()
+        .map(|(txid, _)| tx_key(&txid))
+        .collect();
+    let delete: Vec<&[u8]> = keys.iter().map(|key| &key[..]).collect();
+
+    client.insert_aux(&[(TIP_KEY, &parent.encode()[..])], &delete)
+}
+
+/// Bring an index whose last indexed block is stored under `tip_key` to `best`, following
+/// the canonical chain: blocks retracted by a reorg are removed with `deindex_block`, given
+/// the block and its parent, then the enacted ones are added with `index_block`. Both must
+/// move the tip. An index without a tip is built from genesis.
+pub fn follow_best_chain<C>(
+    client: &C,
+    tip_key: &[u8],
+    best: Hash,
+    mut index_block: impl FnMut(Hash) -> sp_blockchain::Result<()>,
+    mut deindex_block: impl FnMut(Hash, Hash) -> sp_blockchain::Result<()>,
+) -> sp_blockchain::Result<()>
+where
+    C: HeaderBackend<Block> + HeaderMetadata<Block, Error = sp_blockchain::Error> + AuxStore,
+{
+    let tip = client
+        .get_aux(tip_key)?
+        .and_then(|tip| Hash::decode(&mut &tip[..]).ok());
+
+    match tip {
+        Some(tip) => {
+            let route = sp_blockchain::tree_route(client, tip, best)?;
+            for retracted in route.retracted() {
+                let parent = client.header_metadata(retracted.hash)?.parent;
+                deindex_block(retracted.hash, parent)?;
+            }
+            for enacted in route.enacted() {
+                index_block(enacted.hash)?;
+            }
+        }
+        None => {
+            let best_number = client
+                .number(best)?
+                .ok_or_else(|| sp_blockchain::Error::UnknownBlock(format!("{:?}", best)))?;
+            for number in 0..=best_number {
+                if let Some(hash) = client.hash(number)? {
+                    index_block(hash)?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn update<C>(client: &C, best: Hash) -> sp_blockchain::Result<()>
+where
+    C: HeaderBackend<Block>
+        + HeaderMetadata<Block, Error = sp_blockchain::Error>
+        + BlockBackend<Block>
+        + AuxStore,
+{
+    follow_best_chain(
+        client,
+        TIP_KEY,
+        best,
+        |hash| index_block(client, hash),
+        |hash, parent| deindex_block(client, hash, parent),
+    )
+}
+
+/// Keep the index in line with the canonical chain until the client shuts down
+pub async fn run<C>(client: Arc<C>)
+where
+    C: HeaderBackend<Block>
+        + HeaderMetadata<Block, Error = sp_blockchain::Error>
+        + BlockBackend<Block>
+        + BlockchainEvents<Block>
+        + AuxStore,
+{
+    let mut imports = client.import_notification_stream();
+    if let Err(e) = update(&*client, client.info().best_hash) {
+        log::error!("Failed to catch up the UTXO transaction index: {}", e);
+    }
+
+    while let Some(notification) = imports.next().await {
+        if notification.is_new_best {
+            if let Err(e) = update(&*client, notification.hash) {
+                log::error!(
+                    "Failed to index UTXO transactions of block {:?}: {}",
+                    notification.hash,
+                    e
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,623 @@
+//! Implementation of the `wallet` subcommand.
+//!
+//! Queries and submissions go through the RPC of a running node, building and signing
+//! work offline on JSON files so keys never have to touch an online machine. HD wallets
+//! are kept in a JSON file holding the account public key and the next key indices, the
+//! seed is only needed to sign.
+
+use std::{fs, path::Path};
+
+use academy_pow_runtime::{
+    address::Address,
+    pst::Pst,
+    utxo::{self, Transaction, KEY_TYPE},
+    wallet::{
+        BranchAndBound, HdWallet, KeyChain, LargestFirst, RandomSelection, TransactionBuilder,
+        UnsignedTransaction, Utxo,
+    },
+    Hash, RuntimeCall, UncheckedExtrinsic,
+};
+use jsonrpsee::http_client::{HttpClient, HttpClientBuilder};
+use parity_scale_codec::Encode;
+use sc_keystore::LocalKeystore;
+use sc_rpc_api::author::AuthorApiClient;
+use sp_core::{
+    crypto::{Pair as _, SecretString},
+    sr25519, Bytes, H256, H512,
+};
+use sp_keystore::Keystore;
+
+use crate::{
+    cli::{CoinSelectionKind, RpcArgs, WalletCmd, WalletPstCmd},
+    rpc::utxo::UtxoApiClient,
+};
+
+/// Hard junction appended to the seed to get the account of an HD wallet
+const ACCOUNT_JUNCTION: &str = "//utxo";
+
+/// Errors of the wallet subcommand, boxed as [`sc_cli::Error`] is too large to return
+/// from every helper
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+type Result<T> = std::result::Result<T, Error>;
+
+impl WalletCmd {
+    /// Run the wallet subcommand
+    pub fn run(&self) -> Result<()> {
+        sc_cli::build_runtime()?.block_on(async {
+            match self {
+                WalletCmd::Init(cmd) => {
+                    if cmd.wallet_file.exists() {
+                        return Err(format!(
+                            "Wallet file {} already exists",
+                            cmd.wallet_file.display()
+                        )
+                        .into());
+                    }
+                    let account = account_pair(&cmd.suri)?;
+                    let wallet = HdWallet::new(account.public().into(), cmd.gap_limit);
+                    save_wallet(&cmd.wallet_file, &wallet)?;
+                    println!("{:?}", wallet.account);
+                }
+                WalletCmd::NewAddress(cmd) => {
+                    let mut wallet = load_wallet(&cmd.wallet_file)?;
+                    let public = if cmd.change {
+                        wallet.next_change()
+                    } else {
+                        wallet.next_receive()
+                    };
+                    save_wallet(&cmd.wallet_file, &wallet)?;
+                    let address = Address::Pubkey(public)
+                        .to_string_with_hrp(&cmd.address_hrp)
+                        .map_err(application_error)?;
+                    println!("{}", address);
+                }
+                WalletCmd::Scan(cmd) => {
+                    let client = connect(&cmd.rpc)?;
+                    let mut wallet = load_wallet(&cmd.wallet_file)?;
+                    scan(&client, &mut wallet).await?;
+                    save_wallet(&cmd.wallet_file, &wallet)?;
+                    println!(
+                        "receive keys: {}, change keys: {}",
+                        wallet.next_receive, wallet.next_change
+                    );
+                }
+                WalletCmd::Balance(cmd) => {
+                    let client = connect(&cmd.rpc)?;
+                    let mut balance = 0;
+                    for owner in owners(cmd.owner, cmd.wallet_file.as_deref())? {
+                        balance += UtxoApiClient::<Hash>::balance(&client, owner, None, None)
+                            .await
+                            .map_err(rpc_error)?;
+                    }
+                    println!("{}", balance);
+                }
+                WalletCmd::ListUtxos(cmd) => {
+                    let client = connect(&cmd.rpc)?;
+                    let utxos =
+                        list_unspent(&client, owners(cmd.owner, cmd.wallet_file.as_deref())?)
+                            .await?;
+                    println!("{}", to_json(&utxos)?);
+                }
+                WalletCmd::Build(cmd) => {
+                    let client = connect(&cmd.rpc)?;
+                    let utxos =
+                        list_unspent(&client, owners(cmd.from, cmd.wallet_file.as_deref())?)
+                            .await?;
+                    let change = match (cmd.change, cmd.from, &cmd.wallet_file) {
+                        (Some(change), _, _) | (None, Some(change), _) => H256::from(change),
+                        (None, None, Some(path)) => {
+                            let mut wallet = load_wallet(path)?;
+                            let change = wallet.next_change();
+                            save_wallet(path, &wallet)?;
+                            change
+                        }
+                        (None, None, None) => {
+                            return Err("Either --from or --wallet-file is required".into())
+                        }
+                    };
+
+                    let builder = cmd.payments.iter().fold(
+                        TransactionBuilder::new()
+                            .utxos(utxos)
+                            .change_to(change)
+                            .fee_rate(cmd.fee_rate),
+                        |builder, (pubkey, value)| builder.pay_to(H256::from(*pubkey), *value),
+                    );
+                    let unsigned = match cmd.coin_selection {
+                        CoinSelectionKind::LargestFirst => {
+                            builder.coin_selection(LargestFirst).build()
+                        }
+                        CoinSelectionKind::BranchAndBound => {
+                            builder.coin_selection(BranchAndBound::default()).build()
+                        }
+                        CoinSelectionKind::Random => {
+                            builder.coin_selection(RandomSelection::default()).build()
+                        }
+                    }
+                    .map_err(application_error)?;
+                    println!("{}", to_json(&unsigned)?);
+                }
+                WalletCmd::Sign(cmd) => {
+                    let unsigned: UnsignedTransaction = from_json_file(&cmd.file)?;
+                    let transaction = match (&cmd.suri, &cmd.keystore_path) {
+                        (Some(suri), _) if cmd.wallet_file.is_some() => {
+                            let wallet = load_wallet(cmd.wallet_file.as_deref().unwrap())?;
+                            let account = account_pair(suri)?;
+                            if H256::from(account.public()) != wallet.account {
+                                return Err("Seed doesn't match the wallet account".into());
+                            }
+                            unsigned.sign(&wallet.pairs(&account))
+                        }
+                        (Some(suri), _) => {
+                            let pair = sr25519::Pair::from_string(suri, None)
+                                .map_err(|e| format!("Invalid secret URI: {:?}", e))?;
+                            unsigned.sign(&[pair])
+                        }
+                        (None, Some(path)) => {
+                            let keystore = open_keystore(path, &cmd.password)?;
+                            unsigned.sign_with(|owner, payload| {
+                                keystore_sign(&keystore, owner, payload)
+                            })
+                        }
+                        (None, None) => {
+                            return Err("Either --suri or --keystore-path is required".into())
+                        }
+                    }
+                    .map_err(application_error)?;
+                    println!("{}", to_json(&transaction)?);
+                }
+                WalletCmd::Send(cmd) => {
+                    let transaction: Transaction = from_json_file(&cmd.file)?;
+                    let extrinsic =
+                        UncheckedExtrinsic::new_unsigned(RuntimeCall::Utxo(utxo::Call::spend {
+                            transaction,
+                        }));
+                    let client = connect(&cmd.rpc)?;
+                    let hash = AuthorApiClient::<Hash, Hash>::submit_extrinsic(
+                        &client,
+                        Bytes(extrinsic.encode()),
+                    )
+                    .await
+                    .map_err(rpc_error)?;
+                    println!("{:?}", hash);
+                }
+                WalletCmd::Pst(cmd) => cmd.run()?,
+            }
+
+            Ok(())
+        })
+    }
+}
+
+impl WalletPstCmd {
+    /// Run the wallet pst subcommand
+    pub fn run(&self) -> Result<()> {
+        match self {
+            WalletPstCmd::Create(cmd) => {
+                let unsigned: UnsignedTransaction = from_json_file(&cmd.file)?;
+                let mut pst = Pst::from(unsigned);
+                if let Some(path) = &cmd.wallet_file {
+                    pst.add_derivation_hints(&load_wallet(path)?);
+                }
+                println!("{}", pst.to_hex());
+            }
+            WalletPstCmd::Sign(cmd) => {
+                let mut pst = load_pst(&cmd.pst)?;
+                let signed = match (&cmd.suri, &cmd.keystore_path) {
+                    (Some(suri), _) => {
+                        let pair = sr25519::Pair::from_string(suri, None)
+                            .map_err(|e| format!("Invalid secret URI: {:?}", e))?;
+                        pst.sign(&[pair]) + pst.sign_with_account(&account_pair(suri)?)
+                    }
+                    (None, Some(path)) => {
+                        let keystore = open_keystore(path, &cmd.password)?;
+                        pst.sign_with(|owner, payload| keystore_sign(&keystore, owner, payload))
+                    }
+                    (None, None) => {
+                        return Err("Either --suri or --keystore-path is required".into())
+                    }
+                };
+                if signed == 0 {
+                    return Err("None of the inputs is owned by the given keys".into());
+                }
+                println!("{}", pst.to_hex());
+            }
+            WalletPstCmd::Combine(cmd) => {
+                let mut psts = cmd.psts.iter();
+                let mut combined = load_pst(psts.next().expect("at least one PST is required"))?;
+                for path in psts {
+                    combined
+                        .combine(load_pst(path)?)
+                        .map_err(application_error)?;
+                }
+                println!("{}", combined.to_hex());
+            }
+            WalletPstCmd::Finalize(cmd) => {
+                let mut pst = load_pst(&cmd.pst)?;
+                pst.finalize().map_err(application_error)?;
+                println!("{}", pst.to_hex());
+            }
+            WalletPstCmd::Extract(cmd) => {
+                let transaction = load_pst(&cmd.pst)?.extract().map_err(application_error)?;
+                println!("{}", to_json(&transaction)?);
+            }
+            WalletPstCmd::Decode(cmd) => {
+                println!("{}", to_json(&load_pst(&cmd.pst)?)?);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn load_pst(path: &Path) -> Result<Pst> {
+    Pst::from_hex(&fs::read_to_string(path)?).map_err(application_error)
+}
+
+fn open_keystore(path: &Path, password: &Option<String>) -> Result<LocalKeystore> {
+    Ok(LocalKeystore::open(
+        path,
+        password.clone().map(SecretString::new),
+    )?)
+}
+
+/// Signature of `owner` over `payload`, if the keystore holds its key
+fn keystore_sign(keystore: &LocalKeystore, owner: &H256, payload: &[u8]) -> Option<H512> {
+    keystore
+        .sr25519_sign(KEY_TYPE, &sr25519::Public::from_h256(*owner), payload)
+        .ok()
+        .flatten()
+        .map(|signature| H512::from_slice(signature.as_ref()))
+}
+
+/// The account pair of the HD wallet of `seed`
+fn account_pair(seed: &str) -> Result<sr25519::Pair> {
+    sr25519::Pair::from_string(&format!("{}{}", seed, ACCOUNT_JUNCTION), None)
+        .map_err(|e| format!("Invalid secret URI: {:?}", e).into())
+}
+
+fn load_wallet(path: &Path) -> Result<HdWallet> {
+    from_json_file(path)
+}
+
+fn save_wallet(path: &Path, wallet: &HdWallet) -> Result<()> {
+    fs::write(path, to_json(wallet)?)?;
+    Ok(())
+}
+
+/// The single `owner`, or every key handed out by the wallet at `wallet_file`
+fn owners(owner: Option<sr25519::Public>, wallet_file: Option<&Path>) -> Result<Vec<H256>> {
+    match (owner, wallet_file) {
+        (Some(owner), _) => Ok(vec![owner.into()]),
+        (None, Some(path)) => Ok(load_wallet(path)?
+            .keys()
+            .into_iter()
+            .map(|(_, _, public)| public)
+            .collect()),
+        (None, None) => Err("Either an owner or a wallet file is required".into()),
+    }
+}
+
+/// Mark the keys of `wallet` that were ever used, extending each chain until a full gap of
+/// keys was never used
+async fn scan(client: &HttpClient, wallet: &mut HdWallet) -> Result<()> {
+    for chain in [KeyChain::Receive, KeyChain::Change] {
+        loop {
+            let mut found = false;
+            for (index, public) in wallet.lookahead(chain) {
+                if used(client, public).await? {
+                    wallet.mark_used(chain, index);
+                    found = true;
+                }
+            }
+            if !found {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Whether `owner` ever received an output. Keys spent empty only show in the address index,
+/// genesis outputs only in the UTXO set.
+async fn used(client: &HttpClient, owner: H256) -> Result<bool> {
+    let history = UtxoApiClient::<Hash>::address_history(client, owner)
+        .await
+        .map_err(|e| {
+            format!(
+                "Scanning needs the address history, run the node with --utxo-addrindex: {}",
+                e
+            )
+        })?;
+    if !history.is_empty() {
+        return Ok(true);
+    }
+
+    let utxos = UtxoApiClient::<Hash>::list_unspent(client, owner, None)
+        .await
+        .map_err(rpc_error)?;
+    Ok(!utxos.is_empty())
+}
+
+async fn list_unspent(client: &HttpClient, owners: Vec<H256>) -> Result<Vec<Utxo>> {
+    let mut utxos = Vec::new();
+    for owner in owners {
+        utxos.extend(
+            UtxoApiClient::<Hash>::list_unspent(client, owner, None)
+                .await
+                .map_err(rpc_error)?,
+        );
+    }
+    Ok(utxos)
+}
+
+fn connect(rpc: &RpcArgs) -> Result<HttpClient> {
+    HttpClientBuilder::default()
+        .build(&rpc.url)
+        .map_err(rpc_error)
+}
+
+fn to_json(value: &impl serde::Serialize) -> Result<String> {
+    serde_json::to_string_pretty(value).map_err(application_error)
+}
+
+fn from_json_file<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T> {
+    let json = fs::read(path)?;
+    serde_json::from_slice(&json).map_err(application_error)
+}
+
+fn rpc_error(e: impl std::fmt::Display) -> Error {
+    format!("RPC request failed: {}", e).into()
+}
+
+fn application_error(e: impl std::error::Error + Send + Sync + 'static) -> Error {
+    Box::new(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, path::PathBuf, thread, time::Duration};
+
+    use academy_pow_runtime::utxo::Value;
+    use clap::Parser;
+    use multi_pow::{hashes::Md5, ForkingConfig, PowHash};
+    use sc_cli::SubstrateCli;
+    use sc_service::TaskManager;
+
+    use super::*;
+    use crate::cli::{Cli, Subcommand};
+
+    fn free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("a free port")
+            .port()
+    }
+
+    /// Run `academy-pow wallet <args>`
+    fn wallet(args: &[&str]) -> Result<()> {
+        let cli = Cli::parse_from(["academy-pow", "wallet"].iter().chain(args));
+        match cli.subcommand {
+            Some(Subcommand::Wallet(cmd)) => cmd.run(),
+            _ => unreachable!("parsed a wallet subcommand"),
+        }
+    }
+
+    /// Directory removed once dropped
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// An instant seal dev node in this process, sealing a block for every transaction
+    struct DevNode {
+        _task_manager: TaskManager,
+        runtime: tokio::runtime::Runtime,
+        url: String,
+        client: HttpClient,
+        // Dropped last, once the node stopped
+        _base_path: TempDir,
+    }
+
+    impl DevNode {
+        /// Starts a node, with the address index if `addrindex`. `None` without the runtime
+        /// wasm, which builds without SKIP_WASM_BUILD only.
+        fn start(addrindex: bool) -> Option<Self> {
+            if academy_pow_runtime::WASM_BINARY.is_none() {
+                eprintln!(
+                    "Skipped: the dev node needs the runtime wasm, built without SKIP_WASM_BUILD"
+                );
+                return None;
+            }
+
+            let rpc_port = free_port().to_string();
+            let p2p_port = free_port().to_string();
+            // `--tmp` makes one directory per process, the nodes of concurrent tests would
+            // share their database
+            let base_path = TempDir(std::env::temp_dir().join(format!(
+                "academy-pow-dev-node-{}-{}",
+                std::process::id(),
+                rpc_port
+            )));
+            let cli = Cli::parse_from([
+                "academy-pow",
+                "--dev",
+                "--base-path",
+                base_path.0.to_str().unwrap(),
+                "--instant-seal",
+                "--no-prometheus",
+                "--rpc-port",
+                &rpc_port,
+                "--port",
+                &p2p_port,
+            ]);
+            let runtime = sc_cli::build_runtime().unwrap();
+            let config = cli
+                .create_configuration(&cli.run, runtime.handle().clone())
+                .unwrap();
+            let alice = sr25519::Pair::from_string("//Alice", None).unwrap();
+            let task_manager = {
+                let _context = runtime.enter();
+                crate::service::new_full::<sc_network::Litep2pNetworkBackend>(
+                    config,
+                    ForkingConfig::Manual,
+                    alice.public(),
+                    true,
+                    Md5::ID,
+                    crate::service::UtxoOptions {
+                        txindex: false,
+                        addrindex,
+                        max_orphans_per_outpoint: 4,
+                    },
+                    Duration::from_secs(15),
+                )
+                .unwrap()
+            };
+            let url = format!("http://127.0.0.1:{}", rpc_port);
+            let client = connect(&RpcArgs { url: url.clone() }).unwrap();
+
+            Some(Self {
+                _task_manager: task_manager,
+                runtime,
+                url,
+                client,
+                _base_path: base_path,
+            })
+        }
+
+        fn list_unspent(&self, owner: H256) -> Vec<Utxo> {
+            self.runtime
+                .block_on(list_unspent(&self.client, vec![owner]))
+                .unwrap()
+        }
+
+        /// The unspent outputs of `owner` once there are `count` of them
+        fn wait_for_utxos(&self, owner: H256, count: usize) -> Vec<Utxo> {
+            let mut utxos = Vec::new();
+            for _ in 0..50 {
+                utxos = self.list_unspent(owner);
+                if utxos.len() == count {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(200));
+            }
+            utxos
+        }
+
+        fn submit(&self, transaction: Transaction) {
+            let extrinsic =
+                UncheckedExtrinsic::new_unsigned(RuntimeCall::Utxo(utxo::Call::spend {
+                    transaction,
+                }));
+            self.runtime
+                .block_on(AuthorApiClient::<Hash, Hash>::submit_extrinsic(
+                    &self.client,
+                    Bytes(extrinsic.encode()),
+                ))
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn wallet_moves_funds_on_a_dev_node() {
+        let Some(node) = DevNode::start(false) else {
+            return;
+        };
+        let url = node.url.as_str();
+
+        let alice = sr25519::Pair::from_string("//Alice", None).unwrap();
+        let alice_key = H256::from(alice.public());
+        let bob_key = H256::from(sr25519::Pair::from_string("//Bob", None).unwrap().public());
+        let alice_hex = format!("{:?}", alice_key);
+        wallet(&["balance", "--owner", &alice_hex, "--url", url]).unwrap();
+        wallet(&["list-utxos", "--owner", &alice_hex, "--url", url]).unwrap();
+
+        // Alice and Bob each hold one UTXO in the dev genesis
+        let genesis_value: Value = 1 << 50;
+        let funds = node.list_unspent(alice_key);
+        assert_eq!(funds.len(), 1);
+        assert_eq!(funds[0].output.value, genesis_value);
+
+        let transaction = TransactionBuilder::new()
+            .utxos(funds)
+            .pay_to(bob_key, 1_000)
+            .change_to(alice_key)
+            .build()
+            .unwrap()
+            .sign(&[alice])
+            .unwrap();
+        let file = std::env::temp_dir().join(format!(
+            "academy-pow-wallet-test-{}.json",
+            std::process::id()
+        ));
+        fs::write(&file, to_json(&transaction).unwrap()).unwrap();
+        let sent = wallet(&["send", "--file", file.to_str().unwrap(), "--url", url]);
+        let _ = fs::remove_file(&file);
+        sent.unwrap();
+
+        // The transaction is in the next sealed block
+        let received = node.wait_for_utxos(bob_key, 2);
+        let mut values: Vec<Value> = received.iter().map(|utxo| utxo.output.value).collect();
+        values.sort();
+        assert_eq!(values, vec![1_000, genesis_value]);
+
+        let change = node.list_unspent(alice_key);
+        assert_eq!(change.len(), 1);
+        assert!(change[0].output.value < genesis_value - 1_000);
+
+        // Keys spent empty are only found in the address index
+        let mut wallet = HdWallet::new(alice_key, 1);
+        assert!(node
+            .runtime
+            .block_on(scan(&node.client, &mut wallet))
+            .is_err());
+    }
+
+    #[test]
+    fn scan_finds_funds_behind_keys_spent_empty() {
+        let Some(node) = DevNode::start(true) else {
+            return;
+        };
+
+        let alice = sr25519::Pair::from_string("//Alice", None).unwrap();
+        let alice_key = H256::from(alice.public());
+        let account = account_pair("//Dave").unwrap();
+        let mut wallet = HdWallet::new(account.public().into(), 1);
+        let spent_empty = wallet.next_receive();
+        let funded = wallet.next_receive();
+
+        let paid = TransactionBuilder::new()
+            .utxos(node.list_unspent(alice_key))
+            .pay_to(spent_empty, 1_000_000)
+            .change_to(alice_key)
+            .build()
+            .unwrap()
+            .sign(&[alice])
+            .unwrap();
+        node.submit(paid);
+
+        // Everything the first key received moves on to the second one
+        let received = node.wait_for_utxos(spent_empty, 1);
+        let moved = TransactionBuilder::new()
+            .utxos(received)
+            .pay_to(funded, 100_000)
+            .change_to(funded)
+            .build()
+            .unwrap()
+            .sign(&wallet.pairs(&account))
+            .unwrap();
+        node.submit(moved);
+        assert_eq!(node.wait_for_utxos(funded, 2).len(), 2);
+        assert!(node.list_unspent(spent_empty).is_empty());
+
+        let mut restored = HdWallet::new(account.public().into(), 1);
+        node.runtime
+            .block_on(scan(&node.client, &mut restored))
+            .unwrap();
+        assert_eq!(restored.next_receive, 2);
+    }
+}
@@ -0,0 +1,396 @@
+//! Offline simulator of the difficulty adjustment.
+//!
+//! Drives the `difficulty` and `multi_algo` pallets in a mock runtime through a hashrate
+//! scenario, drawing the time to each block from the hashrate and difficulty of every mined
+//! algorithm, and prints one CSV line per block. The random draws are seeded, so a run can be
+//! repeated exactly while tuning the parameters.
+//!
+//! ```text
+//! cargo run -p academy-pow-runtime --example daa_sim -- --scenario hash-and-run --daa lwma > lwma.csv
+//! ```
+//!
+//! Options, with their defaults:
+//! - `--scenario step`: `step` multiplies the md5 hashrate by 10 for a while, `hash-and-run` adds
+//!   a miner with 4 times the md5 hashrate whenever md5 is cheap to mine and removes it when it
+//!   gets expensive, `algo-switch` moves the md5 miners to sha3 for a while
+//! - `--daa kulupu`: one of `kulupu`, `bitcoin`, `asert` or `lwma`
+//! - `--blocks 600`: number of blocks to mine
+//! - `--target-block-time 5000`: in milliseconds
+//! - `--damp-factor 3` and `--clamp-factor 2`: for `kulupu`
+//! - `--retarget-interval 2016`: for `bitcoin`
+//! - `--half-life 288`: for `asert`, in target block times
+//! - `--seed 1`
+
+use std::{env, process};
+
+use academy_pow_runtime::{
+    difficulty::{
+        self, Asert, AsertAnchor, BitcoinRetarget, Difficulty, DifficultyAdjustment,
+        DifficultyAndTimestamp, Kulupu, Lwma, RetargetPeriod,
+    },
+    multi_algo,
+};
+use frame_support::{
+    construct_runtime, derive_impl, parameter_types,
+    traits::{ConstU32, ConstU64, Hooks},
+};
+use multi_pow::{
+    hashes::{Keccak, Md5, Sha3},
+    AlgoId, PowHash,
+};
+use sp_core::U256;
+use sp_runtime::{BuildStorage, Perbill};
+
+const ALGOS: [AlgoId; 3] = [Md5::ID, Sha3::ID, Keccak::ID];
+
+/// Hashes per millisecond of the miners of each algorithm at the start of every scenario
+const BASE_HASHRATE: f64 = 1_000.0;
+
+/// Unix time of the first simulated block, in milliseconds
+const START: u64 = 1_700_000_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Daa {
+    Kulupu,
+    Bitcoin,
+    Asert,
+    Lwma,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scenario {
+    Step,
+    HashAndRun,
+    AlgoSwitch,
+}
+
+parameter_types! {
+    pub static SelectedDaa: Daa = Daa::Kulupu;
+    pub static TargetBlockTime: u128 = 5_000;
+    pub static DampFactor: u128 = 3;
+    pub static ClampFactor: u128 = 2;
+    pub static RetargetInterval: u32 = 2016;
+    pub static HalfLife: u128 = 288 * 5_000;
+    pub static MiningAlgo: AlgoId = Md5::ID;
+    pub const MaxDifficulty: u128 = u128::MAX;
+    pub const DominancePenalty: Perbill = Perbill::from_percent(5);
+    pub DefaultInitialDifficulty: U256 = U256::from(BASE_HASHRATE as u128);
+}
+
+/// Whichever algorithm `--daa` selects
+pub struct Selected;
+
+impl DifficultyAdjustment for Selected {
+    type State = (RetargetPeriod, Option<AsertAnchor>);
+
+    fn next_difficulty(
+        state: &mut Self::State,
+        history: &[DifficultyAndTimestamp<u128>],
+        target_block_time: u128,
+        initial_difficulty: Difficulty,
+    ) -> Difficulty {
+        match SelectedDaa::get() {
+            Daa::Kulupu => Kulupu::<DampFactor, ClampFactor, DampFactor>::next_difficulty(
+                &mut (),
+                history,
+                target_block_time,
+                initial_difficulty,
+            ),
+            Daa::Bitcoin => BitcoinRetarget::<RetargetInterval>::next_difficulty(
+                &mut state.0,
+                history,
+                target_block_time,
+                initial_difficulty,
+            ),
+            Daa::Asert => Asert::<HalfLife>::next_difficulty(
+                &mut state.1,
+                history,
+                target_block_time,
+                initial_difficulty,
+            ),
+            Daa::Lwma => {
+                Lwma::next_difficulty(&mut (), history, target_block_time, initial_difficulty)
+            }
+        }
+    }
+}
+
+type Block = frame_system::mocking::MockBlock<Test>;
+
+construct_runtime!(
+    pub enum Test {
+        System: frame_system,
+        Timestamp: pallet_timestamp,
+        MultiAlgo: multi_algo,
+        Difficulties: difficulty,
+    }
+);
+
+#[derive_impl(frame_system::config_preludes::TestDefaultConfig)]
+impl frame_system::Config for Test {
+    type Block = Block;
+}
+
+impl pallet_timestamp::Config for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = ConstU64<1>;
+    type WeightInfo = ();
+}
+
+impl multi_algo::Config for Test {
+    type RecentBlocks = ConstU32<60>;
+
+    fn mining_algo() -> AlgoId {
+        MiningAlgo::get()
+    }
+}
+
+type AlgoTargetBlockTime = multi_algo::ScaledTargetBlockTime<Test, TargetBlockTime>;
+
+impl difficulty::Config for Test {
+    type TimeProvider = Timestamp;
+    type TargetBlockTime = AlgoTargetBlockTime;
+    type DifficultyAdjustment = Selected;
+    type DifficultyWindow = ConstU32<60>;
+    type AlgoDominance = MultiAlgo;
+    type DominancePenalty = DominancePenalty;
+    type MaxDifficulty = MaxDifficulty;
+    type MinDifficulty = DampFactor;
+    type EmergencyMultiple = ConstU32<6>;
+    type DefaultInitialDifficulty = DefaultInitialDifficulty;
+
+    fn mining_algo() -> AlgoId {
+        MiningAlgo::get()
+    }
+}
+
+/// Deterministic xorshift generator, good enough for drawing block times
+struct Rng(u64);
+
+impl Rng {
+    /// Uniform in `[0, 1)`
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Exponentially distributed with the given mean
+    fn exponential(&mut self, mean: f64) -> f64 {
+        -(1.0 - self.next_f64()).ln() * mean
+    }
+}
+
+fn difficulty(algo: AlgoId) -> Difficulty {
+    Difficulties::difficulty(algo)
+}
+
+fn as_f64(difficulty: Difficulty) -> f64 {
+    difficulty.min(U256::from(u128::MAX)).as_u128() as f64
+}
+
+/// Hashrate mining each algorithm at `block`, in hashes per millisecond
+struct Hashrates {
+    scenario: Scenario,
+    /// Whether the hash-and-run miner is on md5
+    hopping: bool,
+}
+
+impl Hashrates {
+    fn at(&mut self, block: u32, target: f64) -> [f64; 3] {
+        let base = BASE_HASHRATE;
+        match self.scenario {
+            Scenario::Step => {
+                let md5 = if (200..400).contains(&block) {
+                    10.0 * base
+                } else {
+                    base
+                };
+                [md5, 0.0, 0.0]
+            }
+            // The hopper waits for the first block before looking at the difficulty
+            Scenario::HashAndRun if block == 0 => [base, 0.0, 0.0],
+            Scenario::HashAndRun => {
+                // The base miners alone are on target at a difficulty of `base * target`. The
+                // hopper mines when md5 is cheaper than that, and leaves when it is twice as
+                // expensive.
+                let cost = as_f64(difficulty(Md5::ID)) / (base * target);
+                if cost < 1.0 {
+                    self.hopping = true;
+                } else if cost > 2.0 {
+                    self.hopping = false;
+                }
+                let hopper = if self.hopping { 4.0 * base } else { 0.0 };
+                [base + hopper, 0.0, 0.0]
+            }
+            Scenario::AlgoSwitch => {
+                if (200..400).contains(&block) {
+                    [0.0, 2.0 * base, base]
+                } else {
+                    [base, base, base]
+                }
+            }
+        }
+    }
+}
+
+struct Options {
+    scenario: Scenario,
+    daa: Daa,
+    blocks: u32,
+    target_block_time: u128,
+    damp_factor: u128,
+    clamp_factor: u128,
+    retarget_interval: u32,
+    half_life: u128,
+    seed: u64,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options {
+        scenario: Scenario::Step,
+        daa: Daa::Kulupu,
+        blocks: 600,
+        target_block_time: 5_000,
+        damp_factor: 3,
+        clamp_factor: 2,
+        retarget_interval: 2016,
+        half_life: 288,
+        seed: 1,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("missing value for {}", flag))?;
+        let number = || {
+            value
+                .parse::<u64>()
+                .map_err(|e| format!("invalid value for {}: {}", flag, e))
+        };
+        match flag.as_str() {
+            "--scenario" => {
+                options.scenario = match value.as_str() {
+                    "step" => Scenario::Step,
+                    "hash-and-run" => Scenario::HashAndRun,
+                    "algo-switch" => Scenario::AlgoSwitch,
+                    other => return Err(format!("unknown scenario {}", other)),
+                }
+            }
+            "--daa" => {
+                options.daa = match value.as_str() {
+                    "kulupu" => Daa::Kulupu,
+                    "bitcoin" => Daa::Bitcoin,
+                    "asert" => Daa::Asert,
+                    "lwma" => Daa::Lwma,
+                    other => return Err(format!("unknown difficulty adjustment {}", other)),
+                }
+            }
+            "--blocks" => options.blocks = number()? as u32,
+            "--target-block-time" => options.target_block_time = number()?.into(),
+            "--damp-factor" => options.damp_factor = number()?.into(),
+            "--clamp-factor" => options.clamp_factor = number()?.into(),
+            "--retarget-interval" => options.retarget_interval = number()? as u32,
+            "--half-life" => options.half_life = number()?.into(),
+            "--seed" => options.seed = number()?.max(1),
+            other => return Err(format!("unknown option {}", other)),
+        }
+    }
+
+    Ok(options)
+}
+
+fn main() {
+    let options = parse_options().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+
+    SelectedDaa::set(options.daa);
+    TargetBlockTime::set(options.target_block_time);
+    DampFactor::set(options.damp_factor);
+    ClampFactor::set(options.clamp_factor);
+    RetargetInterval::set(options.retarget_interval);
+    HalfLife::set(options.half_life * options.target_block_time);
+
+    let mut hashrates = Hashrates {
+        scenario: options.scenario,
+        hopping: false,
+    };
+    let target = options.target_block_time as f64;
+    let initial = hashrates.at(0, target);
+    let active = initial.iter().filter(|h| **h > 0.0).count().max(1) as f64;
+
+    // Each algorithm starts on target for the hashrate it starts with, or for the base
+    // hashrate if it isn't mined yet
+    let initial_difficulty = |hashrate: f64| {
+        let hashrate = if hashrate > 0.0 {
+            hashrate
+        } else {
+            BASE_HASHRATE
+        };
+        let mut bytes = [0u8; 32];
+        U256::from((hashrate * target * active) as u128).to_little_endian(&mut bytes);
+        bytes
+    };
+    let mut genesis = RuntimeGenesisConfig::default();
+    let mut initial_difficulties = vec![[0u8; 32]; multi_pow::MAX_ALGOS];
+    for (algo, hashrate) in ALGOS.iter().zip(initial) {
+        initial_difficulties[algo.0 as usize] = initial_difficulty(hashrate);
+    }
+    genesis.difficulties.initial_difficulty = initial_difficulties;
+    let storage = genesis.build_storage().expect("the mock genesis is valid");
+
+    sp_io::TestExternalities::new(storage).execute_with(|| {
+        let mut rng = Rng(options.seed);
+        let mut now = START;
+        println!(
+            "block,timestamp,block_time,algo,md5_difficulty,sha3_difficulty,keccak_difficulty,\
+             md5_hashrate,sha3_hashrate,keccak_hashrate"
+        );
+
+        for block in 1..=options.blocks {
+            let rates = hashrates.at(block, target);
+
+            // Every algorithm races for the block, the first to find it wins
+            let Some((algo, block_time)) = ALGOS
+                .iter()
+                .zip(rates)
+                .filter(|(_, hashrate)| *hashrate > 0.0)
+                .map(|(algo, hashrate)| {
+                    let mean = as_f64(difficulty(*algo)) / hashrate;
+                    (*algo, rng.exponential(mean))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+            else {
+                eprintln!("no hashrate left at block {}", block);
+                break;
+            };
+            let block_time = (block_time.round() as u64).max(1);
+            now += block_time;
+
+            MiningAlgo::set(algo);
+            System::set_block_number(block.into());
+            pallet_timestamp::Now::<Test>::put(now);
+            MultiAlgo::on_initialize(block.into());
+            Difficulties::on_finalize(block.into());
+
+            println!(
+                "{},{},{},{},{},{},{},{},{},{}",
+                block,
+                now,
+                block_time,
+                algo,
+                difficulty(Md5::ID),
+                difficulty(Sha3::ID),
+                difficulty(Keccak::ID),
+                rates[0],
+                rates[1],
+                rates[2],
+            );
+        }
+    });
+}
@@ -0,0 +1,165 @@
+//! Checksummed, human-readable addresses for UTXO locking conditions.
+//!
+//! An address is the bech32m encoding of one byte naming the kind of locking condition
+//! followed by 32 bytes of payload, under a human-readable part (HRP) naming the chain, e.g.
+//! `apow1q...`. The checksum catches mistyped characters, so a typo fails to parse instead
+//! of locking funds to a key nobody holds.
+//!
+//! Only available with the `std` feature.
+
+use std::{fmt, str::FromStr};
+
+use bech32::{primitives::decode::CheckedHrpstring, Bech32m, Hrp};
+use serde::{de, Deserialize, Deserializer};
+use sp_core::H256;
+use sp_runtime::traits::{BlakeTwo256, Hash};
+
+/// HRP of production chains
+pub const MAINNET_HRP: &str = "apow";
+/// HRP of test networks
+pub const TESTNET_HRP: &str = "tapow";
+/// HRP of development chains
+pub const DEVNET_HRP: &str = "dapow";
+
+const PUBKEY: u8 = 0;
+const MULTISIG: u8 = 1;
+const SCRIPT_HASH: u8 = 2;
+
+/// A locking condition of a UTXO
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Address {
+    /// Locked to a single sr25519 public key. This is what `TransactionOutput::pubkey` holds.
+    Pubkey(H256),
+    /// Locked to a threshold of sr25519 public keys, committed to by `Address::multisig`
+    Multisig(H256),
+    /// Locked to a script, committed to by the hash of the script
+    ScriptHash(H256),
+}
+
+/// Errors that can occur when parsing an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Not a bech32m string, or the checksum doesn't match
+    Encoding(String),
+    /// The address belongs to another chain
+    WrongHrp { expected: String, found: String },
+    /// The payload doesn't have the length of a kind byte and a 32-byte hash
+    InvalidLength(usize),
+    /// The kind byte doesn't name a locking condition
+    UnknownKind(u8),
+    /// A public key was expected but the address holds another locking condition
+    NotAPubkey,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Encoding(e) => write!(f, "invalid address encoding: {e}"),
+            Error::WrongHrp { expected, found } => {
+                write!(f, "address is for chain {found}, expected {expected}")
+            }
+            Error::InvalidLength(len) => write!(f, "invalid address payload length {len}"),
+            Error::UnknownKind(kind) => write!(f, "unknown address kind {kind}"),
+            Error::NotAPubkey => write!(f, "address is not locked to a single public key"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Address {
+    /// Address of a `threshold`-of-`keys` multisig. The order of the keys doesn't matter.
+    pub fn multisig(threshold: u32, keys: &[H256]) -> Self {
+        let mut keys = keys.to_vec();
+        keys.sort();
+        Address::Multisig(BlakeTwo256::hash_of(&(b"multisig", threshold, keys)))
+    }
+
+    /// Address of a script
+    pub fn script_hash(script: &[u8]) -> Self {
+        Address::ScriptHash(BlakeTwo256::hash(script))
+    }
+
+    /// The public key, if this address is locked to one
+    pub fn pubkey(&self) -> Option<H256> {
+        match self {
+            Address::Pubkey(pubkey) => Some(*pubkey),
+            _ => None,
+        }
+    }
+
+    fn kind_and_payload(&self) -> (u8, &H256) {
+        match self {
+            Address::Pubkey(hash) => (PUBKEY, hash),
+            Address::Multisig(hash) => (MULTISIG, hash),
+            Address::ScriptHash(hash) => (SCRIPT_HASH, hash),
+        }
+    }
+
+    /// Encode the address for the chain using `hrp`
+    pub fn to_string_with_hrp(&self, hrp: &str) -> Result<String, Error> {
+        let hrp = Hrp::parse(hrp).map_err(|e| Error::Encoding(e.to_string()))?;
+        let (kind, payload) = self.kind_and_payload();
+        let mut data = Vec::with_capacity(33);
+        data.push(kind);
+        data.extend_from_slice(payload.as_bytes());
+
+        bech32::encode::<Bech32m>(hrp, &data).map_err(|e| Error::Encoding(e.to_string()))
+    }
+
+    /// Decode an address of any chain, returning its HRP along with it
+    pub fn decode(s: &str) -> Result<(String, Address), Error> {
+        let checked =
+            CheckedHrpstring::new::<Bech32m>(s).map_err(|e| Error::Encoding(e.to_string()))?;
+        let data: Vec<u8> = checked.byte_iter().collect();
+        if data.len() != 33 {
+            return Err(Error::InvalidLength(data.len()));
+        }
+        let hash = H256::from_slice(&data[1..]);
+        let address = match data[0] {
+            PUBKEY => Address::Pubkey(hash),
+            MULTISIG => Address::Multisig(hash),
+            SCRIPT_HASH => Address::ScriptHash(hash),
+            kind => return Err(Error::UnknownKind(kind)),
+        };
+
+        Ok((checked.hrp().to_lowercase(), address))
+    }
+
+    /// Decode an address, checking it belongs to the chain using `hrp`
+    pub fn parse_with_hrp(s: &str, hrp: &str) -> Result<Address, Error> {
+        let (found, address) = Self::decode(s)?;
+        if found != hrp {
+            return Err(Error::WrongHrp {
+                expected: hrp.into(),
+                found,
+            });
+        }
+
+        Ok(address)
+    }
+}
+
+impl FromStr for Address {
+    type Err = Error;
+
+    /// Decode an address of any chain. Use `parse_with_hrp` to reject other chains.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::decode(s).map(|(_, address)| address)
+    }
+}
+
+/// Parse a public key given either as an address or as raw `0x`-prefixed hex
+pub fn parse_pubkey(s: &str) -> Result<H256, Error> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        return H256::from_str(hex).map_err(|e| Error::Encoding(e.to_string()));
+    }
+
+    s.parse::<Address>()?.pubkey().ok_or(Error::NotAPubkey)
+}
+
+/// Serde `deserialize_with` helper accepting a public key as an address or as raw hex
+pub fn deserialize_pubkey<'de, D: Deserializer<'de>>(deserializer: D) -> Result<H256, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_pubkey(&s).map_err(de::Error::custom)
+}
@@ -1,17 +1,35 @@
 //! A difficulty adjustment algorithm (DAA) to keep the block time close to a particular goal
-//! Cribbed from Kulupu https://github.com/kulupu/kulupu/blob/master/runtime/src/difficulty.rs
 //!
-//! It is possible to implement other DAAs such as that of BTC and BCH. This would be an interesting
-//! and worth-while experiment. The DAAs should be abstracted away with a trait.
+//! The pallet keeps a window of past difficulties and timestamps and hands it to a
+//! [`DifficultyAdjustment`] selected through its `Config`. Implementations are provided for:
+//! - [`Kulupu`], cribbed from https://github.com/kulupu/kulupu/blob/master/runtime/src/difficulty.rs
+//! - [`BitcoinRetarget`], Bitcoin's retarget every 2016 blocks
+//! - [`Asert`], Bitcoin Cash's absolutely scheduled exponentially rising targets (aserti3-2d)
+//! - [`Lwma`], Zawy's linearly weighted moving average (LWMA-1)
+//!
+//! All of them work on difficulties rather than targets, so where the reference algorithm
+//! multiplies a target by some factor, these divide the difficulty by it.
 //! Some ideas: https://papers.ssrn.com/sol3/papers.cfm?abstract_id=3410460
 
-use core::cmp::{max, min};
+use core::{
+    cmp::{max, min},
+    marker::PhantomData,
+};
 
-use frame_support::traits::Time;
-use parity_scale_codec::{Decode, Encode, MaxEncodedLen};
+use frame_support::{
+    pallet_prelude::{Get, MaxEncodedLen, Member},
+    traits::Time,
+    Parameter,
+};
+use multi_pow::{AlgoId, ALGORITHMS};
+use parity_scale_codec::{Decode, Encode};
 use scale_info::TypeInfo;
 use sp_core::U256;
-use sp_runtime::traits::UniqueSaturatedInto;
+use sp_runtime::{
+    traits::{Convert, SaturatedConversion, Saturating, UniqueSaturatedInto},
+    PerThing, Perbill,
+};
+use sp_std::vec::Vec;
 
 #[derive(Encode, Decode, Clone, Copy, Eq, PartialEq, Debug, MaxEncodedLen, TypeInfo)]
 pub struct DifficultyAndTimestamp<M> {
@@ -29,11 +47,395 @@ pub fn clamp(actual: u128, goal: u128, clamp_factor: u128) -> u128 {
     max(goal / clamp_factor, min(actual, goal * clamp_factor))
 }
 
-const DIFFICULTY_ADJUST_WINDOW: u128 = 60;
-type Difficulty = U256;
+pub type Difficulty = U256;
+
+/// The difficulty after `elapsed` time without a block, halved once for every full `span` of
+/// it and never below `min_difficulty`, or `difficulty` itself if that is lower already
+pub fn emergency_decay(
+    difficulty: Difficulty,
+    elapsed: u128,
+    span: u128,
+    min_difficulty: Difficulty,
+) -> Difficulty {
+    let halvings = elapsed.checked_div(span).unwrap_or(0);
+    if halvings == 0 || difficulty <= min_difficulty {
+        return difficulty;
+    }
+
+    max(min_difficulty, difficulty >> halvings.min(256) as usize)
+}
+
+/// An algorithm computing the difficulty of the next block from the recent ones.
+pub trait DifficultyAdjustment {
+    /// Whatever the algorithm needs to remember between blocks, beyond the window
+    type State: Parameter + Member + Default + MaxEncodedLen;
+
+    /// The difficulty of the next block.
+    ///
+    /// `history` holds the past difficulties and timestamps, from earliest to latest, and ends
+    /// with the block being finalized. Timestamps are in the same unit as `target_block_time`.
+    /// An empty `history` gives `initial_difficulty` back, leaving the state alone.
+    /// The result is still bounded by the pallet's `MinDifficulty` and `MaxDifficulty`.
+    fn next_difficulty(
+        state: &mut Self::State,
+        history: &[DifficultyAndTimestamp<u128>],
+        target_block_time: u128,
+        initial_difficulty: Difficulty,
+    ) -> Difficulty;
+}
+
+/// The damp/clamp algorithm of Kulupu.
+///
+/// Compares the time taken by the window with the time it should have taken, moves that
+/// toward the goal by the damp factor and limits it to the clamp factor, then scales the
+/// average difficulty of the window by it. `MinDifficulty` bounds the sum of the window, which
+/// avoids getting stuck when trying to increase a tiny difficulty subject to dampening.
+///
+/// As in Kulupu, the window of `n` blocks measures `n - 1` block times against `n` targets, so
+/// blocks right on target still raise the difficulty slightly.
+///
+/// ```
+/// use academy_pow_runtime::difficulty::{DifficultyAdjustment, DifficultyAndTimestamp, Kulupu};
+/// use frame_support::traits::ConstU128;
+/// use sp_core::U256;
+///
+/// type Daa = Kulupu<ConstU128<3>, ConstU128<2>, ConstU128<3>>;
+/// let initial = U256::from(1_000);
+/// let window = |block_time: u128| -> Vec<_> {
+///     (0..60)
+///         .map(|i| DifficultyAndTimestamp { difficulty: U256::from(1_000), timestamp: i * block_time })
+///         .collect()
+/// };
+///
+/// assert_eq!(Daa::next_difficulty(&mut (), &window(5_000), 5_000, initial), U256::from(1_005));
+/// assert_eq!(Daa::next_difficulty(&mut (), &window(10_000), 5_000, initial), U256::from(756));
+/// // Clamped to half the difficulty
+/// assert_eq!(Daa::next_difficulty(&mut (), &window(50_000), 5_000, initial), U256::from(500));
+/// // Nothing to adjust without a block
+/// assert_eq!(Daa::next_difficulty(&mut (), &[], 5_000, initial), initial);
+/// ```
+pub struct Kulupu<DampFactor, ClampFactor, MinDifficulty>(
+    PhantomData<(DampFactor, ClampFactor, MinDifficulty)>,
+);
+
+impl<DampFactor, ClampFactor, MinDifficulty> DifficultyAdjustment
+    for Kulupu<DampFactor, ClampFactor, MinDifficulty>
+where
+    DampFactor: Get<u128>,
+    ClampFactor: Get<u128>,
+    MinDifficulty: Get<u128>,
+{
+    type State = ();
+
+    fn next_difficulty(
+        _state: &mut (),
+        history: &[DifficultyAndTimestamp<u128>],
+        target_block_time: u128,
+        initial_difficulty: Difficulty,
+    ) -> Difficulty {
+        if history.is_empty() {
+            return initial_difficulty;
+        }
+
+        let ts_delta = history
+            .windows(2)
+            .map(|pair| pair[1].timestamp.saturating_sub(pair[0].timestamp))
+            .sum::<u128>()
+            .max(1);
+
+        let diff_sum = history
+            .iter()
+            .fold(U256::zero(), |sum, item| sum + item.difficulty)
+            .max(U256::from(MinDifficulty::get()));
+
+        // Calculate the average length of the adjustment window
+        let adjustment_window = history.len() as u128 * target_block_time;
+
+        // adjust time delta toward goal subject to dampening and clamping
+        let adj_ts = clamp(
+            damp(ts_delta, adjustment_window, DampFactor::get()),
+            adjustment_window,
+            ClampFactor::get(),
+        );
+
+        diff_sum * U256::from(target_block_time) / U256::from(adj_ts)
+    }
+}
+
+/// Progress through the current retarget period of [`BitcoinRetarget`]
+#[derive(Encode, Decode, Clone, Copy, Eq, PartialEq, Debug, Default, MaxEncodedLen, TypeInfo)]
+pub struct RetargetPeriod {
+    /// Blocks finalized so far in the period
+    pub blocks: u32,
+    /// Timestamp of the first block of the period
+    pub start: u128,
+}
+
+/// Bitcoin's retarget, which keeps the difficulty for `Interval` blocks (2016 on Bitcoin) and
+/// then scales it by the ratio between the time the period should have taken and the time it
+/// took, limited to a factor of 4 either way.
+///
+/// Like Bitcoin, the period is measured from its first block to its last, so it spans one
+/// block time less than it is compared against.
+///
+/// The vectors below use the timestamps of Bitcoin Core's `pow_tests`, with the 10 minute
+/// target in seconds.
+///
+/// ```
+/// use academy_pow_runtime::difficulty::{
+///     BitcoinRetarget, DifficultyAdjustment, DifficultyAndTimestamp, RetargetPeriod,
+/// };
+/// use frame_support::traits::ConstU32;
+/// use sp_core::U256;
+///
+/// type Daa = BitcoinRetarget<ConstU32<2016>>;
+/// let initial = U256::from(1_000_000_000u64);
+/// let last_block = |start, timestamp| {
+///     let mut period = RetargetPeriod { blocks: 2015, start };
+///     let block = DifficultyAndTimestamp { difficulty: U256::from(1_000_000_000u64), timestamp };
+///     let next = Daa::next_difficulty(&mut period, &[block], 600, initial);
+///     assert_eq!(period.blocks, 0);
+///     next
+/// };
+///
+/// // get_next_work
+/// assert_eq!(last_block(1261130161, 1262152739), U256::from(1_182_892_649u64));
+/// // get_next_work_lower_limit_actual: the difficulty at most quadruples
+/// assert_eq!(last_block(1279008237, 1279297671), U256::from(4_000_000_000u64));
+/// // get_next_work_upper_limit_actual: the difficulty at most quarters
+/// assert_eq!(last_block(1263163443, 1269211443), U256::from(250_000_000u64));
+///
+/// // Within a period the difficulty doesn't change
+/// let mut period = RetargetPeriod::default();
+/// let block = DifficultyAndTimestamp { difficulty: U256::from(7), timestamp: 1231006505 };
+/// assert_eq!(Daa::next_difficulty(&mut period, &[block], 600, initial), U256::from(7));
+/// assert_eq!(period, RetargetPeriod { blocks: 1, start: 1231006505 });
+///
+/// // Without a block the period doesn't move either
+/// assert_eq!(Daa::next_difficulty(&mut period, &[], 600, initial), initial);
+/// assert_eq!(period, RetargetPeriod { blocks: 1, start: 1231006505 });
+/// ```
+pub struct BitcoinRetarget<Interval>(PhantomData<Interval>);
+
+impl<Interval: Get<u32>> DifficultyAdjustment for BitcoinRetarget<Interval> {
+    type State = RetargetPeriod;
+
+    fn next_difficulty(
+        period: &mut RetargetPeriod,
+        history: &[DifficultyAndTimestamp<u128>],
+        target_block_time: u128,
+        initial_difficulty: Difficulty,
+    ) -> Difficulty {
+        let Some(latest) = history.last() else {
+            return initial_difficulty;
+        };
+
+        if period.blocks == 0 {
+            period.start = latest.timestamp;
+        }
+        period.blocks += 1;
+        if period.blocks < Interval::get() {
+            return latest.difficulty;
+        }
+
+        let target_timespan = Interval::get() as u128 * target_block_time;
+        let actual_timespan = latest
+            .timestamp
+            .saturating_sub(period.start)
+            .clamp(target_timespan / 4, target_timespan * 4);
+        *period = RetargetPeriod::default();
+
+        latest
+            .difficulty
+            .saturating_mul(U256::from(target_timespan))
+            / U256::from(actual_timespan.max(1))
+    }
+}
+
+/// The block that [`Asert`] schedules the following blocks from
+#[derive(Encode, Decode, Clone, Copy, Eq, PartialEq, Debug, MaxEncodedLen, TypeInfo)]
+pub struct AsertAnchor {
+    /// Blocks finalized since the anchor
+    pub height: u64,
+    /// Timestamp of the anchor
+    pub timestamp: u128,
+    /// Difficulty of the anchor
+    pub difficulty: Difficulty,
+}
+
+/// Bitcoin Cash's ASERT (aserti3-2d), which halves the difficulty of the anchor block for
+/// every `HalfLife` the chain is behind its ideal schedule, and doubles it for every
+/// `HalfLife` it is ahead. The first block finalized becomes the anchor.
+///
+/// `2^x` is approximated with the same fixed point cubic as the reference implementation.
+///
+/// ```
+/// use academy_pow_runtime::difficulty::{
+///     Asert, AsertAnchor, DifficultyAdjustment, DifficultyAndTimestamp,
+/// };
+/// use frame_support::traits::ConstU128;
+/// use sp_core::U256;
+///
+/// // Two days, as on Bitcoin Cash, with the 10 minute target in seconds.
+/// type Daa = Asert<ConstU128<172_800>>;
+/// let initial = U256::from(1_000_000);
+/// let anchor = AsertAnchor { height: 0, timestamp: 1_000_000, difficulty: U256::from(1_000_000) };
+/// let next_block = |delay: i128| {
+///     let timestamp = (1_000_000 + 600 + delay) as u128;
+///     let block = DifficultyAndTimestamp { difficulty: U256::from(1), timestamp };
+///     Daa::next_difficulty(&mut Some(anchor), &[block], 600, initial)
+/// };
+///
+/// assert_eq!(next_block(0), U256::from(1_000_000));
+/// assert_eq!(next_block(172_800), U256::from(500_000));
+/// assert_eq!(next_block(-172_800), U256::from(2_000_000));
+/// assert_eq!(next_block(86_400), U256::from(707_046));
+/// assert_eq!(next_block(-86_400), U256::from(1_414_093));
+///
+/// // The first block becomes the anchor
+/// let mut state = None;
+/// let block = DifficultyAndTimestamp { difficulty: U256::from(42), timestamp: 5 };
+/// assert_eq!(Daa::next_difficulty(&mut state, &[block], 600, initial), U256::from(42));
+/// assert_eq!(state, Some(AsertAnchor { height: 0, timestamp: 5, difficulty: U256::from(42) }));
+///
+/// // No block, no anchor
+/// let mut state = None;
+/// assert_eq!(Daa::next_difficulty(&mut state, &[], 600, initial), initial);
+/// assert_eq!(state, None);
+/// ```
+pub struct Asert<HalfLife>(PhantomData<HalfLife>);
+
+impl<HalfLife: Get<u128>> DifficultyAdjustment for Asert<HalfLife> {
+    type State = Option<AsertAnchor>;
+
+    fn next_difficulty(
+        anchor: &mut Option<AsertAnchor>,
+        history: &[DifficultyAndTimestamp<u128>],
+        target_block_time: u128,
+        initial_difficulty: Difficulty,
+    ) -> Difficulty {
+        let Some(latest) = history.last() else {
+            return initial_difficulty;
+        };
+
+        let anchor = match anchor {
+            Some(anchor) => {
+                anchor.height += 1;
+                anchor
+            }
+            None => anchor.insert(AsertAnchor {
+                height: 0,
+                timestamp: latest.timestamp,
+                difficulty: latest.difficulty,
+            }),
+        };
+
+        // How far ahead of schedule the chain is, in 16.16 fixed point half lives
+        let ideal_delta = target_block_time.saturating_mul(anchor.height as u128) as i128;
+        let time_delta = latest.timestamp as i128 - anchor.timestamp as i128;
+        let exponent = (ideal_delta - time_delta).saturating_mul(65_536) / HalfLife::get() as i128;
+
+        let shifts = exponent >> 16;
+        let frac = (exponent - (shifts << 16)) as u128;
+        let factor = ((195_766_423_245_049 * frac
+            + 971_821_376 * frac.pow(2)
+            + 5_127 * frac.pow(3)
+            + (1 << 47))
+            >> 48)
+            + 65_536;
+
+        let difficulty = anchor.difficulty.saturating_mul(U256::from(factor));
+        let difficulty = if shifts >= 0 {
+            if difficulty.bits() as i128 + shifts > 256 {
+                U256::max_value()
+            } else {
+                difficulty << shifts as usize
+            }
+        } else if -shifts >= 256 {
+            U256::zero()
+        } else {
+            difficulty >> (-shifts) as usize
+        };
+
+        difficulty >> 16
+    }
+}
+
+/// Zawy's LWMA-1, which takes the average difficulty of the window and scales it by how the
+/// block times compare to the target, weighting recent blocks linearly more than older ones.
+///
+/// Out of order timestamps count as one unit, and block times are capped at 6 targets, so a
+/// few bad timestamps can't move the difficulty much. As in the reference, the result is
+/// lowered by 1% to offset the bias of the average.
+///
+/// ```
+/// use academy_pow_runtime::difficulty::{DifficultyAdjustment, DifficultyAndTimestamp, Lwma};
+/// use sp_core::U256;
+///
+/// let initial = U256::from(1_000_000);
+/// let window = |block_time: u128| -> Vec<_> {
+///     (0..61)
+///         .map(|i| DifficultyAndTimestamp { difficulty: U256::from(1_000_000), timestamp: i * block_time })
+///         .collect()
+/// };
+///
+/// assert_eq!(Lwma::next_difficulty(&mut (), &window(600), 600, initial), U256::from(990_000));
+/// assert_eq!(Lwma::next_difficulty(&mut (), &window(1_200), 600, initial), U256::from(495_000));
+/// // Block times are capped at 6 targets
+/// assert_eq!(Lwma::next_difficulty(&mut (), &window(6_000), 600, initial), U256::from(165_000));
+/// // Identical timestamps count as one second each, and the weighted times are floored
+/// assert_eq!(Lwma::next_difficulty(&mut (), &window(0), 600, initial), U256::from(10_065_000));
+/// ```
+pub struct Lwma;
+
+impl DifficultyAdjustment for Lwma {
+    type State = ();
+
+    fn next_difficulty(
+        _state: &mut (),
+        history: &[DifficultyAndTimestamp<u128>],
+        target_block_time: u128,
+        initial_difficulty: Difficulty,
+    ) -> Difficulty {
+        let Some((first, blocks)) = history.split_first() else {
+            return initial_difficulty;
+        };
+        if blocks.is_empty() {
+            return first.difficulty;
+        }
+
+        let n = blocks.len() as u128;
+        let mut previous = first.timestamp;
+        let mut weighted_times = 0u128;
+        let mut sum_difficulty = U256::zero();
+        for (weight, block) in (1..).zip(blocks) {
+            let timestamp = max(block.timestamp, previous + 1);
+            let solve_time = min(6 * target_block_time, timestamp - previous);
+            previous = timestamp;
+            weighted_times += solve_time * weight;
+            sum_difficulty += block.difficulty;
+        }
+
+        // Keep the weighted times reasonable in case of strange timestamps
+        let weighted_times = max(weighted_times, n * n * target_block_time / 20).max(1);
+
+        sum_difficulty.saturating_mul(U256::from(target_block_time * (n + 1) * 99))
+            / U256::from(weighted_times * 2 * 100)
+    }
+}
 
 pub use pallet::*;
 
+sp_api::decl_runtime_apis! {
+    /// Queries of the difficulty adjustment of each mining algorithm for off-chain clients
+    pub trait DifficultyHistoryApi {
+        /// The window of past difficulties and timestamps of the blocks mined with `algo`,
+        /// from earliest to latest. Until the algorithm has mined a full window, it starts with
+        /// the initial difficulty at on-target timestamps.
+        fn difficulty_history(algo: AlgoId) -> Vec<DifficultyAndTimestamp<u128>>;
+    }
+}
+
 #[frame_support::pallet(dev_mode)]
 pub mod pallet {
     use frame_support::pallet_prelude::*;
@@ -43,161 +445,623 @@ pub mod pallet {
 
     /// Pallet's configuration trait.
     #[pallet::config]
-    pub trait Config<I: 'static = ()>: frame_system::Config {
+    pub trait Config: frame_system::Config {
         /// A Source for timestamp data
         type TimeProvider: Time;
-        /// The block time that the DAA will attempt to maintain
+        /// The block time that the DAA will attempt to maintain for each algorithm
         type TargetBlockTime: Get<u128>;
-        /// Dampening factor to use for difficulty adjustment
-        type DampFactor: Get<u128>;
-        /// Clamp factor to use for difficulty adjustment
-        /// Limit value to within this factor of goal. Recommended value: 2
-        type ClampFactor: Get<u128>;
+        /// The algorithm computing each next difficulty
+        type DifficultyAdjustment: DifficultyAdjustment;
+        /// Number of past blocks the algorithm gets to see
+        #[pallet::constant]
+        type DifficultyWindow: Get<u32>;
+        /// How far beyond its fair share of the recent blocks a mining algorithm mined, when
+        /// several algorithms are mined at once. Use `()` for none.
+        type AlgoDominance: Convert<AlgoId, Perbill>;
+        /// Increase of the difficulty of a fully dominant algorithm, on each of its blocks.
+        /// Scaled down with the dominance.
+        type DominancePenalty: Get<Perbill>;
         /// The maximum difficulty allowed. Recommended to use u128::max_value()
         type MaxDifficulty: Get<u128>;
         /// Minimum difficulty, enforced in difficulty retargetting
-        /// avoids getting stuck when trying to increase difficulty subject to dampening
-        /// Recommended to use same value as DampFactor
+        /// With `Kulupu`, recommended to use same value as its damp factor
         type MinDifficulty: Get<u128>;
+        /// Number of target block times without a block of an algorithm after which its
+        /// difficulty halves, and halves again after each further such period, so an abandoned
+        /// algorithm or a chain that lost its miners doesn't stall. Use 0 to never decay.
+        #[pallet::constant]
+        type EmergencyMultiple: Get<u32>;
+        /// Initial difficulty of the algorithms the genesis config leaves out, such as those
+        /// added to the registry later on
+        type DefaultInitialDifficulty: Get<Difficulty>;
 
-        /// Now that the pallet is instantiable, we need a way to decide which blocks are
-        /// relevant to this instance. This function does just that.
-        ///
-        /// The default implementation assumes that all blocks are relevant which is what
-        /// you probably want when there is only a single instance.
-        fn relevant_to_this_instance() -> bool {
-            true
-        }
+        /// The algorithm the current block is mined with
+        fn mining_algo() -> AlgoId;
     }
 
+    /// The in-code storage version.
+    const STORAGE_VERSION: StorageVersion = StorageVersion::new(2);
+
     #[pallet::pallet]
-    pub struct Pallet<T, I = ()>(_);
+    #[pallet::storage_version(STORAGE_VERSION)]
+    pub struct Pallet<T>(_);
+
+    pub(crate) type Moment<T> = <<T as Config>::TimeProvider as Time>::Moment;
 
-    type DifficultyList<T, I> =
-        [Option<DifficultyAndTimestamp<<<T as Config<I>>::TimeProvider as Time>::Moment>>; 60];
+    type DifficultyList<T> =
+        BoundedVec<DifficultyAndTimestamp<Moment<T>>, <T as Config>::DifficultyWindow>;
 
-    /// Past difficulties and timestamps, from earliest to latest.
+    /// Past difficulties and timestamps of each algorithm, as a ring buffer starting at
+    /// `WindowHead`.
     #[pallet::storage]
-    pub type PastDifficultiesAndTimestamps<T: Config<I>, I: 'static = ()> =
-        StorageValue<_, DifficultyList<T, I>, ValueQuery, EmptyList<T, I>>;
+    pub type PastDifficultiesAndTimestamps<T: Config> =
+        StorageMap<_, Twox64Concat, AlgoId, DifficultyList<T>, ValueQuery>;
 
-    pub struct EmptyList<T: Config<I>, I: 'static = ()>(PhantomData<(T, I)>);
-    impl<T: Config<I>, I> Get<DifficultyList<T, I>> for EmptyList<T, I> {
-        fn get() -> DifficultyList<T, I> {
-            [None; DIFFICULTY_ADJUST_WINDOW as usize]
-        }
-    }
+    /// Position of the earliest entry of `PastDifficultiesAndTimestamps`, which the next block
+    /// of the algorithm overwrites.
+    #[pallet::storage]
+    pub type WindowHead<T: Config> = StorageMap<_, Twox64Concat, AlgoId, u32, ValueQuery>;
+
+    /// Current difficulty of each algorithm, unset until its first block.
+    #[pallet::storage]
+    pub type CurrentDifficulty<T: Config> =
+        StorageMap<_, Twox64Concat, AlgoId, Difficulty, OptionQuery>;
 
-    /// Current difficulty.
+    /// Initial difficulty of each algorithm.
     #[pallet::storage]
-    #[pallet::getter(fn difficulty)]
-    pub type CurrentDifficulty<T: Config<I>, I: 'static = ()> =
-        StorageValue<_, Difficulty, ValueQuery>;
+    pub type InitialDifficulty<T: Config> =
+        StorageMap<_, Twox64Concat, AlgoId, Difficulty, ValueQuery, T::DefaultInitialDifficulty>;
 
-    /// Initial difficulty.
+    /// Timestamp the emergency decay of the difficulty is measured from: the latest block of
+    /// the algorithm, or the latest decay since. Unset before the first block of the chain.
     #[pallet::storage]
-    pub type InitialDifficulty<T: Config<I>, I: 'static = ()> =
-        StorageValue<_, Difficulty, ValueQuery>;
+    pub type EmergencyAnchor<T: Config> =
+        StorageMap<_, Twox64Concat, AlgoId, Moment<T>, OptionQuery>;
+
+    /// Timestamp of the latest block of each algorithm, minimum difficulty blocks included.
+    /// Unset before the first block of the chain.
+    #[pallet::storage]
+    pub type LastBlockTime<T: Config> = StorageMap<_, Twox64Concat, AlgoId, Moment<T>, OptionQuery>;
+
+    /// Whether the testnet rule is on: after twice the target block time without a block of
+    /// an algorithm, a block of it at `MinDifficulty` is accepted.
+    #[pallet::storage]
+    pub type MinDifficultyBlocks<T: Config> = StorageValue<_, bool, ValueQuery>;
+
+    /// What the difficulty adjustment algorithm remembers between blocks of each algorithm.
+    #[pallet::storage]
+    pub type AdjustmentState<T: Config> = StorageMap<
+        _,
+        Twox64Concat,
+        AlgoId,
+        <<T as Config>::DifficultyAdjustment as DifficultyAdjustment>::State,
+        ValueQuery,
+    >;
 
     #[pallet::genesis_config]
-    pub struct GenesisConfig<T: Config<I>, I: 'static = ()> {
-        pub _ph_data: Option<PhantomData<(T, I)>>,
-        pub initial_difficulty: [u8; 32], // Difficulty = U256
+    pub struct GenesisConfig<T: Config> {
+        pub _ph_data: Option<PhantomData<T>>,
+        /// Initial difficulty of each algorithm, by id. Algorithms left out or at zero start
+        /// at `DefaultInitialDifficulty`.
+        pub initial_difficulty: Vec<[u8; 32]>, // Difficulty = U256
+        /// Turns the testnet minimum difficulty rule on, which the node must be told about too
+        pub min_difficulty_blocks: bool,
     }
 
     #[pallet::genesis_build]
-    impl<T: Config<I>, I: 'static> BuildGenesisConfig for GenesisConfig<T, I> {
+    impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
         fn build(&self) {
-            let initial_difficulty = U256::from_little_endian(&self.initial_difficulty);
-            // Initialize the Current difficulty
-            CurrentDifficulty::<T, I>::put(&initial_difficulty);
+            for (id, difficulty) in self.initial_difficulty.iter().enumerate() {
+                let initial_difficulty = U256::from_little_endian(difficulty);
+                if initial_difficulty.is_zero() {
+                    continue;
+                }
+
+                // Stored because we will need it to fill the window when the first block of
+                // the algorithm is finalized.
+                InitialDifficulty::<T>::insert(AlgoId(id as u8), initial_difficulty);
+            }
 
-            // Store the initial difficulty in storage because we will need it
-            // during the first DIFFICULTY_ADJUSTMENT_WINDOW blocks (see todo below).
-            InitialDifficulty::<T, I>::put(&initial_difficulty);
+            MinDifficultyBlocks::<T>::put(self.min_difficulty_blocks);
         }
     }
 
-    impl<T: Config<I>, I: 'static> Default for GenesisConfig<T, I> {
+    impl<T: Config> Default for GenesisConfig<T> {
         fn default() -> Self {
             GenesisConfig {
                 _ph_data: Default::default(),
-                initial_difficulty: [0u8; 32],
+                initial_difficulty: Vec::new(),
+                min_difficulty_blocks: false,
             }
         }
     }
 
     #[pallet::hooks]
-    impl<T: Config<I>, I: 'static> Hooks<BlockNumberFor<T>> for Pallet<T, I> {
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
         fn on_finalize(_n: BlockNumberFor<T>) {
-            // First check if this is block is relevant to this instance of the difficulty adjustment algorithm
-            if !T::relevant_to_this_instance() {
+            let now = T::TimeProvider::now();
+            let mined = T::mining_algo();
+            for algorithm in ALGORITHMS {
+                Self::retarget(algorithm.id, algorithm.id == mined, now);
+            }
+        }
+    }
+
+    impl<T: Config> Pallet<T> {
+        /// Current difficulty of `algo`, its initial difficulty until its first block
+        pub fn difficulty(algo: AlgoId) -> Difficulty {
+            CurrentDifficulty::<T>::get(algo).unwrap_or_else(|| InitialDifficulty::<T>::get(algo))
+        }
+
+        /// Difficulty of a block of `algo` with timestamp `now`, after the emergency decay for
+        /// the time without a block since the latest one
+        pub fn difficulty_at(algo: AlgoId, now: Moment<T>) -> Difficulty {
+            Self::decay(algo, now).0
+        }
+
+        /// Difficulty of a block of `algo` with timestamp `now`, under the testnet minimum
+        /// difficulty rule if it is on
+        pub fn min_difficulty_at(algo: AlgoId, now: Moment<T>) -> Difficulty {
+            let difficulty = Self::difficulty_at(algo, now);
+            if Self::min_difficulty_block(algo, now) {
+                return min(difficulty, U256::from(T::MinDifficulty::get()));
+            }
+
+            difficulty
+        }
+
+        /// Updates the difficulty of `algo` once the block is finalized, retargetting it if
+        /// the block is `relevant`, mined with `algo`
+        fn retarget(algo: AlgoId, relevant: bool, now: Moment<T>) {
+            let (decayed, anchor) = Self::decay(algo, now);
+            let min_difficulty_block = relevant && Self::min_difficulty_block(algo, now);
+
+            // The first block of the chain starts the clock of every algorithm
+            if relevant || LastBlockTime::<T>::get(algo).is_none() {
+                <LastBlockTime<T>>::insert(algo, now);
+            }
+
+            // Minimum difficulty blocks say nothing of the hashrate, so they are left out too,
+            // and can't drag the difficulty down for good.
+            if !relevant || min_difficulty_block {
+                // The time without a block of this algorithm counts all the same
+                <CurrentDifficulty<T>>::insert(algo, decayed);
+                <EmergencyAnchor<T>>::insert(algo, anchor);
                 return;
             }
 
-            let mut data = PastDifficultiesAndTimestamps::<T, I>::get();
+            let (mut data, head) = Self::window(algo, now);
+
+            // Overwrite the earliest entry with this block, at the difficulty it was mined at
+            data[head] = DifficultyAndTimestamp {
+                timestamp: now,
+                difficulty: decayed,
+            };
+            let head = (head + 1) % data.len();
+
+            let history = Self::in_order(&data, head);
+            let mut state = AdjustmentState::<T>::get(algo);
+            let next = T::DifficultyAdjustment::next_difficulty(
+                &mut state,
+                &history,
+                T::TargetBlockTime::get(),
+                InitialDifficulty::<T>::get(algo),
+            );
+
+            // Make a dominant algorithm harder to mine
+            let penalty = T::DominancePenalty::get() * T::AlgoDominance::convert(algo);
+            let next = next.saturating_add(
+                next.saturating_mul(U256::from(penalty.deconstruct()))
+                    / U256::from(Perbill::ACCURACY),
+            );
+
+            let difficulty = min(
+                U256::from(T::MaxDifficulty::get()),
+                max(U256::from(T::MinDifficulty::get()), next),
+            );
+
+            <AdjustmentState<T>>::insert(algo, state);
+            <PastDifficultiesAndTimestamps<T>>::insert(algo, data);
+            <WindowHead<T>>::insert(algo, head as u32);
+            <CurrentDifficulty<T>>::insert(algo, difficulty);
+            <EmergencyAnchor<T>>::insert(algo, now);
+        }
+
+        /// Whether a block of `algo` with timestamp `now` only needs `MinDifficulty`, coming
+        /// more than twice the target block time after the latest one
+        fn min_difficulty_block(algo: AlgoId, now: Moment<T>) -> bool {
+            let Some(last) = LastBlockTime::<T>::get(algo) else {
+                return false;
+            };
+            let elapsed: u128 = now.saturating_sub(last).unique_saturated_into();
 
-            for i in 1..data.len() {
-                data[i - 1] = data[i];
+            MinDifficultyBlocks::<T>::get() && elapsed > T::TargetBlockTime::get().saturating_mul(2)
+        }
+
+        /// The decayed difficulty of `algo` at `now` and the timestamp of its latest halving.
+        /// Halvings are kept at whole spans from the latest block, so applying the decay on
+        /// every block halves as often as applying it once.
+        fn decay(algo: AlgoId, now: Moment<T>) -> (Difficulty, Moment<T>) {
+            let difficulty = Self::difficulty(algo);
+            let Some(anchor) = EmergencyAnchor::<T>::get(algo) else {
+                return (difficulty, now);
+            };
+
+            let span = T::TargetBlockTime::get().saturating_mul(T::EmergencyMultiple::get().into());
+            let elapsed: u128 = now.saturating_sub(anchor).unique_saturated_into();
+            let decayed = emergency_decay(
+                difficulty,
+                elapsed,
+                span,
+                U256::from(T::MinDifficulty::get()),
+            );
+            if decayed == difficulty {
+                return (difficulty, anchor);
             }
 
-            data[data.len() - 1] = Some(DifficultyAndTimestamp {
-                timestamp: T::TimeProvider::now(),
-                difficulty: Self::difficulty(),
-            });
+            let halved_for = elapsed - elapsed % span;
+            (decayed, anchor.saturating_add(halved_for.saturated_into()))
+        }
 
-            let mut ts_delta = 0;
-            for i in 1..(DIFFICULTY_ADJUST_WINDOW as usize) {
-                let prev: Option<u128> = data[i - 1].map(|d| d.timestamp.unique_saturated_into());
-                let cur: Option<u128> = data[i].map(|d| d.timestamp.unique_saturated_into());
+        /// Past difficulties and timestamps of `algo`, from earliest to latest
+        pub fn history(algo: AlgoId) -> Vec<DifficultyAndTimestamp<u128>> {
+            let data = PastDifficultiesAndTimestamps::<T>::get(algo);
+            let head = WindowHead::<T>::get(algo) as usize;
 
-                let delta = match (prev, cur) {
-                    (Some(prev), Some(cur)) => cur.saturating_sub(prev),
-                    _ => T::TargetBlockTime::get(),
-                };
-                ts_delta += delta;
+            Self::in_order(&data, head.checked_rem(data.len()).unwrap_or(0))
+        }
+
+        /// The entries of the ring buffer starting at `head`
+        fn in_order(data: &DifficultyList<T>, head: usize) -> Vec<DifficultyAndTimestamp<u128>> {
+            data[head..]
+                .iter()
+                .chain(&data[..head])
+                .map(|d| DifficultyAndTimestamp {
+                    difficulty: d.difficulty,
+                    timestamp: d.timestamp.unique_saturated_into(),
+                })
+                .collect()
+        }
+
+        /// The stored window of `algo` and the position of its earliest entry, with exactly
+        /// `DifficultyWindow` entries.
+        ///
+        /// The window is empty until the first block, and may be shorter or longer than the
+        /// window after a runtime upgrade. Missing earlier blocks are taken to have had the
+        /// initial difficulty and to have arrived right on target, the excess earliest ones
+        /// are dropped.
+        fn window(algo: AlgoId, now: Moment<T>) -> (DifficultyList<T>, usize) {
+            let window = T::DifficultyWindow::get().max(1) as usize;
+            let data = PastDifficultiesAndTimestamps::<T>::get(algo);
+            let head = WindowHead::<T>::get(algo) as usize;
+            if data.len() == window {
+                return (data, head % window);
             }
 
-            if ts_delta == 0 {
-                ts_delta = 1;
+            let mut data = data.into_inner();
+            let len = data.len();
+            if len > 0 {
+                data.rotate_left(head % len);
+            }
+            data.drain(..len.saturating_sub(window));
+
+            let target = T::TargetBlockTime::get();
+            let earliest = data.first().map_or(now, |d| d.timestamp);
+            let missing = window - data.len();
+            let filler = (0..missing).map(|i| DifficultyAndTimestamp {
+                difficulty: InitialDifficulty::<T>::get(algo),
+                timestamp: earliest.saturating_sub(
+                    target
+                        .saturating_mul((missing - i) as u128)
+                        .saturated_into(),
+                ),
+            });
+            let data = filler.chain(data).collect::<Vec<_>>();
+
+            (BoundedVec::truncate_from(data), 0)
+        }
+    }
+}
+
+/// Storage migrations of the difficulty pallet
+pub mod migrations {
+    use frame_support::{
+        migration::{get_storage_value, take_storage_value},
+        pallet_prelude::*,
+        traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion},
+    };
+
+    use super::*;
+
+    /// The window before storage version 1, a fixed array of the 60 latest blocks with `None`
+    /// until the chain had that many
+    type LegacyList<T> = [Option<DifficultyAndTimestamp<Moment<T>>>; 60];
+
+    /// Key of the storage version of a pallet, under its prefix
+    const STORAGE_VERSION_KEY: &[u8] = b":__STORAGE_VERSION__:";
+
+    /// Moves the storage of the former pallet instances, one per algorithm, into the maps of
+    /// storage version 2.
+    ///
+    /// `Instances` lists the algorithm of each instance along with the name it had in
+    /// `construct_runtime`. Instances still at storage version 0 had their window in a fixed
+    /// array. Blocks that window hadn't seen yet are left out, they are filled in with the
+    /// initial difficulty when the next block of the algorithm is finalized.
+    pub struct MergeInstances<T, Instances>(PhantomData<(T, Instances)>);
+
+    impl<T: Config, Instances: Get<Vec<(AlgoId, &'static str)>>> OnRuntimeUpgrade
+        for MergeInstances<T, Instances>
+    {
+        fn on_runtime_upgrade() -> Weight {
+            if Pallet::<T>::on_chain_storage_version() >= 2 {
+                return T::DbWeight::get().reads(1);
             }
 
-            let mut diff_sum = U256::zero();
-            //TODO Could we just initialize every array cell to the initial difficulty to not need the
-            // separate storage item?
-            for item in data.iter().take(DIFFICULTY_ADJUST_WINDOW as usize) {
-                let diff = match item.map(|d| d.difficulty) {
-                    Some(diff) => diff,
-                    None => InitialDifficulty::<T, I>::get(),
+            let instances = Instances::get();
+            for (algo, name) in &instances {
+                let prefix = name.as_bytes();
+                let version: u16 = get_storage_value(prefix, STORAGE_VERSION_KEY, &[]).unwrap_or(0);
+
+                let (data, head) = if version == 0 {
+                    let legacy: LegacyList<T> =
+                        take_storage_value(prefix, b"PastDifficultiesAndTimestamps", &[])
+                            .unwrap_or([None; 60]);
+                    let known: Vec<_> = legacy.into_iter().flatten().collect();
+
+                    // Keep the latest blocks when the new window is smaller
+                    let window = T::DifficultyWindow::get().max(1) as usize;
+                    (known[known.len().saturating_sub(window)..].to_vec(), 0)
+                } else {
+                    let data: Vec<DifficultyAndTimestamp<Moment<T>>> =
+                        take_storage_value(prefix, b"PastDifficultiesAndTimestamps", &[])
+                            .unwrap_or_default();
+                    let head: u32 =
+                        take_storage_value(prefix, b"WindowHead", &[]).unwrap_or_default();
+                    (data, head)
                 };
-                diff_sum += diff;
+                log::info!(
+                    "Migrated {} past difficulties of {} from {}",
+                    data.len(),
+                    algo,
+                    name
+                );
+                PastDifficultiesAndTimestamps::<T>::insert(algo, BoundedVec::truncate_from(data));
+                WindowHead::<T>::insert(algo, head);
+
+                if let Some(difficulty) =
+                    take_storage_value::<Difficulty>(prefix, b"CurrentDifficulty", &[])
+                {
+                    CurrentDifficulty::<T>::insert(algo, difficulty);
+                }
+                if let Some(difficulty) =
+                    take_storage_value::<Difficulty>(prefix, b"InitialDifficulty", &[])
+                {
+                    InitialDifficulty::<T>::insert(algo, difficulty);
+                }
+                if let Some(state) = take_storage_value::<
+                    <T::DifficultyAdjustment as DifficultyAdjustment>::State,
+                >(prefix, b"AdjustmentState", &[])
+                {
+                    AdjustmentState::<T>::insert(algo, state);
+                }
+                if let Some(anchor) =
+                    take_storage_value::<Moment<T>>(prefix, b"EmergencyAnchor", &[])
+                {
+                    EmergencyAnchor::<T>::insert(algo, anchor);
+                }
+                if let Some(last) = take_storage_value::<Moment<T>>(prefix, b"LastBlockTime", &[]) {
+                    LastBlockTime::<T>::insert(algo, last);
+                }
+                // The rule was set alike in every instance
+                if let Some(on) = take_storage_value::<bool>(prefix, b"MinDifficultyBlocks", &[]) {
+                    MinDifficultyBlocks::<T>::put(on);
+                }
+                take_storage_value::<u16>(prefix, STORAGE_VERSION_KEY, &[]);
             }
+            StorageVersion::new(2).put::<Pallet<T>>();
+
+            let items = instances.len() as u64 * 9;
+            T::DbWeight::get().reads_writes(items + 1, items + 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use multi_pow::{
+        hashes::{Md5, Sha3},
+        PowHash,
+    };
+
+    use super::*;
+    use crate::mock::{mine, new_test_ext, Difficulty as DifficultyPallet, Test, Timestamp};
+
+    #[test]
+    fn emergency_decay_halves_once_per_full_span() {
+        let difficulty = U256::from(1_000_000);
+        let min_difficulty = U256::from(3);
+
+        assert_eq!(
+            emergency_decay(difficulty, 5_999, 6_000, min_difficulty),
+            difficulty
+        );
+        assert_eq!(
+            emergency_decay(difficulty, 6_000, 6_000, min_difficulty),
+            difficulty / 2
+        );
+        assert_eq!(
+            emergency_decay(difficulty, 17_999, 6_000, min_difficulty),
+            difficulty / 4
+        );
+        assert_eq!(
+            emergency_decay(difficulty, 18_000, 6_000, min_difficulty),
+            difficulty / 8
+        );
+    }
+
+    #[test]
+    fn emergency_decay_stops_at_the_minimum_difficulty() {
+        let min_difficulty = U256::from(3);
+
+        assert_eq!(
+            emergency_decay(U256::from(1_000_000), u128::MAX, 6_000, min_difficulty),
+            min_difficulty
+        );
+        assert_eq!(
+            emergency_decay(U256::from(2), 60_000, 6_000, min_difficulty),
+            U256::from(2)
+        );
+        assert_eq!(
+            emergency_decay(U256::from(1_000), 60_000, 0, min_difficulty),
+            U256::from(1_000)
+        );
+    }
+
+    #[test]
+    fn difficulty_decays_with_the_time_since_the_latest_block() {
+        new_test_ext(vec![]).execute_with(|| {
+            // The first block starts the clock of every algorithm, a span is 6 target block times
+            mine(Md5::ID, 1_000);
+            let initial = DifficultyPallet::difficulty(Sha3::ID);
+            let at = |ms| DifficultyPallet::difficulty_at(Sha3::ID, Timestamp::get() + ms);
 
-            if diff_sum < U256::from(T::MinDifficulty::get()) {
-                diff_sum = U256::from(T::MinDifficulty::get());
+            assert_eq!(at(5_999), initial);
+            assert_eq!(at(6_000), initial / 2);
+            assert_eq!(at(12_000), initial / 4);
+            assert_eq!(at(u32::MAX.into()), U256::from(3));
+        });
+    }
+
+    #[test]
+    fn decaying_on_every_block_halves_as_often_as_decaying_once() {
+        let once = new_test_ext(vec![]).execute_with(|| {
+            mine(Md5::ID, 1_000);
+            DifficultyPallet::difficulty_at(Sha3::ID, Timestamp::get() + 12_000)
+        });
+
+        let on_every_block = new_test_ext(vec![]).execute_with(|| {
+            mine(Md5::ID, 1_000);
+            for _ in 0..3 {
+                mine(Md5::ID, 4_000);
             }
+            DifficultyPallet::difficulty(Sha3::ID)
+        });
 
-            // Calculate the average length of the adjustment window
-            let adjustment_window = DIFFICULTY_ADJUST_WINDOW * T::TargetBlockTime::get();
+        assert_eq!(on_every_block, once);
+        assert_eq!(
+            once,
+            new_test_ext(vec![]).execute_with(|| DifficultyPallet::difficulty(Sha3::ID)) / 4
+        );
+    }
 
-            // adjust time delta toward goal subject to dampening and clamping
-            let adj_ts = clamp(
-                damp(ts_delta, adjustment_window, T::DampFactor::get()),
-                adjustment_window,
-                T::ClampFactor::get(),
+    #[test]
+    fn a_block_resets_the_decay_of_its_algorithm() {
+        new_test_ext(vec![]).execute_with(|| {
+            mine(Md5::ID, 1_000);
+            // Sha3 is mined with md5 alone in the recent blocks, a span of 6 seconds. With both
+            // algorithms active afterwards a span is 12 seconds.
+            mine(Sha3::ID, 13_000);
+
+            // A full span from the latest sha3 block is needed for the next halving
+            let retargeted = DifficultyPallet::difficulty(Sha3::ID);
+            assert_eq!(
+                DifficultyPallet::difficulty_at(Sha3::ID, Timestamp::get() + 11_999),
+                retargeted
+            );
+            assert_eq!(
+                DifficultyPallet::difficulty_at(Sha3::ID, Timestamp::get() + 12_000),
+                retargeted / 2
             );
+        });
+    }
 
-            // minimum difficulty avoids getting stuck due to dampening
-            let difficulty = min(
-                U256::from(T::MaxDifficulty::get()),
-                max(
-                    U256::from(T::MinDifficulty::get()),
-                    diff_sum * U256::from(T::TargetBlockTime::get()) / U256::from(adj_ts),
-                ),
+    #[test]
+    fn blocks_are_checked_at_the_difficulty_they_are_recorded_at() {
+        new_test_ext(vec![]).execute_with(|| {
+            mine(Md5::ID, 1_000);
+
+            // The node checks a block on the state of its parent. The first sha3 block brings a
+            // second algorithm in, and comes late enough to decay.
+            let checked = DifficultyPallet::difficulty_at(Sha3::ID, Timestamp::get() + 13_000);
+            assert_ne!(checked, DifficultyPallet::difficulty(Sha3::ID));
+            mine(Sha3::ID, 13_000);
+
+            let recorded = DifficultyPallet::history(Sha3::ID)
+                .last()
+                .unwrap()
+                .difficulty;
+            assert_eq!(recorded, checked);
+        });
+    }
+
+    #[test]
+    fn late_blocks_need_only_the_minimum_difficulty_on_testnets() {
+        new_test_ext(vec![]).execute_with(|| {
+            MinDifficultyBlocks::<Test>::put(true);
+            mine(Md5::ID, 1_000);
+            mine(Md5::ID, 1_000);
+            let difficulty = DifficultyPallet::difficulty(Md5::ID);
+            let at = |ms| DifficultyPallet::min_difficulty_at(Md5::ID, Timestamp::get() + ms);
+
+            // More than twice the target block time
+            assert_eq!(at(2_000), difficulty);
+            assert_eq!(at(2_001), U256::from(3));
+        });
+    }
+
+    #[test]
+    fn minimum_difficulty_blocks_are_left_out_of_the_window() {
+        new_test_ext(vec![]).execute_with(|| {
+            MinDifficultyBlocks::<Test>::put(true);
+            mine(Md5::ID, 1_000);
+            mine(Md5::ID, 1_000);
+            let history = DifficultyPallet::history(Md5::ID);
+            let difficulty = DifficultyPallet::difficulty(Md5::ID);
+
+            mine(Md5::ID, 2_500);
+            assert_eq!(DifficultyPallet::history(Md5::ID), history);
+            assert_eq!(DifficultyPallet::difficulty(Md5::ID), difficulty);
+
+            // The clock restarts from the minimum difficulty block all the same
+            assert_eq!(
+                DifficultyPallet::min_difficulty_at(Md5::ID, Timestamp::get() + 1_000),
+                difficulty
             );
+        });
+    }
 
-            <PastDifficultiesAndTimestamps<T, I>>::put(data);
-            <CurrentDifficulty<T, I>>::put(difficulty);
-        }
+    #[test]
+    fn minimum_difficulty_blocks_are_judged_on_the_state_they_are_checked_on() {
+        new_test_ext(vec![]).execute_with(|| {
+            MinDifficultyBlocks::<Test>::put(true);
+            mine(Md5::ID, 1_000);
+            let history = DifficultyPallet::history(Sha3::ID);
+
+            // With md5 alone in the recent blocks the first sha3 block is late after 2 seconds,
+            // with both algorithms active it would be after 4
+            assert_eq!(
+                DifficultyPallet::min_difficulty_at(Sha3::ID, Timestamp::get() + 3_000),
+                U256::from(3)
+            );
+            mine(Sha3::ID, 3_000);
+            assert_eq!(DifficultyPallet::history(Sha3::ID), history);
+        });
+    }
+
+    #[test]
+    fn late_blocks_need_the_full_difficulty_without_the_rule() {
+        new_test_ext(vec![]).execute_with(|| {
+            mine(Md5::ID, 1_000);
+            mine(Md5::ID, 1_000);
+            let history = DifficultyPallet::history(Md5::ID);
+            let difficulty = DifficultyPallet::difficulty(Md5::ID);
+
+            assert_eq!(
+                DifficultyPallet::min_difficulty_at(Md5::ID, Timestamp::get() + 2_500),
+                difficulty
+            );
+            mine(Md5::ID, 2_500);
+            assert_ne!(DifficultyPallet::history(Md5::ID), history);
+        });
     }
 }
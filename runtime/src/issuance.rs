@@ -23,7 +23,6 @@ impl Issuance<u64, u128> for () {
 /// Initial issuance is 50 / block
 /// Issuance is cut in half every 210,000 blocks
 /// cribbed from github.com/Bitcoin-ABC/bitcoin-abc/blob/9c7b12e6f128a59423f4de3d6d4b5231ebe9aac2/src/validation.cpp#L1007
-
 pub struct BitcoinHalving;
 
 /// The number of blocks between each halving.
@@ -9,7 +9,7 @@
 include!(concat!(env!("OUT_DIR"), "/wasm_binary.rs"));
 
 pub use frame_support::{
-    construct_runtime, parameter_types, derive_impl,
+    construct_runtime, derive_impl, parameter_types,
     traits::{
         Currency, EstimateNextNewSession, Imbalance, IsSubType, KeyOwnerProofSystem,
         LockIdentifier, Nothing, OnUnbalanced, ValidatorSet, VariantCountOf,
@@ -24,18 +24,20 @@ pub use frame_support::{
 };
 use frame_support::{
     genesis_builder_helper::{build_state, get_preset},
-    instances::{Instance1, Instance2, Instance3},
     sp_runtime::Perquintill,
     traits::{ConstU128, ConstU32, ConstU8},
 };
-use multi_pow::SupportedHashes;
+use multi_pow::{
+    hashes::{Keccak, Md5, Sha3},
+    AlgoId, PowHash, ALGORITHMS,
+};
 pub use pallet_balances::Call as BalancesCall;
 pub use pallet_timestamp::Call as TimestampCall;
 use pallet_transaction_payment::{ConstFeeMultiplier, FungibleAdapter, Multiplier};
 use parity_scale_codec::Decode;
 use sp_api::impl_runtime_apis;
 use sp_consensus_pow::POW_ENGINE_ID;
-use sp_core::OpaqueMetadata;
+use sp_core::{OpaqueMetadata, U256};
 // A few exports that help ease life for downstream crates.
 #[cfg(any(feature = "std", test))]
 pub use sp_runtime::BuildStorage;
@@ -44,11 +46,8 @@ use sp_runtime::{
     traits::{
         AccountIdLookup, BlakeTwo256, Block as BlockT, Bounded, IdentifyAccount, One, Verify,
     },
-    transaction_validity::{
-        InvalidTransaction, TransactionSource, TransactionValidity, TransactionValidityError,
-    },
-    ApplyExtrinsicResult, DigestItem, MultiSignature,
-    ExtrinsicInclusionMode,
+    transaction_validity::{TransactionLongevity, TransactionSource, TransactionValidity},
+    ApplyExtrinsicResult, DigestItem, ExtrinsicInclusionMode, MultiSignature,
 };
 pub use sp_runtime::{FixedPointNumber, Perbill, Permill};
 use sp_std::prelude::*;
@@ -82,7 +81,11 @@ pub type Index = u32;
 pub type Hash = sp_core::H256;
 
 /// Consensus digest containing block author and supported hash algorithm.
-pub type PreDigest = (AccountId, SupportedHashes);
+pub type PreDigest = (AccountId, AlgoId);
+
+/// Checksummed, human-readable addresses of UTXO owners
+#[cfg(feature = "std")]
+pub mod address;
 
 /// The BlockAuthor trait in `./block_author.rs`
 pub mod block_author;
@@ -93,9 +96,24 @@ pub mod difficulty;
 /// The total issuance and halving time
 pub mod issuance;
 
+/// Mock runtime for the unit tests of the pallets
+#[cfg(test)]
+mod mock;
+
+/// Block time and share of each mining algorithm when several are mined at once
+pub mod multi_algo;
+
+/// Partially signed transactions, passed between the signers of a UTXO spend
+#[cfg(feature = "std")]
+pub mod pst;
+
 /// UTXOs serve as the digital equivalent of change you receive after making a cash purchase
 pub mod utxo;
 
+/// Coin selection, transaction building and signing for UTXO wallets
+#[cfg(feature = "std")]
+pub mod wallet;
+
 /// Opaque types. These are used by the CLI to instantiate machinery that don't need to know
 /// the specifics of the runtime. They can then be made to be agnostic over specific formats
 /// of data like extrinsics, allowing for them to continue syncing the network through upgrades
@@ -119,7 +137,7 @@ pub const VERSION: RuntimeVersion = RuntimeVersion {
     spec_name: create_runtime_str!("academy-pow"),
     impl_name: create_runtime_str!("academy-pow"),
     authoring_version: 1,
-    spec_version: 1,
+    spec_version: 21,
     impl_version: 1,
     apis: RUNTIME_API_VERSIONS,
     transaction_version: 1,
@@ -238,10 +256,28 @@ parameter_types! {
     pub const DampFactor: u128 = 3;
     pub const ClampFactor: u128 = 2;
     pub const MaxDifficulty: u128 = u128::max_value();
+    pub const DifficultyWindow: u32 = 60;
+    pub const EmergencyMultiple: u32 = 6;
+    pub const DominancePenalty: Perbill = Perbill::from_percent(5);
+    pub const RecentAlgoBlocks: u32 = 60;
+    pub DefaultInitialDifficulty: U256 = U256::from(2_000_000);
+    // Names of the difficulty instances each algorithm had before they were merged
+    pub LegacyDifficultyInstances: Vec<(AlgoId, &'static str)> = vec![
+        (Md5::ID, "Md5DifficultyAdjustment"),
+        (Sha3::ID, "Sha3DifficultyAdjustment"),
+        (Keccak::ID, "KeccakDifficultyAdjustment"),
+    ];
 }
 
+/// The block time of each mining algorithm, for the chain to keep `TargetBlockTime`
+type AlgoTargetBlockTime = multi_algo::ScaledTargetBlockTime<Runtime, TargetBlockTime>;
+
+/// The difficulty adjustment algorithm of every mining algorithm. `difficulty::BitcoinRetarget`,
+/// `difficulty::Asert` and `difficulty::Lwma` can be swapped in here.
+type Daa = difficulty::Kulupu<DampFactor, ClampFactor, DampFactor>;
+
 // Helper function to get the current blocks PoW algo from the predigest
-fn current_blocks_mining_algo() -> SupportedHashes {
+fn current_blocks_mining_algo() -> AlgoId {
     System::digest()
         .logs
         .iter()
@@ -254,42 +290,28 @@ fn current_blocks_mining_algo() -> SupportedHashes {
         .expect("There should be exactly one pow pre-digest item")
 }
 
-impl difficulty::Config<Instance1> for Runtime {
+impl difficulty::Config for Runtime {
     type TimeProvider = Timestamp;
-    type TargetBlockTime = TargetBlockTime;
-    type DampFactor = DampFactor;
-    type ClampFactor = ClampFactor;
+    type TargetBlockTime = AlgoTargetBlockTime;
+    type DifficultyAdjustment = Daa;
+    type DifficultyWindow = DifficultyWindow;
     type MaxDifficulty = MaxDifficulty;
     type MinDifficulty = DampFactor;
+    type EmergencyMultiple = EmergencyMultiple;
+    type AlgoDominance = MultiAlgo;
+    type DominancePenalty = DominancePenalty;
+    type DefaultInitialDifficulty = DefaultInitialDifficulty;
 
-    fn relevant_to_this_instance() -> bool {
-        current_blocks_mining_algo() == SupportedHashes::Md5
+    fn mining_algo() -> AlgoId {
+        current_blocks_mining_algo()
     }
 }
 
-impl difficulty::Config<Instance2> for Runtime {
-    type TimeProvider = Timestamp;
-    type TargetBlockTime = TargetBlockTime;
-    type DampFactor = DampFactor;
-    type ClampFactor = ClampFactor;
-    type MaxDifficulty = MaxDifficulty;
-    type MinDifficulty = DampFactor;
+impl multi_algo::Config for Runtime {
+    type RecentBlocks = RecentAlgoBlocks;
 
-    fn relevant_to_this_instance() -> bool {
-        current_blocks_mining_algo() == SupportedHashes::Sha3
-    }
-}
-
-impl difficulty::Config<Instance3> for Runtime {
-    type TimeProvider = Timestamp;
-    type TargetBlockTime = TargetBlockTime;
-    type DampFactor = DampFactor;
-    type ClampFactor = ClampFactor;
-    type MaxDifficulty = MaxDifficulty;
-    type MinDifficulty = DampFactor;
-
-    fn relevant_to_this_instance() -> bool {
-        current_blocks_mining_algo() == SupportedHashes::Keccak
+    fn mining_algo() -> AlgoId {
+        current_blocks_mining_algo()
     }
 }
 
@@ -330,10 +352,19 @@ impl pallet_transaction_payment::Config for Runtime {
     type FeeMultiplierUpdate = ConstFeeMultiplier<FeeMultiplier>;
 }
 
+parameter_types! {
+    // A day of blocks at the 5 second target block time
+    pub const UtxoLongevity: TransactionLongevity = 17_280;
+    // Orphans get an hour for the outpoints they spend to show up
+    pub const UtxoOrphanLongevity: TransactionLongevity = 720;
+}
+
 impl utxo::Config for Runtime {
     type RuntimeEvent = RuntimeEvent;
     type BlockAuthor = BlockAuthor;
     type Issuance = issuance::BitcoinHalving;
+    type Longevity = UtxoLongevity;
+    type OrphanLongevity = UtxoOrphanLongevity;
 }
 
 construct_runtime!(
@@ -342,11 +373,13 @@ construct_runtime!(
         Timestamp: pallet_timestamp,
         Balances: pallet_balances,
         TransactionPayment: pallet_transaction_payment,
-        Md5DifficultyAdjustment: difficulty::<Instance1>,
-        Sha3DifficultyAdjustment: difficulty::<Instance2>,
-        KeccakDifficultyAdjustment: difficulty::<Instance3>,
-        BlockAuthor: block_author,
+        // Indices of the three difficulty instances it replaces, one per algorithm
+        DifficultyAdjustment: difficulty = 4,
+        BlockAuthor: block_author = 7,
         Utxo: utxo,
+        // After DifficultyAdjustment, it records the algorithm of a block once the
+        // difficulties retargeted
+        MultiAlgo: multi_algo,
     }
 );
 
@@ -379,8 +412,16 @@ pub type Executive = frame_executive::Executive<
     frame_system::ChainContext<Runtime>,
     Runtime,
     AllPalletsWithSystem,
+    Migrations,
 >;
 
+/// Storage migrations run on the next runtime upgrade.
+type Migrations = (
+    difficulty::migrations::MergeInstances<Runtime, LegacyDifficultyInstances>,
+    utxo::migrations::AddAssets<Runtime>,
+    utxo::migrations::IndexOwners<Runtime>,
+);
+
 impl_runtime_apis! {
     impl sp_api::Core<Block> for Runtime {
         fn version() -> RuntimeVersion {
@@ -437,21 +478,6 @@ impl_runtime_apis! {
             tx: <Block as BlockT>::Extrinsic,
             block_hash: <Block as BlockT>::Hash,
         ) -> TransactionValidity {
-            // Extrinsics representing UTXO transaction need some special handling
-            if let Some(&utxo::Call::spend{ ref transaction }) = IsSubType::<<Utxo as Callable<Runtime>>::RuntimeCall>::is_sub_type(&tx.function)
-            {
-                match Utxo::validate_transaction(&transaction) {
-                    // Transaction verification failed
-                    Err(e) => {
-                        sp_runtime::print(e);
-                        return Err(TransactionValidityError::Invalid(InvalidTransaction::Custom(1)));
-                    }
-                    // Race condition, or Transaction is good to go
-                    Ok(tv) => { return Ok(tv); }
-                }
-            }
-
-            // Fall back to default logic for non UTXO-spending extrinsics
             Executive::validate_transaction(source, tx, block_hash)
         }
     }
@@ -476,11 +502,46 @@ impl_runtime_apis! {
 
     impl sp_consensus_pow::DifficultyApi<Block, multi_pow::Threshold> for Runtime {
         fn difficulty() -> multi_pow::Threshold {
-            multi_pow::Threshold {
-                md5: Md5DifficultyAdjustment::difficulty(),
-                sha3: Sha3DifficultyAdjustment::difficulty(),
-                keccak: KeccakDifficultyAdjustment::difficulty(),
-            }
+            ALGORITHMS
+                .iter()
+                .map(|a| (a.id, DifficultyAdjustment::difficulty(a.id)))
+                .collect()
+        }
+    }
+
+    impl multi_pow::TimedDifficultyApi<Block> for Runtime {
+        fn difficulty_at(timestamp: u64) -> multi_pow::Threshold {
+            ALGORITHMS
+                .iter()
+                .map(|a| (a.id, DifficultyAdjustment::difficulty_at(a.id, timestamp)))
+                .collect()
+        }
+
+        fn min_difficulty_at(timestamp: u64) -> multi_pow::Threshold {
+            ALGORITHMS
+                .iter()
+                .map(|a| (a.id, DifficultyAdjustment::min_difficulty_at(a.id, timestamp)))
+                .collect()
+        }
+    }
+
+    impl difficulty::DifficultyHistoryApi<Block> for Runtime {
+        fn difficulty_history(algo: AlgoId) -> Vec<difficulty::DifficultyAndTimestamp<u128>> {
+            DifficultyAdjustment::history(algo)
+        }
+    }
+
+    impl utxo::UtxoApi<Block> for Runtime {
+        fn nft_holder(id: Hash) -> Option<Hash> {
+            Utxo::nft_holder(id)
+        }
+
+        fn utxos_of(owner: Hash) -> Vec<(Hash, utxo::TransactionOutput)> {
+            Utxo::utxos_of(owner)
+        }
+
+        fn utxo(outpoint: Hash) -> Option<utxo::TransactionOutput> {
+            utxo::UtxoStore::<Runtime>::get(outpoint)
         }
     }
 
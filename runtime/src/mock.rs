@@ -0,0 +1,115 @@
+use frame_support::{
+    construct_runtime, derive_impl, parameter_types,
+    traits::{ConstU128, ConstU32, ConstU64, Hooks},
+};
+use multi_pow::{hashes::Md5, AlgoId, PowHash};
+use sp_core::{sr25519, Pair, H256, U256};
+use sp_runtime::{BuildStorage, Perbill};
+
+use crate::{
+    difficulty, multi_algo,
+    utxo::{self, Value},
+};
+
+type Block = frame_system::mocking::MockBlock<Test>;
+
+construct_runtime!(
+    pub enum Test {
+        System: frame_system,
+        Timestamp: pallet_timestamp,
+        Utxo: utxo,
+        // After the difficulty pallet, as in the runtime
+        Difficulty: difficulty,
+        MultiAlgo: multi_algo,
+    }
+);
+
+#[derive_impl(frame_system::config_preludes::TestDefaultConfig)]
+impl frame_system::Config for Test {
+    type Block = Block;
+}
+
+impl utxo::Config for Test {
+    type RuntimeEvent = RuntimeEvent;
+    type BlockAuthor = ();
+    type Issuance = ();
+    type Longevity = ConstU64<100>;
+    type OrphanLongevity = ConstU64<10>;
+}
+
+impl pallet_timestamp::Config for Test {
+    type Moment = u64;
+    type OnTimestampSet = ();
+    type MinimumPeriod = ConstU64<1>;
+    type WeightInfo = ();
+}
+
+parameter_types! {
+    /// Algorithm the current block is mined with
+    pub static MiningAlgo: AlgoId = Md5::ID;
+    pub const TargetBlockTime: u128 = 1_000;
+    pub static DominancePenalty: Perbill = Perbill::from_percent(5);
+    pub DefaultInitialDifficulty: U256 = U256::from(1_000_000);
+}
+
+impl multi_algo::Config for Test {
+    type RecentBlocks = ConstU32<10>;
+
+    fn mining_algo() -> AlgoId {
+        MiningAlgo::get()
+    }
+}
+
+impl difficulty::Config for Test {
+    type TimeProvider = Timestamp;
+    type TargetBlockTime = multi_algo::ScaledTargetBlockTime<Test, TargetBlockTime>;
+    type DifficultyAdjustment = difficulty::Kulupu<ConstU128<3>, ConstU128<2>, ConstU128<3>>;
+    type DifficultyWindow = ConstU32<10>;
+    type MaxDifficulty = ConstU128<{ u128::MAX }>;
+    type MinDifficulty = ConstU128<3>;
+    type EmergencyMultiple = ConstU32<6>;
+    type AlgoDominance = MultiAlgo;
+    type DominancePenalty = DominancePenalty;
+    type DefaultInitialDifficulty = DefaultInitialDifficulty;
+
+    fn mining_algo() -> AlgoId {
+        MiningAlgo::get()
+    }
+}
+
+/// Key pair of a test account, derived from `seed`
+pub fn pair(seed: u8) -> sr25519::Pair {
+    sr25519::Pair::from_seed(&[seed; 32])
+}
+
+/// Public key of the account of `seed`, as UTXOs are locked to it
+pub fn owner(seed: u8) -> H256 {
+    H256(pair(seed).public().0)
+}
+
+/// Mines the next block with `algo`, `ms` after the previous one, running the hooks of the
+/// algorithm balancing and the difficulty
+pub fn mine(algo: AlgoId, ms: u64) {
+    MiningAlgo::set(algo);
+    System::set_block_number(System::block_number() + 1);
+    Timestamp::set_timestamp(Timestamp::get() + ms);
+    Difficulty::on_finalize(System::block_number());
+    MultiAlgo::on_finalize(System::block_number());
+}
+
+/// Externalities with the given native UTXOs in genesis, at block 1
+pub fn new_test_ext(genesis_utxos: Vec<(Value, H256)>) -> sp_io::TestExternalities {
+    let storage = RuntimeGenesisConfig {
+        utxo: utxo::GenesisConfig {
+            genesis_utxos,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+    .build_storage()
+    .unwrap();
+
+    let mut ext = sp_io::TestExternalities::new(storage);
+    ext.execute_with(|| System::set_block_number(1));
+    ext
+}
@@ -0,0 +1,208 @@
+//! Balance between the mining algorithms, in the style of Myriad and DigiByte.
+//!
+//! Each algorithm's difficulty retargets on its own, so with miners on every algorithm the chain
+//! would produce blocks once per target block time per algorithm. This pallet remembers the
+//! algorithm of the latest blocks. With `N` algorithms active among them, each algorithm targets
+//! `N` times the block time, which keeps the block time of the whole chain on target. An
+//! algorithm mining more than its fair share of the latest blocks is dominant, and the
+//! difficulty pallet raises its difficulty accordingly.
+
+use core::marker::PhantomData;
+
+use frame_support::traits::Get;
+use multi_pow::AlgoId;
+use sp_runtime::{traits::Convert, Perbill};
+use sp_std::vec::Vec;
+
+pub use pallet::*;
+
+#[frame_support::pallet(dev_mode)]
+pub mod pallet {
+    use frame_support::pallet_prelude::*;
+    use frame_system::pallet_prelude::*;
+
+    use super::*;
+
+    #[pallet::pallet]
+    pub struct Pallet<T>(_);
+
+    #[pallet::config]
+    pub trait Config: frame_system::Config {
+        /// Number of latest blocks the activity and share of the algorithms are measured on
+        #[pallet::constant]
+        type RecentBlocks: Get<u32>;
+
+        /// The algorithm the current block is mined with
+        fn mining_algo() -> AlgoId;
+    }
+
+    /// Algorithms of the latest blocks, from earliest to latest. The current block is added
+    /// once finalized.
+    #[pallet::storage]
+    pub type RecentAlgos<T: Config> =
+        StorageValue<_, BoundedVec<AlgoId, T::RecentBlocks>, ValueQuery>;
+
+    #[pallet::hooks]
+    impl<T: Config> Hooks<BlockNumberFor<T>> for Pallet<T> {
+        // The weight of `on_finalize`
+        fn on_initialize(_n: BlockNumberFor<T>) -> Weight {
+            T::DbWeight::get().reads_writes(1, 1)
+        }
+
+        // Recorded after the difficulties retarget, the pallet comes after the difficulty
+        // pallet in the runtime. The retarget then sees the recent algorithms the node sees on
+        // the parent state when it checks the difficulty of the block.
+        fn on_finalize(_n: BlockNumberFor<T>) {
+            let algo = T::mining_algo();
+            RecentAlgos::<T>::mutate(|algos| {
+                if algos.is_full() && !algos.is_empty() {
+                    algos.remove(0);
+                }
+                // Can only fail with no recent blocks to remember
+                let _ = algos.try_push(algo);
+            });
+        }
+    }
+
+    impl<T: Config> Pallet<T> {
+        /// Number of algorithms that mined some of the latest blocks, at least one
+        pub fn active_algos() -> u32 {
+            let mut active: Vec<AlgoId> = Vec::new();
+            for algo in RecentAlgos::<T>::get() {
+                if !active.contains(&algo) {
+                    active.push(algo);
+                }
+            }
+
+            (active.len() as u32).max(1)
+        }
+
+        /// How far beyond its fair share of the latest blocks `algo` mined, from its fair
+        /// share or less to all of them
+        pub fn dominance(algo: AlgoId) -> Perbill {
+            let recent = RecentAlgos::<T>::get();
+            let total = recent.len() as u32;
+            let mined = recent.iter().filter(|a| **a == algo).count() as u32;
+            let active = Self::active_algos();
+
+            if active <= 1 || mined * active <= total {
+                return Perbill::zero();
+            }
+
+            // The share beyond the fair `1 / active`, relative to the most there can be:
+            // (mined / total - 1 / active) / (1 - 1 / active)
+            Perbill::from_rational(mined * active - total, total * (active - 1))
+        }
+    }
+}
+
+/// `TargetBlockTime` multiplied by the number of active algorithms, the block time each
+/// algorithm should keep
+pub struct ScaledTargetBlockTime<T, TargetBlockTime>(PhantomData<(T, TargetBlockTime)>);
+
+impl<T: Config, TargetBlockTime: Get<u128>> Get<u128>
+    for ScaledTargetBlockTime<T, TargetBlockTime>
+{
+    fn get() -> u128 {
+        TargetBlockTime::get().saturating_mul(Pallet::<T>::active_algos() as u128)
+    }
+}
+
+/// Dominance of each algorithm over the latest blocks
+impl<T: Config> Convert<AlgoId, Perbill> for Pallet<T> {
+    fn convert(algo: AlgoId) -> Perbill {
+        Pallet::<T>::dominance(algo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use multi_pow::{
+        hashes::{Md5, Sha3},
+        PowHash,
+    };
+
+    use super::*;
+    use crate::{
+        difficulty,
+        mock::{mine, new_test_ext, DominancePenalty, MultiAlgo, Test},
+    };
+
+    type AlgoBlockTime = ScaledTargetBlockTime<Test, crate::mock::TargetBlockTime>;
+
+    #[test]
+    fn block_time_scales_with_the_active_algorithms() {
+        new_test_ext(vec![]).execute_with(|| {
+            assert_eq!(MultiAlgo::active_algos(), 1);
+            assert_eq!(AlgoBlockTime::get(), 1_000);
+
+            mine(Md5::ID, 1_000);
+            mine(Md5::ID, 1_000);
+            assert_eq!(AlgoBlockTime::get(), 1_000);
+
+            mine(Sha3::ID, 1_000);
+            assert_eq!(MultiAlgo::active_algos(), 2);
+            assert_eq!(AlgoBlockTime::get(), 2_000);
+        });
+    }
+
+    #[test]
+    fn algorithms_leave_the_count_once_out_of_the_recent_blocks() {
+        new_test_ext(vec![]).execute_with(|| {
+            mine(Sha3::ID, 1_000);
+            for _ in 0..9 {
+                mine(Md5::ID, 1_000);
+            }
+            assert_eq!(MultiAlgo::active_algos(), 2);
+
+            mine(Md5::ID, 1_000);
+            assert_eq!(RecentAlgos::<Test>::get().len(), 10);
+            assert_eq!(MultiAlgo::active_algos(), 1);
+        });
+    }
+
+    #[test]
+    fn dominance_is_the_share_beyond_the_fair_one() {
+        new_test_ext(vec![]).execute_with(|| {
+            // Alone, an algorithm has all the blocks as its fair share
+            mine(Md5::ID, 1_000);
+            assert_eq!(MultiAlgo::dominance(Md5::ID), Perbill::zero());
+
+            // An even split has nobody dominant
+            mine(Sha3::ID, 1_000);
+            assert_eq!(MultiAlgo::dominance(Md5::ID), Perbill::zero());
+            assert_eq!(MultiAlgo::dominance(Sha3::ID), Perbill::zero());
+
+            // 3 out of 4 blocks with 2 algorithms, half way from the fair half to all of them
+            mine(Md5::ID, 1_000);
+            mine(Md5::ID, 1_000);
+            assert_eq!(MultiAlgo::dominance(Md5::ID), Perbill::from_percent(50));
+            assert_eq!(MultiAlgo::dominance(Sha3::ID), Perbill::zero());
+        });
+    }
+
+    #[test]
+    fn a_dominant_algorithm_retargets_harder() {
+        // The difficulty of md5 after a block mined under the given penalty, with md5 behind 3
+        // of the 4 blocks before it
+        let difficulty_with = |penalty| {
+            new_test_ext(vec![]).execute_with(|| {
+                DominancePenalty::set(Perbill::zero());
+                mine(Sha3::ID, 1_000);
+                mine(Md5::ID, 1_000);
+                mine(Md5::ID, 1_000);
+                mine(Md5::ID, 1_000);
+                assert_eq!(MultiAlgo::dominance(Md5::ID), Perbill::from_percent(50));
+                DominancePenalty::set(penalty);
+                mine(Md5::ID, 1_000);
+
+                difficulty::Pallet::<Test>::difficulty(Md5::ID)
+            })
+        };
+
+        let unpenalized = difficulty_with(Perbill::zero());
+        // Half the 5% penalty for half the dominance
+        let penalized = difficulty_with(Perbill::from_percent(5));
+        assert_eq!(penalized, unpenalized + unpenalized / 40);
+    }
+}
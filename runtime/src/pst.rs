@@ -0,0 +1,303 @@
+//! Partially signed transactions (PST), a container in the spirit of Bitcoin's PSBT.
+//!
+//! A PST carries everything a signer needs to check what it signs without access to the
+//! chain: the unsigned transaction, the outputs spent by its inputs and, for HD wallets,
+//! where the owning keys derive from. Signers add their signatures independently, the
+//! results are combined, and once every input is signed the PST is finalized and the
+//! transaction extracted for submission.
+//!
+//! PSTs are exchanged as `0x`-prefixed hex of `MAGIC` followed by the SCALE encoded `Pst`.
+//! The `version` field is checked on decoding so the format can evolve.
+//!
+//! Only available with the `std` feature.
+
+use std::{collections::BTreeMap, fmt};
+
+use parity_scale_codec::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use sp_core::{
+    bytes,
+    sr25519::{Pair as Sr25519Pair, Public, Signature},
+    Pair, H256, H512,
+};
+
+use crate::{
+    utxo::{get_simple_transaction, Transaction, TransactionOutput},
+    wallet::{HdWallet, KeyChain, UnsignedTransaction},
+};
+
+/// Prefix of every encoded PST
+pub const MAGIC: [u8; 4] = *b"pst\xff";
+
+/// Version of the PST format produced by this code
+pub const PST_VERSION: u32 = 0;
+
+/// Where the key owning an input derives from, see `HdWallet`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct DerivationHint {
+    /// Public key of the HD wallet account
+    pub account: H256,
+    /// Branch of the key
+    pub chain: KeyChain,
+    /// Index of the key in its branch
+    pub index: u32,
+}
+
+/// Everything known about one input of a PST
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct PstInput {
+    /// The output spent by the input
+    pub prevout: TransactionOutput,
+    /// Signatures over the signing payload, by public key
+    pub partial_signatures: BTreeMap<H256, H512>,
+    /// Where the key owning `prevout` derives from, if known
+    pub derivation: Option<DerivationHint>,
+}
+
+/// A partially signed transaction
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Pst {
+    /// Version of the format, `PST_VERSION`
+    pub version: u32,
+    /// The transaction. Sigscripts stay zero until the PST is finalized.
+    pub transaction: Transaction,
+    /// Per-input data, in the order of `transaction.inputs`
+    pub inputs: Vec<PstInput>,
+}
+
+/// Errors that can occur when handling a PST
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Not a hex encoded PST
+    Encoding(String),
+    /// The PST was produced by an unsupported version of the format
+    UnsupportedVersion(u32),
+    /// The number of per-input entries doesn't match the number of inputs
+    InputsMismatch,
+    /// Combined PSTs don't describe the same transaction
+    DifferentTransactions,
+    /// The owner of the input at this index hasn't signed yet
+    MissingSignature(usize),
+    /// The signature of the input at this index doesn't verify
+    InvalidSignature(usize),
+    /// The PST must be finalized first
+    NotFinalized,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Encoding(e) => write!(f, "invalid PST encoding: {e}"),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported PST version {v}"),
+            Error::InputsMismatch => write!(f, "PST inputs don't match the transaction inputs"),
+            Error::DifferentTransactions => write!(f, "PSTs describe different transactions"),
+            Error::MissingSignature(i) => write!(f, "input {i} is not signed"),
+            Error::InvalidSignature(i) => write!(f, "signature of input {i} is invalid"),
+            Error::NotFinalized => write!(f, "PST is not finalized"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<UnsignedTransaction> for Pst {
+    fn from(unsigned: UnsignedTransaction) -> Self {
+        let mut transaction = unsigned.transaction;
+        for input in transaction.inputs.iter_mut() {
+            input.sigscript = H512::zero();
+        }
+
+        Pst {
+            version: PST_VERSION,
+            transaction,
+            inputs: unsigned
+                .prevouts
+                .into_iter()
+                .map(|prevout| PstInput {
+                    prevout,
+                    partial_signatures: BTreeMap::new(),
+                    derivation: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Pst {
+    /// Decode a PST from its `0x`-prefixed hex encoding
+    pub fn from_hex(s: &str) -> Result<Self, Error> {
+        let bytes = bytes::from_hex(s.trim()).map_err(|e| Error::Encoding(e.to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Decode a PST from `MAGIC` followed by its SCALE encoding
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut payload = bytes
+            .strip_prefix(&MAGIC[..])
+            .ok_or_else(|| Error::Encoding("missing PST magic".into()))?;
+        let version = u32::decode(&mut &payload[..]).map_err(|e| Error::Encoding(e.to_string()))?;
+        if version != PST_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let pst = Pst::decode(&mut payload).map_err(|e| Error::Encoding(e.to_string()))?;
+        if pst.inputs.len() != pst.transaction.inputs.len() {
+            return Err(Error::InputsMismatch);
+        }
+
+        Ok(pst)
+    }
+
+    /// `MAGIC` followed by the SCALE encoding of the PST
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        self.encode_to(&mut bytes);
+        bytes
+    }
+
+    /// `0x`-prefixed hex encoding of the PST
+    pub fn to_hex(&self) -> String {
+        bytes::to_hex(&self.to_bytes(), false)
+    }
+
+    /// The payload every input owner signs, as checked by the utxo pallet
+    pub fn signing_payload(&self) -> Vec<u8> {
+        get_simple_transaction(&self.transaction)
+    }
+
+    /// Record where the keys of `wallet` owning inputs derive from, so signers holding
+    /// only the seed can find them
+    pub fn add_derivation_hints(&mut self, wallet: &HdWallet) {
+        let keys = wallet.keys();
+        for input in self.inputs.iter_mut() {
+            if let Some((chain, index, _)) = keys
+                .iter()
+                .find(|(_, _, public)| *public == input.prevout.pubkey)
+            {
+                input.derivation = Some(DerivationHint {
+                    account: wallet.account,
+                    chain: *chain,
+                    index: *index,
+                });
+            }
+        }
+    }
+
+    /// Sign every input whose owner `signer` holds a key for. `signer` is given the owner
+    /// and the payload, and returns `None` if it doesn't hold the owner's key.
+    ///
+    /// Returns the number of inputs signed.
+    pub fn sign_with(&mut self, mut signer: impl FnMut(&H256, &[u8]) -> Option<H512>) -> usize {
+        let payload = self.signing_payload();
+        let mut signed = 0;
+        for input in self.inputs.iter_mut() {
+            let owner = input.prevout.pubkey;
+            if let Some(signature) = signer(&owner, &payload) {
+                input.partial_signatures.insert(owner, signature);
+                signed += 1;
+            }
+        }
+
+        signed
+    }
+
+    /// Sign every input owned by one of `keys`, returning the number of inputs signed
+    pub fn sign(&mut self, keys: &[Sr25519Pair]) -> usize {
+        self.sign_with(|owner, payload| {
+            keys.iter()
+                .find(|pair| H256::from(pair.public()) == *owner)
+                .map(|pair| H512::from_slice(pair.sign(payload).as_ref()))
+        })
+    }
+
+    /// Sign the inputs whose derivation hints point into the HD wallet of `account`,
+    /// returning the number of inputs signed
+    pub fn sign_with_account(&mut self, account: &Sr25519Pair) -> usize {
+        let account_public = H256::from(account.public());
+        let keys: Vec<Sr25519Pair> = self
+            .inputs
+            .iter()
+            .filter_map(|input| input.derivation)
+            .filter(|hint| hint.account == account_public)
+            .map(|hint| HdWallet::pair(account, hint.chain, hint.index))
+            .collect();
+        self.sign(&keys)
+    }
+
+    /// Merge the signatures and derivation hints of `other`, which must describe the
+    /// same transaction
+    pub fn combine(&mut self, other: Pst) -> Result<(), Error> {
+        if other.version != self.version
+            || other.signing_payload() != self.signing_payload()
+            || other.inputs.len() != self.inputs.len()
+            || other
+                .inputs
+                .iter()
+                .zip(self.inputs.iter())
+                .any(|(theirs, ours)| theirs.prevout != ours.prevout)
+        {
+            return Err(Error::DifferentTransactions);
+        }
+
+        for (ours, theirs) in self.inputs.iter_mut().zip(other.inputs) {
+            ours.partial_signatures.extend(theirs.partial_signatures);
+            ours.derivation = ours.derivation.or(theirs.derivation);
+        }
+
+        Ok(())
+    }
+
+    /// Whether every input owner has signed
+    pub fn is_complete(&self) -> bool {
+        self.inputs
+            .iter()
+            .all(|input| input.partial_signatures.contains_key(&input.prevout.pubkey))
+    }
+
+    /// Check the signature of every input owner and move it into the sigscripts
+    pub fn finalize(&mut self) -> Result<(), Error> {
+        if self.inputs.len() != self.transaction.inputs.len() {
+            return Err(Error::InputsMismatch);
+        }
+
+        let payload = self.signing_payload();
+        let mut sigscripts = Vec::with_capacity(self.inputs.len());
+        for (index, input) in self.inputs.iter().enumerate() {
+            let owner = input.prevout.pubkey;
+            let signature = input
+                .partial_signatures
+                .get(&owner)
+                .ok_or(Error::MissingSignature(index))?;
+            if !Sr25519Pair::verify(
+                &Signature::from_raw(signature.0),
+                &payload,
+                &Public::from_h256(owner),
+            ) {
+                return Err(Error::InvalidSignature(index));
+            }
+            sigscripts.push(*signature);
+        }
+
+        for (input, sigscript) in self.transaction.inputs.iter_mut().zip(sigscripts) {
+            input.sigscript = sigscript;
+        }
+
+        Ok(())
+    }
+
+    /// Whether `finalize` succeeded
+    pub fn is_finalized(&self) -> bool {
+        self.transaction
+            .inputs
+            .iter()
+            .all(|input| input.sigscript != H512::zero())
+    }
+
+    /// The signed transaction of a finalized PST, ready for submission
+    pub fn extract(self) -> Result<Transaction, Error> {
+        if !self.is_finalized() {
+            return Err(Error::NotFinalized);
+        }
+
+        Ok(self.transaction)
+    }
+}
@@ -6,6 +6,7 @@ use scale_info::TypeInfo;
 #[cfg(feature = "std")]
 use serde::{Deserialize, Serialize};
 use sp_core::{
+    crypto::KeyTypeId,
     sr25519::{Public, Signature},
     ByteArray, H256, H512,
 };
@@ -16,8 +17,30 @@ use super::{block_author::BlockAuthor, issuance::Issuance};
 
 pub type Value = u128;
 
+/// Key type under which keystores hold the `sr25519` keys owning UTXOs
+pub const KEY_TYPE: KeyTypeId = KeyTypeId(*b"utxo");
+
+/// Identifier of an asset carried by a UTXO.
+/// The all-zero id is reserved for the native asset.
+pub type AssetId = H256;
+
+/// The native asset. Block rewards are paid and transaction fees are charged in it.
+pub const NATIVE_ASSET: AssetId = H256([0u8; 32]);
+
 pub use pallet::*;
 
+sp_api::decl_runtime_apis! {
+    /// Queries of the UTXO set for off-chain clients
+    pub trait UtxoApi {
+        /// Outpoint currently holding the non-fungible token `id`
+        fn nft_holder(id: H256) -> Option<H256>;
+        /// All unspent outputs locked to `owner`, along with their outpoints
+        fn utxos_of(owner: H256) -> Vec<(H256, TransactionOutput)>;
+        /// The unspent output at `outpoint`, if any
+        fn utxo(outpoint: H256) -> Option<TransactionOutput>;
+    }
+}
+
 /// Single transaction to be dispatched
 #[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
 #[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Encode, Decode, Hash, Debug, TypeInfo)]
@@ -51,13 +74,123 @@ pub struct TransactionOutput {
     /// Public key associated with this output. In order to spend this output
     /// owner must provide a proof by hashing the whole `Transaction` and
     /// signing it with a corresponding private key.
+    /// In JSON it may also be given as an address, see `crate::address`.
+    #[cfg_attr(
+        feature = "std",
+        serde(deserialize_with = "crate::address::deserialize_pubkey")
+    )]
     pub pubkey: H256,
+
+    /// Asset carried by this output, the native asset unless stated otherwise
+    #[cfg_attr(feature = "std", serde(default))]
+    pub asset: AssetId,
+
+    /// Non-fungible token riding along with the value of this output
+    #[cfg_attr(feature = "std", serde(default))]
+    pub nft: Option<NonFungible>,
 }
 
+/// A 1-of-1 token. Its id stays the same as it moves from output to output.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Encode, Decode, Hash, Debug, TypeInfo)]
+pub struct NonFungible {
+    /// Unique token id, see `nft_id`
+    pub id: H256,
+
+    /// Hash of the off-chain metadata, fixed when the token is minted
+    pub metadata: H256,
+}
+
+/// Issuance of a non-native asset, dispatched along with the `Transaction` minting it.
+/// Outputs of the issued asset are exempt from conservation checks in that transaction.
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Default, Clone, Encode, Decode, Hash, Debug, TypeInfo)]
+pub struct AssetIssuance {
+    /// Asset to mint more of. `None` registers a new asset whose id is
+    /// derived from the first input of the minting transaction.
+    pub asset: Option<AssetId>,
+
+    /// Public key controlling the asset. Only this key may mint it.
+    pub issuer: H256,
+
+    /// Issuer's signature over `get_issuance_payload`
+    pub signature: H512,
+}
+
+/// An output as reported by the events of the pallet
+#[cfg_attr(feature = "std", derive(Serialize, Deserialize))]
+#[derive(PartialEq, Eq, Clone, Encode, Decode, Debug, TypeInfo)]
+pub struct CreatedOutput {
+    /// Key of the output in `UtxoStore`
+    pub outpoint: H256,
+    /// Public key the output is locked to
+    pub owner: H256,
+    /// Value of the output
+    pub value: Value,
+    /// Asset of the output
+    pub asset: AssetId,
+}
+
+impl CreatedOutput {
+    /// Report of `output`, stored at `outpoint`
+    pub fn new(outpoint: H256, output: &TransactionOutput) -> Self {
+        Self {
+            outpoint,
+            owner: output.pubkey,
+            value: output.value,
+            asset: output.asset,
+        }
+    }
+}
+
+/// Strips a transaction of its Signature fields by replacing value with ZERO-initialized fixed hash.
+/// This is the payload signed by the owner of every input.
+pub fn get_simple_transaction(transaction: &Transaction) -> Vec<u8> {
+    let mut trx = transaction.clone();
+    for input in trx.inputs.iter_mut() {
+        input.sigscript = H512::zero();
+    }
+
+    trx.encode()
+}
+
+/// Payload signed by the issuer of `issuance`: the payload of the inputs followed by the
+/// asset and the issuer, so neither can be swapped without a new signature.
+pub fn get_issuance_payload(transaction: &Transaction, issuance: &AssetIssuance) -> Vec<u8> {
+    let mut payload = get_simple_transaction(transaction);
+    (issuance.asset, issuance.issuer).encode_to(&mut payload);
+    payload
+}
+
+/// Id of a transaction, the hash of the whole transaction including its sigscripts.
+/// Outpoints created by a transaction are derived from the same encoding.
+pub fn txid(transaction: &Transaction) -> H256 {
+    BlakeTwo256::hash_of(transaction)
+}
+
+/// Outpoint of the output at `index` of `transaction`
+pub fn outpoint(transaction: &Transaction, index: u64) -> H256 {
+    BlakeTwo256::hash_of(&(&transaction.encode(), index))
+}
+
+/// Id of the non-fungible token minted at `index` of the outputs of a transaction
+/// whose first input spends `minting_outpoint`.
+pub fn nft_id(minting_outpoint: &H256, index: u64) -> H256 {
+    BlakeTwo256::hash_of(&(b"nft", minting_outpoint, index))
+}
+
+/// Id of the asset that a new issuance made by `transaction` registers.
+/// The first input is consumed by the transaction, so the id can never be derived twice.
+pub fn new_asset_id(transaction: &Transaction) -> Option<AssetId> {
+    transaction
+        .inputs
+        .first()
+        .map(|input| BlakeTwo256::hash_of(&(b"asset", input.outpoint)))
+}
 
 /// Because code is built on `no-std` feature.
 /// And we got error:
-/// ```
+/// ```text
 /// ...
 /// the trait `Serialize` is not implemented for `TransactionOutput`
 /// the trait `Deserialize<'_>` is not implemented for `TransactionOutput`
@@ -85,9 +218,22 @@ pub mod pallet {
 
         /// A source to determine the issuance portion of the block reward
         type Issuance: Issuance<BlockNumberFor<Self>, Value>;
+
+        /// Number of blocks a valid transaction may wait in the pool before it is dropped
+        #[pallet::constant]
+        type Longevity: Get<TransactionLongevity>;
+
+        /// Number of blocks a transaction spending outpoints that don't exist yet may wait
+        /// in the pool. Shorter than `Longevity`, as the outpoints may never be created.
+        #[pallet::constant]
+        type OrphanLongevity: Get<TransactionLongevity>;
     }
 
+    /// The in-code storage version.
+    const STORAGE_VERSION: StorageVersion = StorageVersion::new(2);
+
     #[pallet::pallet]
+    #[pallet::storage_version(STORAGE_VERSION)]
     pub struct Pallet<T>(_);
 
     /// Total reward value to be redistributed among authorities.
@@ -120,6 +266,33 @@ pub mod pallet {
     pub type UtxoOf<T: Config> =
         StorageMap<Hasher = Identity, Key = Public, Value = H256, QueryKind = OptionQuery>;
 
+    /// Outpoints of the unspent outputs locked to each key, so they are listed without
+    /// scanning `UtxoStore`.
+    /// Mapping from the owner's `sr25519::Pubkey` and an outpoint to nothing
+    #[pallet::storage]
+    pub type UtxosByOwner<T: Config> = StorageDoubleMap<
+        Hasher1 = Identity,
+        Key1 = H256,
+        Hasher2 = Identity,
+        Key2 = H256,
+        Value = (),
+        QueryKind = OptionQuery,
+    >;
+
+    /// Non-native assets issued so far.
+    /// Mapping from `AssetId` to the issuer's `sr25519::Pubkey`
+    #[pallet::storage]
+    #[pallet::getter(fn asset_issuer)]
+    pub type Assets<T: Config> =
+        StorageMap<Hasher = Identity, Key = AssetId, Value = H256, QueryKind = OptionQuery>;
+
+    /// Current location of every non-fungible token.
+    /// Mapping from token id to the outpoint holding it
+    #[pallet::storage]
+    #[pallet::getter(fn nft_holder)]
+    pub type NftHolder<T: Config> =
+        StorageMap<Hasher = Identity, Key = H256, Value = H256, QueryKind = OptionQuery>;
+
     #[pallet::genesis_config]
     pub struct GenesisConfig<T: Config> {
         pub _ph_data: Option<PhantomData<T>>,
@@ -130,7 +303,12 @@ pub mod pallet {
     impl<T: Config> BuildGenesisConfig for GenesisConfig<T> {
         fn build(&self) {
             for utxo in self.genesis_utxos.iter() {
-                let utxo = TransactionOutput { value: utxo.0, pubkey: utxo.1 };
+                let utxo = TransactionOutput {
+                    value: utxo.0,
+                    pubkey: utxo.1,
+                    asset: NATIVE_ASSET,
+                    nft: None,
+                };
                 let hash = BlakeTwo256::hash_of(&utxo);
                 Pallet::<T>::store_utxo(&utxo, hash);
             }
@@ -148,19 +326,40 @@ pub mod pallet {
 
     /// Pallets use events to inform users when important changes are made.
     /// https://paritytech.github.io/polkadot-sdk/master/polkadot_sdk_docs/guides/your_first_pallet/index.html#event-and-error
+    ///
+    /// Runtimes up to spec version 1 emitted the first four variants, later ones emit
+    /// `Transferred`, `Coinbase` and `FaucetDeposited` in their place. The superseded variants
+    /// keep their indices so the events of blocks from before the upgrade still decode.
     #[pallet::event]
     #[pallet::generate_deposit(pub(super) fn deposit_event)]
     pub enum Event<T: Config> {
-        /// Dispatch transaction successful
+        /// Dispatch transaction successful. Superseded by `Transferred`.
         TransactionSuccess(Transaction),
-        /// UTXO out processed
+        /// UTXO out processed. Superseded by `Transferred`.
         TransactionOutputProcessed(H256),
-        /// Reward distributed to `BlockAuthor`
+        /// Reward distributed to `BlockAuthor`. Superseded by `Coinbase`.
         RewardDistributed(Value, H256),
-        /// Faucet to `To`
+        /// Faucet to `To`. Superseded by `FaucetDeposited`.
         Faucet(Value, H256),
         /// No one get reward
         RewardWasted,
+        /// New asset registered with its issuer
+        AssetIssued(AssetId, H256),
+        /// A transaction was dispatched, spending `spent` and creating `created`
+        Transferred {
+            txid: H256,
+            spent: Vec<H256>,
+            created: Vec<CreatedOutput>,
+            fee: Value,
+        },
+        /// The block author was paid the block subsidy and the fees of the block
+        Coinbase {
+            output: CreatedOutput,
+            subsidy: Value,
+            fees: Value,
+        },
+        /// The faucet created an output
+        FaucetDeposited { output: CreatedOutput },
     }
 
     /// Errors inform users that something went wrong.
@@ -191,6 +390,76 @@ pub mod pallet {
         ZeroAmount,
         /// Invalid signature
         InvalidSignature,
+        /// Non-native asset amounts must be equal on both sides of a transaction
+        AssetNotConserved,
+        /// Referenced asset has not been issued
+        UnknownAsset,
+        /// The native asset cannot be issued
+        NativeIssuance,
+        /// Issuance is not signed by the asset's issuer
+        InvalidIssuer,
+        /// Non-fungible token id is neither spent nor minted by the transaction
+        InvalidNftId,
+        /// Non-fungible token input must map to exactly one output with the same id and metadata
+        NftNotTransferred,
+        /// The asset a new issuance would register has been issued already
+        AssetExists,
+    }
+
+    impl<T> Error<T> {
+        /// Stable code of the error, reported as `InvalidTransaction::Custom` when the
+        /// transaction pool rejects a transaction. Codes are never reused: new errors get
+        /// new codes.
+        pub fn code(&self) -> u8 {
+            match self {
+                Error::MissingInput => 1,
+                Error::RewardOverflow => 2,
+                Error::MaximumTransactionDepth => 3,
+                Error::EmptyInput => 4,
+                Error::EmptyOutput => 5,
+                Error::DuplicatedInput => 6,
+                Error::DuplicatedOutput => 7,
+                Error::InputOverflow => 8,
+                Error::OutputOverflow => 9,
+                Error::OutputOverInput => 10,
+                Error::ZeroAmount => 11,
+                Error::InvalidSignature => 12,
+                Error::AssetNotConserved => 13,
+                Error::UnknownAsset => 14,
+                Error::NativeIssuance => 15,
+                Error::InvalidIssuer => 16,
+                Error::InvalidNftId => 17,
+                Error::NftNotTransferred => 18,
+                Error::AssetExists => 19,
+                _ => 0,
+            }
+        }
+
+        /// The error reported under `code`, if any
+        pub fn from_code(code: u8) -> Option<Self> {
+            Some(match code {
+                1 => Error::MissingInput,
+                2 => Error::RewardOverflow,
+                3 => Error::MaximumTransactionDepth,
+                4 => Error::EmptyInput,
+                5 => Error::EmptyOutput,
+                6 => Error::DuplicatedInput,
+                7 => Error::DuplicatedOutput,
+                8 => Error::InputOverflow,
+                9 => Error::OutputOverflow,
+                10 => Error::OutputOverInput,
+                11 => Error::ZeroAmount,
+                12 => Error::InvalidSignature,
+                13 => Error::AssetNotConserved,
+                14 => Error::UnknownAsset,
+                15 => Error::NativeIssuance,
+                16 => Error::InvalidIssuer,
+                17 => Error::InvalidNftId,
+                18 => Error::NftNotTransferred,
+                19 => Error::AssetExists,
+                _ => return None,
+            })
+        }
     }
 
     #[pallet::hooks]
@@ -207,8 +476,16 @@ pub mod pallet {
 
     #[pallet::call]
     impl<T: Config> Pallet<T> {
-        pub fn spend(_origin: OriginFor<T>, transaction: Transaction) -> DispatchResult {
-            let transaction_validity = Self::validate_transaction(&transaction)?;
+        /// Spend UTXOs. Submitted unsigned: the sigscripts of the inputs are the authorization.
+        ///
+        /// The signatures are not verified again here. Block execution dispatches unsigned
+        /// calls through `ValidateUnsigned::pre_dispatch` only, which runs every check of the
+        /// pool, signatures included, so this call must never be dispatched any other way.
+        pub fn spend(origin: OriginFor<T>, transaction: Transaction) -> DispatchResult {
+            ensure_none(origin)?;
+
+            // `pre_dispatch` verified the signatures, see the invariant of `spend`
+            let transaction_validity = Self::check_transaction(&transaction, None, false)?;
             ensure!(
                 transaction_validity.requires.is_empty(),
                 Error::<T>::MissingInput
@@ -216,8 +493,6 @@ pub mod pallet {
 
             Self::update_storage(&transaction, transaction_validity.priority as Value)?;
 
-            Self::deposit_event(Event::<T>::TransactionSuccess(transaction));
-
             Ok(())
         }
 
@@ -225,11 +500,75 @@ pub mod pallet {
             let _ = Self::deposit_creating(&to, value);
             Ok(())
         }
+
+        /// Spend UTXOs while minting an asset. Submitted unsigned like `spend`, and relying on
+        /// `pre_dispatch` for the signatures the same way.
+        pub fn issue(
+            origin: OriginFor<T>,
+            transaction: Transaction,
+            issuance: AssetIssuance,
+        ) -> DispatchResult {
+            ensure_none(origin)?;
+
+            // `pre_dispatch` verified the signatures, see the invariant of `spend`
+            let transaction_validity = Self::check_issuance(&transaction, &issuance, false)?;
+            ensure!(
+                transaction_validity.requires.is_empty(),
+                Error::<T>::MissingInput
+            );
+
+            let asset = match issuance.asset {
+                Some(asset) => asset,
+                None => new_asset_id(&transaction).ok_or(Error::<T>::EmptyInput)?,
+            };
+
+            Self::update_storage(&transaction, transaction_validity.priority as Value)?;
+
+            if !Assets::<T>::contains_key(asset) {
+                Assets::<T>::insert(asset, issuance.issuer);
+                Self::deposit_event(Event::<T>::AssetIssued(asset, issuance.issuer));
+            }
+
+            Ok(())
+        }
+    }
+
+    #[pallet::validate_unsigned]
+    impl<T: Config> ValidateUnsigned for Pallet<T> {
+        type Call = Call<T>;
+
+        /// Pool validation of `spend` and `issue`. Spends of outpoints that don't exist yet are
+        /// valid, they require the missing outpoints to be provided first.
+        fn validate_unsigned(_source: TransactionSource, call: &Self::Call) -> TransactionValidity {
+            let validity = match call {
+                Call::spend { transaction } => Self::validate_transaction(transaction),
+                Call::issue {
+                    transaction,
+                    issuance,
+                } => Self::validate_issuance(transaction, issuance),
+                _ => return InvalidTransaction::Call.into(),
+            };
+
+            validity.map_err(|e| InvalidTransaction::Custom(e.code()).into())
+        }
+
+        /// Block execution runs the same checks as the pool, signatures included, rather than
+        /// trusting the pool of the block author. Every spent outpoint must exist by then.
+        /// Dispatch only repeats the checks that don't involve signatures.
+        fn pre_dispatch(call: &Self::Call) -> Result<(), TransactionValidityError> {
+            let valid = Self::validate_unsigned(TransactionSource::InBlock, call)?;
+            if !valid.requires.is_empty() {
+                return Err(InvalidTransaction::Custom(Error::<T>::MissingInput.code()).into());
+            }
+
+            Ok(())
+        }
     }
 
     impl<T: Config> Pallet<T> {
         /// Update storage to reflect changes made by transaction
         /// Where each utxo key is a hash of the entire transaction and its order in the TransactionOutputs vector
+        /// `reward` is the fee paid by the transaction, reported in `Event::Transferred`
         fn update_storage(transaction: &Transaction, reward: Value) -> DispatchResult {
             // Calculate new reward total
             let new_total = TotalReward::<T>::get()
@@ -239,13 +578,16 @@ pub mod pallet {
 
             // Removing spent UTXOs
             for input in &transaction.inputs {
-                UtxoStore::<T>::remove(input.outpoint);
+                if let Some(spent) = UtxoStore::<T>::take(input.outpoint) {
+                    UtxosByOwner::<T>::remove(spent.pubkey, input.outpoint);
+                }
             }
 
             let mut index: u64 = 0;
+            let mut created = Vec::with_capacity(transaction.outputs.len());
             log::info!("transaction outputs len: {:?}", transaction.outputs.len());
             for output in &transaction.outputs {
-                let hash = BlakeTwo256::hash_of(&(&transaction.encode(), index));
+                let hash = outpoint(transaction, index);
                 log::info!("hash: {:?}", hash);
                 // validated before, this is safe
                 index = index
@@ -253,20 +595,34 @@ pub mod pallet {
                     .ok_or(Error::<T>::MaximumTransactionDepth)
                     .unwrap();
                 Self::store_utxo(output, hash);
-                Self::deposit_event(Event::TransactionOutputProcessed(hash));
+                created.push(CreatedOutput::new(hash, output));
             }
 
+            Self::deposit_event(Event::Transferred {
+                txid: txid(transaction),
+                spent: transaction
+                    .inputs
+                    .iter()
+                    .map(|input| input.outpoint)
+                    .collect(),
+                created,
+                fee: reward,
+            });
+
             Ok(())
         }
 
         /// Redistribute combined reward value to block Author
         fn disperse_reward(author: &Public) {
-            let reward = TotalReward::<T>::take()
-                + T::Issuance::issuance(frame_system::Pallet::<T>::block_number());
+            let fees = TotalReward::<T>::take();
+            let subsidy = T::Issuance::issuance(frame_system::Pallet::<T>::block_number());
+            let reward = fees + subsidy;
 
             let utxo = TransactionOutput {
                 value: reward,
                 pubkey: H256::from_slice(author.as_slice()),
+                asset: NATIVE_ASSET,
+                nft: None,
             };
 
             let hash = BlakeTwo256::hash_of(&(
@@ -275,39 +631,47 @@ pub mod pallet {
             ));
 
             Self::store_utxo(&utxo, hash);
-            Self::deposit_event(Event::RewardDistributed(reward, hash));
+            Self::deposit_event(Event::Coinbase {
+                output: CreatedOutput::new(hash, &utxo),
+                subsidy,
+                fees,
+            });
         }
 
         fn deposit_creating(to: &Public, value: Value) -> DispatchResult {
             let utxo = TransactionOutput {
                 value,
                 pubkey: H256::from_slice(&to[..]),
+                asset: NATIVE_ASSET,
+                nft: None,
             };
 
             let hash = BlakeTwo256::hash_of(&(&utxo, 0));
             Self::store_utxo(&utxo, hash);
 
-            Self::deposit_event(Event::<T>::Faucet(value, hash));
+            Self::deposit_event(Event::<T>::FaucetDeposited {
+                output: CreatedOutput::new(hash, &utxo),
+            });
 
             Ok(())
         }
 
         fn store_utxo(utxo: &TransactionOutput, hash: H256) {
             UtxoStore::<T>::insert(hash, utxo);
+            UtxosByOwner::<T>::insert(utxo.pubkey, hash, ());
+            if let Some(nft) = &utxo.nft {
+                NftHolder::<T>::insert(nft.id, hash);
+            }
             // Convert H256 back to sr25519::Public
             let pubkey = Public::from_h256(utxo.pubkey);
             UtxoOf::<T>::insert(pubkey, hash);
         }
 
-        // Strips a transaction of its Signature fields by replacing value with ZERO-initialized fixed hash.
-        fn get_simple_transaction(transaction: &Transaction) -> Vec<u8> {
-            //&'a [u8] {
-            let mut trx = transaction.clone();
-            for input in trx.inputs.iter_mut() {
-                input.sigscript = H512::zero();
-            }
-
-            trx.encode()
+        /// All unspent outputs locked to `owner`, along with their outpoints
+        pub fn utxos_of(owner: H256) -> Vec<(H256, TransactionOutput)> {
+            UtxosByOwner::<T>::iter_key_prefix(owner)
+                .filter_map(|outpoint| Some((outpoint, UtxoStore::<T>::get(outpoint)?)))
+                .collect()
         }
 
         // /// Helper fn for Transaction Pool
@@ -332,14 +696,73 @@ pub mod pallet {
         /// - all inputs match to existing, unspent and unlocked outputs
         /// - each input is used exactly once
         /// - each output is defined exactly once and has nonzero value
-        /// - total output value must not exceed total input value
+        /// - total native output value must not exceed total native input value
+        /// - every other asset is conserved, since fees are paid in the native asset only
+        /// - each non-fungible token input moves to exactly one output, other tokens are new mints
         /// - new outputs do not collide with existing ones
         /// - sum of input and output values does not overflow
         /// - provided signatures are valid
         /// - transaction outputs cannot be modified by malicious nodes
         pub fn validate_transaction(
             transaction: &Transaction,
-        ) -> Result<ValidTransaction, &'static str> {
+        ) -> Result<ValidTransaction, Error<T>> {
+            Self::check_transaction(transaction, None, true)
+        }
+
+        /// Check an asset issuance for validity
+        /// Called by both transaction pool and runtime execution
+        ///
+        /// On top of the `validate_transaction` rules, ensures that:
+        /// - the native asset is never issued
+        /// - a new asset id is not taken yet, an existing one is minted by its issuer only
+        /// - the issuer signed the transaction along with the asset and the issuer fields
+        pub fn validate_issuance(
+            transaction: &Transaction,
+            issuance: &AssetIssuance,
+        ) -> Result<ValidTransaction, Error<T>> {
+            Self::check_issuance(transaction, issuance, true)
+        }
+
+        /// `validate_issuance`, verifying the signatures only if `check_signatures` is set
+        fn check_issuance(
+            transaction: &Transaction,
+            issuance: &AssetIssuance,
+            check_signatures: bool,
+        ) -> Result<ValidTransaction, Error<T>> {
+            let asset = match issuance.asset {
+                Some(asset) => {
+                    ensure!(asset != NATIVE_ASSET, Error::<T>::NativeIssuance);
+                    let issuer = Assets::<T>::get(asset).ok_or(Error::<T>::UnknownAsset)?;
+                    ensure!(issuer == issuance.issuer, Error::<T>::InvalidIssuer);
+                    asset
+                }
+                None => {
+                    let asset = new_asset_id(transaction).ok_or(Error::<T>::EmptyInput)?;
+                    ensure!(!Assets::<T>::contains_key(asset), Error::<T>::AssetExists);
+                    asset
+                }
+            };
+
+            if check_signatures {
+                let is_valid_sig = sp_io::crypto::sr25519_verify(
+                    &Signature::from_raw(*issuance.signature.as_fixed_bytes()),
+                    &get_issuance_payload(transaction, issuance),
+                    &Public::from_h256(issuance.issuer),
+                );
+                ensure!(is_valid_sig, Error::<T>::InvalidIssuer);
+            }
+
+            Self::check_transaction(transaction, Some(asset), check_signatures)
+        }
+
+        /// Shared checks of `validate_transaction` and `validate_issuance`.
+        /// Outputs of the `minted` asset are not backed by inputs. The sigscripts are verified
+        /// only if `check_signatures` is set.
+        fn check_transaction(
+            transaction: &Transaction,
+            minted: Option<AssetId>,
+            check_signatures: bool,
+        ) -> Result<ValidTransaction, Error<T>> {
             // Check basic requirements
             ensure!(!transaction.inputs.is_empty(), Error::<T>::EmptyInput);
             ensure!(!transaction.outputs.is_empty(), Error::<T>::EmptyOutput);
@@ -364,10 +787,14 @@ pub mod pallet {
                 );
             }
 
-            let mut total_input: Value = 0;
-            let mut total_output: Value = 0;
+            // Running totals per asset
+            let mut total_input: BTreeMap<AssetId, Value> = BTreeMap::new();
+            let mut total_output: BTreeMap<AssetId, Value> = BTreeMap::new();
             let mut output_index: u64 = 0;
-            let simple_transaction = Self::get_simple_transaction(transaction);
+            let simple_transaction = get_simple_transaction(transaction);
+
+            // Non-fungible tokens spent by the transaction
+            let mut input_nfts: BTreeMap<H256, H256> = BTreeMap::new();
 
             // Variables sent to transaction pool
             let mut missing_utxos = Vec::new();
@@ -377,15 +804,21 @@ pub mod pallet {
             // Check that inputs are valid
             for input in transaction.inputs.iter() {
                 if let Some(input_utxo) = UtxoStore::<T>::get(&input.outpoint) {
-                    log::info!("encoded tx: {:?}", simple_transaction);
-                    let is_valid_sig = sp_io::crypto::sr25519_verify(
-                        &Signature::from_raw(*input.sigscript.as_fixed_bytes()),
-                        &simple_transaction,
-                        &Public::from_h256(input_utxo.pubkey),
-                    );
-                    log::info!("is_valid_sig: {:?}", is_valid_sig);
-                    ensure!(is_valid_sig, Error::<T>::InvalidSignature);
-                    total_input = total_input
+                    if check_signatures {
+                        log::info!("encoded tx: {:?}", simple_transaction);
+                        let is_valid_sig = sp_io::crypto::sr25519_verify(
+                            &Signature::from_raw(*input.sigscript.as_fixed_bytes()),
+                            &simple_transaction,
+                            &Public::from_h256(input_utxo.pubkey),
+                        );
+                        log::info!("is_valid_sig: {:?}", is_valid_sig);
+                        ensure!(is_valid_sig, Error::<T>::InvalidSignature);
+                    }
+                    if let Some(nft) = input_utxo.nft {
+                        input_nfts.insert(nft.id, nft.metadata);
+                    }
+                    let total = total_input.entry(input_utxo.asset).or_default();
+                    *total = total
                         .checked_add(input_utxo.value)
                         .ok_or(Error::<T>::InputOverflow)?;
                 } else {
@@ -396,7 +829,7 @@ pub mod pallet {
             // Check that outputs are valid
             for output in transaction.outputs.iter() {
                 ensure!(output.value > 0, Error::<T>::ZeroAmount);
-                let hash = BlakeTwo256::hash_of(&(&transaction.encode(), output_index));
+                let hash = outpoint(transaction, output_index);
                 output_index = output_index
                     .checked_add(1)
                     .ok_or(Error::<T>::MaximumTransactionDepth)?;
@@ -404,7 +837,8 @@ pub mod pallet {
                     !UtxoStore::<T>::contains_key(hash),
                     Error::<T>::DuplicatedOutput
                 );
-                total_output = total_output
+                let total = total_output.entry(output.asset).or_default();
+                *total = total
                     .checked_add(output.value)
                     .ok_or(Error::<T>::OutputOverflow)?;
                 new_utxos.push(hash.as_fixed_bytes().to_vec());
@@ -412,20 +846,620 @@ pub mod pallet {
 
             // If no race condition, check the math
             if missing_utxos.is_empty() {
-                ensure!(total_input >= total_output, Error::<T>::OutputOverInput);
-                reward = total_input
-                    .checked_sub(total_output)
+                let native_input = total_input.remove(&NATIVE_ASSET).unwrap_or_default();
+                let native_output = total_output.remove(&NATIVE_ASSET).unwrap_or_default();
+                ensure!(native_input >= native_output, Error::<T>::OutputOverInput);
+                reward = native_input
+                    .checked_sub(native_output)
                     .ok_or(Error::<T>::RewardOverflow)?;
+
+                if let Some(minted) = minted {
+                    total_input.remove(&minted);
+                    total_output.remove(&minted);
+                }
+                ensure!(total_input == total_output, Error::<T>::AssetNotConserved);
+
+                let minting_outpoint = &transaction.inputs[0].outpoint;
+                for (index, output) in transaction.outputs.iter().enumerate() {
+                    let Some(nft) = &output.nft else { continue };
+                    match input_nfts.remove(&nft.id) {
+                        Some(metadata) => {
+                            ensure!(metadata == nft.metadata, Error::<T>::NftNotTransferred)
+                        }
+                        None => {
+                            ensure!(
+                                nft.id == nft_id(minting_outpoint, index as u64)
+                                    && !NftHolder::<T>::contains_key(nft.id),
+                                Error::<T>::InvalidNftId
+                            )
+                        }
+                    }
+                }
+                ensure!(input_nfts.is_empty(), Error::<T>::NftNotTransferred);
             }
 
             // Returns transaction details
+            let longevity = if missing_utxos.is_empty() {
+                T::Longevity::get()
+            } else {
+                T::OrphanLongevity::get()
+            };
             Ok(ValidTransaction {
                 requires: missing_utxos,
                 provides: new_utxos,
                 priority: reward as u64,
-                longevity: TransactionLongevity::max_value(),
+                longevity,
                 propagate: true,
             })
         }
     }
 }
+
+pub mod migrations {
+    use frame_support::{
+        pallet_prelude::*,
+        traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion},
+    };
+
+    use super::*;
+
+    /// An output before storage version 1, always of the native asset
+    #[derive(Encode, Decode)]
+    struct LegacyOutput {
+        value: Value,
+        pubkey: H256,
+    }
+
+    /// Rewrites the outputs of `UtxoStore` in the layout of storage version 1, which added
+    /// their asset and non-fungible token. Outpoints stay the same.
+    pub struct AddAssets<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for AddAssets<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if Pallet::<T>::on_chain_storage_version() >= 1 {
+                return T::DbWeight::get().reads(1);
+            }
+
+            let mut upgraded = 0u64;
+            UtxoStore::<T>::translate::<LegacyOutput, _>(|_, output| {
+                upgraded += 1;
+                Some(TransactionOutput {
+                    value: output.value,
+                    pubkey: output.pubkey,
+                    asset: NATIVE_ASSET,
+                    nft: None,
+                })
+            });
+            log::info!("Upgraded {} UTXOs to the multi-asset layout", upgraded);
+            StorageVersion::new(1).put::<Pallet<T>>();
+
+            T::DbWeight::get().reads_writes(upgraded + 1, upgraded + 1)
+        }
+    }
+
+    /// Fills `UtxosByOwner` in from `UtxoStore`, for chains started before storage version 2.
+    /// Reads the whole UTXO set once.
+    pub struct IndexOwners<T>(PhantomData<T>);
+
+    impl<T: Config> OnRuntimeUpgrade for IndexOwners<T> {
+        fn on_runtime_upgrade() -> Weight {
+            if Pallet::<T>::on_chain_storage_version() != 1 {
+                return T::DbWeight::get().reads(1);
+            }
+
+            let mut indexed = 0u64;
+            for (outpoint, output) in UtxoStore::<T>::iter() {
+                UtxosByOwner::<T>::insert(output.pubkey, outpoint, ());
+                indexed += 1;
+            }
+            log::info!("Indexed the owners of {} UTXOs", indexed);
+            StorageVersion::new(2).put::<Pallet<T>>();
+
+            T::DbWeight::get().reads_writes(indexed + 1, indexed + 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use frame_support::{assert_noop, assert_ok, pallet_prelude::InvalidTransaction};
+    use sp_core::Pair;
+    use sp_runtime::traits::ValidateUnsigned;
+
+    use super::*;
+    use crate::mock::*;
+
+    const ALICE: u8 = 1;
+    const BOB: u8 = 2;
+
+    fn output(value: Value, seed: u8, asset: AssetId) -> TransactionOutput {
+        TransactionOutput {
+            value,
+            pubkey: owner(seed),
+            asset,
+            nft: None,
+        }
+    }
+
+    /// Outpoint of the native genesis UTXO of `value` owned by `seed`
+    fn genesis_outpoint(value: Value, seed: u8) -> H256 {
+        BlakeTwo256::hash_of(&output(value, seed, NATIVE_ASSET))
+    }
+
+    /// Transaction spending `outpoints`, all owned by `seed`, into `outputs`
+    fn signed(seed: u8, outpoints: &[H256], outputs: Vec<TransactionOutput>) -> Transaction {
+        let mut transaction = Transaction {
+            inputs: outpoints
+                .iter()
+                .map(|outpoint| TransactionInput {
+                    outpoint: *outpoint,
+                    sigscript: H512::zero(),
+                })
+                .collect(),
+            outputs,
+        };
+        let signature = pair(seed).sign(&get_simple_transaction(&transaction));
+        for input in transaction.inputs.iter_mut() {
+            input.sigscript = H512::from_slice(signature.as_ref());
+        }
+        transaction
+    }
+
+    /// Issuance of `asset` by `issuer`, signed by `issuer`
+    fn issuance(transaction: &Transaction, asset: Option<AssetId>, issuer: u8) -> AssetIssuance {
+        let mut issuance = AssetIssuance {
+            asset,
+            issuer: owner(issuer),
+            signature: H512::zero(),
+        };
+        let signature = pair(issuer).sign(&get_issuance_payload(transaction, &issuance));
+        issuance.signature = H512::from_slice(signature.as_ref());
+        issuance
+    }
+
+    /// Issues 1000 of a new asset to Alice out of her genesis UTXO of 100, paying a fee of 10.
+    /// Returns the transaction and the asset.
+    fn issue_new_asset() -> (Transaction, AssetId) {
+        let asset = new_asset_id_of(ALICE);
+        let transaction = signed(
+            ALICE,
+            &[genesis_outpoint(100, ALICE)],
+            vec![output(90, ALICE, NATIVE_ASSET), output(1_000, ALICE, asset)],
+        );
+        let issuance = issuance(&transaction, None, ALICE);
+        assert_ok!(Utxo::issue(
+            RuntimeOrigin::none(),
+            transaction.clone(),
+            issuance
+        ));
+        (transaction, asset)
+    }
+
+    /// Id of the asset registered by an issuance spending the genesis UTXO of `seed` first
+    fn new_asset_id_of(seed: u8) -> AssetId {
+        BlakeTwo256::hash_of(&(b"asset", genesis_outpoint(100, seed)))
+    }
+
+    #[test]
+    fn issuance_registers_a_new_asset() {
+        new_test_ext(vec![(100, owner(ALICE))]).execute_with(|| {
+            let (transaction, asset) = issue_new_asset();
+
+            assert_eq!(Assets::<Test>::get(asset), Some(owner(ALICE)));
+            assert_eq!(
+                UtxoStore::<Test>::get(outpoint(&transaction, 1)),
+                Some(output(1_000, ALICE, asset))
+            );
+            assert!(!UtxoStore::<Test>::contains_key(genesis_outpoint(
+                100, ALICE
+            )));
+            assert_eq!(TotalReward::<Test>::get(), 10);
+            System::assert_has_event(Event::AssetIssued(asset, owner(ALICE)).into());
+        });
+    }
+
+    #[test]
+    fn only_the_issuer_mints_more_of_an_asset() {
+        new_test_ext(vec![(100, owner(ALICE)), (100, owner(BOB))]).execute_with(|| {
+            let (first, asset) = issue_new_asset();
+
+            let by_bob = signed(
+                BOB,
+                &[genesis_outpoint(100, BOB)],
+                vec![output(100, BOB, NATIVE_ASSET), output(500, BOB, asset)],
+            );
+            let bobs_issuance = issuance(&by_bob, Some(asset), BOB);
+            assert_noop!(
+                Utxo::issue(RuntimeOrigin::none(), by_bob, bobs_issuance),
+                Error::<Test>::InvalidIssuer
+            );
+
+            let by_alice = signed(
+                ALICE,
+                &[outpoint(&first, 0)],
+                vec![output(90, ALICE, NATIVE_ASSET), output(500, ALICE, asset)],
+            );
+            let alices_issuance = issuance(&by_alice, Some(asset), ALICE);
+            assert_ok!(Utxo::issue(
+                RuntimeOrigin::none(),
+                by_alice.clone(),
+                alices_issuance
+            ));
+            assert_eq!(
+                UtxoStore::<Test>::get(outpoint(&by_alice, 1)),
+                Some(output(500, ALICE, asset))
+            );
+        });
+    }
+
+    #[test]
+    fn new_issuance_of_a_taken_asset_is_refused() {
+        new_test_ext(vec![(100, owner(ALICE))]).execute_with(|| {
+            let (transaction, _) = issue_new_asset();
+
+            let again = issuance(&transaction, None, ALICE);
+            let result = Utxo::validate_issuance(&transaction, &again);
+            assert!(matches!(result, Err(Error::AssetExists)));
+            assert_eq!(Error::<Test>::AssetExists.code(), 19);
+        });
+    }
+
+    #[test]
+    fn issuance_fields_are_signed() {
+        new_test_ext(vec![(100, owner(ALICE)), (100, owner(BOB))]).execute_with(|| {
+            let transaction = signed(
+                ALICE,
+                &[genesis_outpoint(100, ALICE)],
+                vec![
+                    output(100, ALICE, NATIVE_ASSET),
+                    output(1_000, ALICE, new_asset_id_of(ALICE)),
+                ],
+            );
+
+            // A signature over the inputs' payload alone does not cover the issuer
+            let mut unsigned_fields = issuance(&transaction, None, ALICE);
+            let signature = pair(ALICE).sign(&get_simple_transaction(&transaction));
+            unsigned_fields.signature = H512::from_slice(signature.as_ref());
+            assert_eq!(
+                Utxo::pre_dispatch(&Call::issue {
+                    transaction: transaction.clone(),
+                    issuance: unsigned_fields,
+                }),
+                Err(InvalidTransaction::Custom(Error::<Test>::InvalidIssuer.code()).into())
+            );
+
+            // Nor can the issuer be swapped under an existing signature
+            let mut swapped = issuance(&transaction, None, ALICE);
+            swapped.issuer = owner(BOB);
+            assert_eq!(
+                Utxo::pre_dispatch(&Call::issue {
+                    transaction,
+                    issuance: swapped,
+                }),
+                Err(InvalidTransaction::Custom(Error::<Test>::InvalidIssuer.code()).into())
+            );
+        });
+    }
+
+    #[test]
+    fn signatures_are_verified_before_dispatch_only() {
+        new_test_ext(vec![(100, owner(ALICE))]).execute_with(|| {
+            let transaction = signed(
+                ALICE,
+                &[genesis_outpoint(100, ALICE)],
+                vec![output(100, BOB, NATIVE_ASSET)],
+            );
+            assert_ok!(Utxo::pre_dispatch(&Call::spend {
+                transaction: transaction.clone()
+            }));
+
+            // Block execution never dispatches a call `pre_dispatch` refused
+            let mut forged = transaction.clone();
+            forged.inputs[0].sigscript = H512::repeat_byte(1);
+            assert_eq!(
+                Utxo::pre_dispatch(&Call::spend {
+                    transaction: forged
+                }),
+                Err(InvalidTransaction::Custom(Error::<Test>::InvalidSignature.code()).into())
+            );
+
+            let mut issuance = issuance(&transaction, None, ALICE);
+            issuance.signature = H512::repeat_byte(1);
+            assert_eq!(
+                Utxo::pre_dispatch(&Call::issue {
+                    transaction,
+                    issuance
+                }),
+                Err(InvalidTransaction::Custom(Error::<Test>::InvalidIssuer.code()).into())
+            );
+        });
+    }
+
+    #[test]
+    fn native_and_unknown_assets_cannot_be_minted() {
+        new_test_ext(vec![(100, owner(ALICE))]).execute_with(|| {
+            let transaction = signed(
+                ALICE,
+                &[genesis_outpoint(100, ALICE)],
+                vec![output(200, ALICE, NATIVE_ASSET)],
+            );
+            let native = issuance(&transaction, Some(NATIVE_ASSET), ALICE);
+            assert_noop!(
+                Utxo::issue(RuntimeOrigin::none(), transaction.clone(), native),
+                Error::<Test>::NativeIssuance
+            );
+
+            let unknown = issuance(&transaction, Some(H256::repeat_byte(7)), ALICE);
+            assert_noop!(
+                Utxo::issue(RuntimeOrigin::none(), transaction, unknown),
+                Error::<Test>::UnknownAsset
+            );
+        });
+    }
+
+    #[test]
+    fn spends_conserve_every_asset() {
+        new_test_ext(vec![(100, owner(ALICE))]).execute_with(|| {
+            let (first, asset) = issue_new_asset();
+            let inputs = [outpoint(&first, 0), outpoint(&first, 1)];
+
+            let inflated = signed(
+                ALICE,
+                &inputs,
+                vec![output(90, BOB, NATIVE_ASSET), output(1_001, BOB, asset)],
+            );
+            assert_noop!(
+                Utxo::spend(RuntimeOrigin::none(), inflated),
+                Error::<Test>::AssetNotConserved
+            );
+
+            let overspent = signed(
+                ALICE,
+                &inputs,
+                vec![output(91, BOB, NATIVE_ASSET), output(1_000, BOB, asset)],
+            );
+            assert_noop!(
+                Utxo::spend(RuntimeOrigin::none(), overspent),
+                Error::<Test>::OutputOverInput
+            );
+
+            let split = signed(
+                ALICE,
+                &inputs,
+                vec![
+                    output(85, BOB, NATIVE_ASSET),
+                    output(600, BOB, asset),
+                    output(400, ALICE, asset),
+                ],
+            );
+            assert_ok!(Utxo::spend(RuntimeOrigin::none(), split.clone()));
+            assert_eq!(
+                UtxoStore::<Test>::get(outpoint(&split, 2)),
+                Some(output(400, ALICE, asset))
+            );
+            assert_eq!(TotalReward::<Test>::get(), 15);
+        });
+    }
+
+    /// Alice mints a token out of her genesis UTXO of 100. Returns the minting transaction
+    /// and the token.
+    fn mint_nft() -> (Transaction, NonFungible) {
+        let nft = NonFungible {
+            id: nft_id(&genesis_outpoint(100, ALICE), 0),
+            metadata: H256::repeat_byte(1),
+        };
+        let transaction = signed(
+            ALICE,
+            &[genesis_outpoint(100, ALICE)],
+            vec![with_nft(output(100, ALICE, NATIVE_ASSET), &nft)],
+        );
+        assert_ok!(Utxo::spend(RuntimeOrigin::none(), transaction.clone()));
+        (transaction, nft)
+    }
+
+    fn with_nft(output: TransactionOutput, nft: &NonFungible) -> TransactionOutput {
+        TransactionOutput {
+            nft: Some(nft.clone()),
+            ..output
+        }
+    }
+
+    #[test]
+    fn minting_records_the_holder() {
+        new_test_ext(vec![(100, owner(ALICE))]).execute_with(|| {
+            let (transaction, nft) = mint_nft();
+
+            assert_eq!(
+                NftHolder::<Test>::get(nft.id),
+                Some(outpoint(&transaction, 0))
+            );
+            assert_eq!(
+                UtxoStore::<Test>::get(outpoint(&transaction, 0)).and_then(|utxo| utxo.nft),
+                Some(nft)
+            );
+        });
+    }
+
+    #[test]
+    fn tokens_are_minted_once() {
+        new_test_ext(vec![(100, owner(ALICE))]).execute_with(|| {
+            // Ids are derived from the first input and the index of the output
+            let wrong_id = NonFungible {
+                id: nft_id(&genesis_outpoint(100, ALICE), 1),
+                metadata: H256::zero(),
+            };
+            let transaction = signed(
+                ALICE,
+                &[genesis_outpoint(100, ALICE)],
+                vec![with_nft(output(100, ALICE, NATIVE_ASSET), &wrong_id)],
+            );
+            assert_noop!(
+                Utxo::spend(RuntimeOrigin::none(), transaction),
+                Error::<Test>::InvalidNftId
+            );
+
+            // A token moving on cannot be minted a second time next to it
+            let (minting, nft) = mint_nft();
+            let copy = signed(
+                ALICE,
+                &[outpoint(&minting, 0)],
+                vec![
+                    with_nft(output(50, BOB, NATIVE_ASSET), &nft),
+                    with_nft(output(40, ALICE, NATIVE_ASSET), &nft),
+                ],
+            );
+            assert_noop!(
+                Utxo::spend(RuntimeOrigin::none(), copy),
+                Error::<Test>::InvalidNftId
+            );
+        });
+    }
+
+    #[test]
+    fn transfers_move_the_token_unchanged() {
+        new_test_ext(vec![(100, owner(ALICE))]).execute_with(|| {
+            let (minting, nft) = mint_nft();
+            let spent = [outpoint(&minting, 0)];
+
+            let altered = NonFungible {
+                metadata: H256::repeat_byte(2),
+                ..nft.clone()
+            };
+            let relabelled = signed(
+                ALICE,
+                &spent,
+                vec![with_nft(output(100, BOB, NATIVE_ASSET), &altered)],
+            );
+            assert_noop!(
+                Utxo::spend(RuntimeOrigin::none(), relabelled),
+                Error::<Test>::NftNotTransferred
+            );
+
+            let burnt = signed(ALICE, &spent, vec![output(100, BOB, NATIVE_ASSET)]);
+            assert_noop!(
+                Utxo::spend(RuntimeOrigin::none(), burnt),
+                Error::<Test>::NftNotTransferred
+            );
+
+            let transfer = signed(
+                ALICE,
+                &spent,
+                vec![
+                    output(60, ALICE, NATIVE_ASSET),
+                    with_nft(output(40, BOB, NATIVE_ASSET), &nft),
+                ],
+            );
+            assert_ok!(Utxo::spend(RuntimeOrigin::none(), transfer.clone()));
+            assert_eq!(NftHolder::<Test>::get(nft.id), Some(outpoint(&transfer, 1)));
+            assert_eq!(
+                UtxoStore::<Test>::get(outpoint(&transfer, 1)).map(|utxo| utxo.pubkey),
+                Some(owner(BOB))
+            );
+        });
+    }
+
+    #[test]
+    fn utxos_are_listed_by_owner() {
+        new_test_ext(vec![(100, owner(ALICE)), (50, owner(ALICE))]).execute_with(|| {
+            let mut listed = Utxo::utxos_of(owner(ALICE));
+            listed.sort_by_key(|(_, output)| output.value);
+            assert_eq!(
+                listed,
+                vec![
+                    (genesis_outpoint(50, ALICE), output(50, ALICE, NATIVE_ASSET)),
+                    (
+                        genesis_outpoint(100, ALICE),
+                        output(100, ALICE, NATIVE_ASSET)
+                    ),
+                ]
+            );
+
+            let transaction = signed(
+                ALICE,
+                &[genesis_outpoint(100, ALICE)],
+                vec![
+                    output(60, BOB, NATIVE_ASSET),
+                    output(40, ALICE, NATIVE_ASSET),
+                ],
+            );
+            assert_ok!(Utxo::spend(RuntimeOrigin::none(), transaction.clone()));
+
+            let mut listed = Utxo::utxos_of(owner(ALICE));
+            listed.sort_by_key(|(_, output)| output.value);
+            assert_eq!(
+                listed,
+                vec![
+                    (outpoint(&transaction, 1), output(40, ALICE, NATIVE_ASSET)),
+                    (genesis_outpoint(50, ALICE), output(50, ALICE, NATIVE_ASSET)),
+                ]
+            );
+            assert_eq!(
+                Utxo::utxos_of(owner(BOB)),
+                vec![(outpoint(&transaction, 0), output(60, BOB, NATIVE_ASSET))]
+            );
+        });
+    }
+
+    #[test]
+    fn migration_indexes_the_owners_of_existing_utxos() {
+        use frame_support::traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion};
+
+        new_test_ext(vec![(100, owner(ALICE)), (50, owner(BOB))]).execute_with(|| {
+            // A chain from before the index
+            let _ = UtxosByOwner::<Test>::clear(u32::MAX, None);
+            StorageVersion::new(1).put::<Utxo>();
+            assert!(Utxo::utxos_of(owner(ALICE)).is_empty());
+
+            migrations::IndexOwners::<Test>::on_runtime_upgrade();
+
+            assert_eq!(Utxo::on_chain_storage_version(), 2);
+            assert_eq!(
+                Utxo::utxos_of(owner(ALICE)),
+                vec![(
+                    genesis_outpoint(100, ALICE),
+                    output(100, ALICE, NATIVE_ASSET)
+                )]
+            );
+            assert_eq!(
+                Utxo::utxos_of(owner(BOB)),
+                vec![(genesis_outpoint(50, BOB), output(50, BOB, NATIVE_ASSET))]
+            );
+        });
+    }
+
+    #[test]
+    fn migrations_carry_outputs_of_the_first_layout_over() {
+        use frame_support::{
+            storage::unhashed,
+            traits::{GetStorageVersion, OnRuntimeUpgrade, StorageVersion},
+        };
+
+        new_test_ext(vec![]).execute_with(|| {
+            // A chain from before multiple assets, with a UTXO of Alice in the first layout
+            let outpoint = H256::repeat_byte(7);
+            unhashed::put_raw(
+                &UtxoStore::<Test>::hashed_key_for(outpoint),
+                &(100 as Value, owner(ALICE)).encode(),
+            );
+            StorageVersion::new(0).put::<Utxo>();
+
+            migrations::AddAssets::<Test>::on_runtime_upgrade();
+            migrations::IndexOwners::<Test>::on_runtime_upgrade();
+
+            assert_eq!(Utxo::on_chain_storage_version(), 2);
+            assert_eq!(
+                Utxo::utxos_of(owner(ALICE)),
+                vec![(outpoint, output(100, ALICE, NATIVE_ASSET))]
+            );
+        });
+    }
+
+    #[test]
+    fn error_codes_round_trip() {
+        for code in 1..=19 {
+            assert_eq!(Error::<Test>::from_code(code).map(|e| e.code()), Some(code));
+        }
+        assert!(Error::<Test>::from_code(0).is_none());
+        assert!(Error::<Test>::from_code(20).is_none());
+    }
+}
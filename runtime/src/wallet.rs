@@ -0,0 +1,805 @@
+//! Building blocks for UTXO wallets: coin selection, a `TransactionBuilder`, signing and
+//! hierarchical deterministic keys.
+//!
+//! Only available with the `std` feature. Clients use it instead of assembling `Transaction`s
+//! by hand: pick the UTXOs to spend, let the builder add change and the fee, then sign the
+//! result with the `sr25519` keys owning the inputs. An `HdWallet` hands out a fresh key for
+//! every payment and every change output instead of reusing a single key.
+
+use std::{cmp::Reverse, fmt};
+
+use parity_scale_codec::{Decode, Encode};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+use sp_core::{
+    crypto::{Derive, DeriveJunction},
+    sr25519, ByteArray, Pair, H256, H512,
+};
+
+use crate::utxo::{
+    get_simple_transaction, Transaction, TransactionInput, TransactionOutput, Value, NATIVE_ASSET,
+};
+
+/// Encoded size of a `TransactionInput`: the outpoint and its sigscript
+pub const INPUT_SIZE: usize = 32 + 64;
+
+/// An unspent output that a wallet may spend
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct Utxo {
+    /// Key of the output in `UtxoStore`
+    pub outpoint: H256,
+    /// The output itself
+    pub output: TransactionOutput,
+}
+
+impl Utxo {
+    /// Value left after paying for the input spending this UTXO
+    fn effective_value(&self, fee_rate: Value) -> Value {
+        self.output
+            .value
+            .saturating_sub(fee_rate.saturating_mul(INPUT_SIZE as Value))
+    }
+}
+
+/// Errors that can occur when building or signing a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The available UTXOs can't cover the outputs and the fee
+    InsufficientFunds { needed: Value, available: Value },
+    /// The transaction has no outputs
+    NoOutputs,
+    /// Change is due but no change key was configured
+    NoChangeKey,
+    /// No signing key was given for the owner of an input
+    MissingKey(H256),
+    /// The number of previous outputs doesn't match the number of inputs
+    PrevoutsMismatch,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InsufficientFunds { needed, available } => {
+                write!(
+                    f,
+                    "insufficient funds: needed {needed}, available {available}"
+                )
+            }
+            Error::NoOutputs => write!(f, "transaction has no outputs"),
+            Error::NoChangeKey => write!(f, "change is due but no change key was set"),
+            Error::MissingKey(owner) => write!(f, "no signing key for owner {owner:?}"),
+            Error::PrevoutsMismatch => write!(f, "previous outputs don't match the inputs"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Fee of a transaction at `fee_rate` (value per byte), estimated from its encoded size.
+/// Signatures are fixed-size, so the estimate is the same before and after signing.
+pub fn estimate_fee(transaction: &Transaction, fee_rate: Value) -> Value {
+    fee_rate.saturating_mul(transaction.encoded_size() as Value)
+}
+
+/// Total native value of `outputs`
+fn native_value<'a>(outputs: impl IntoIterator<Item = &'a TransactionOutput>) -> Value {
+    outputs
+        .into_iter()
+        .filter(|output| output.asset == NATIVE_ASSET)
+        .map(|output| output.value)
+        .sum()
+}
+
+/// A strategy picking which UTXOs fund a transaction
+pub trait CoinSelection {
+    /// Pick UTXOs among `candidates` whose value covers `target` plus the fee of the
+    /// inputs spending them at `fee_rate`.
+    ///
+    /// `cost_of_change` is what adding a change output would cost. Strategies may use it to
+    /// prefer selections that need no change at all.
+    fn select(
+        &mut self,
+        candidates: &[Utxo],
+        target: Value,
+        fee_rate: Value,
+        cost_of_change: Value,
+    ) -> Result<Vec<Utxo>, Error>;
+}
+
+/// Accumulate UTXOs in the given order until `target` is reached
+fn accumulate<'a>(
+    candidates: impl IntoIterator<Item = &'a Utxo>,
+    target: Value,
+    fee_rate: Value,
+) -> Result<Vec<Utxo>, Error> {
+    let mut selected = Vec::new();
+    let mut total: Value = 0;
+    for utxo in candidates {
+        if total >= target {
+            break;
+        }
+        let value = utxo.effective_value(fee_rate);
+        if value == 0 {
+            continue;
+        }
+        total = total.saturating_add(value);
+        selected.push(utxo.clone());
+    }
+
+    if total < target {
+        return Err(Error::InsufficientFunds {
+            needed: target,
+            available: total,
+        });
+    }
+    Ok(selected)
+}
+
+/// Spend the biggest UTXOs first. Keeps the number of inputs, and so the fee, low.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LargestFirst;
+
+impl CoinSelection for LargestFirst {
+    fn select(
+        &mut self,
+        candidates: &[Utxo],
+        target: Value,
+        fee_rate: Value,
+        _cost_of_change: Value,
+    ) -> Result<Vec<Utxo>, Error> {
+        let mut sorted: Vec<&Utxo> = candidates.iter().collect();
+        sorted.sort_by_key(|utxo| Reverse(utxo.output.value));
+        accumulate(sorted, target, fee_rate)
+    }
+}
+
+/// Search for a combination of UTXOs that matches the target without needing change,
+/// as Bitcoin Core's branch and bound does. Falls back to `LargestFirst` if none is found
+/// within `max_tries` steps.
+#[derive(Debug, Clone, Copy)]
+pub struct BranchAndBound {
+    pub max_tries: usize,
+}
+
+impl Default for BranchAndBound {
+    fn default() -> Self {
+        Self { max_tries: 100_000 }
+    }
+}
+
+impl BranchAndBound {
+    /// Depth-first search over include/exclude decisions, biggest UTXOs first.
+    /// Returns the indexes (into `values`) of the best changeless selection found.
+    fn search(&self, values: &[Value], target: Value, cost_of_change: Value) -> Option<Vec<usize>> {
+        let upper = target.saturating_add(cost_of_change);
+        let mut remaining: Value = values.iter().sum();
+        let mut selected: Vec<usize> = Vec::new();
+        let mut total: Value = 0;
+        let mut best: Option<(Value, Vec<usize>)> = None;
+        let mut index = 0;
+
+        for _ in 0..self.max_tries {
+            let backtrack = if total + remaining < target || total > upper {
+                true
+            } else if total >= target {
+                if best
+                    .as_ref()
+                    .is_none_or(|(waste, _)| total - target < *waste)
+                {
+                    best = Some((total - target, selected.clone()));
+                }
+                true
+            } else {
+                false
+            };
+
+            if backtrack {
+                // Walk back to the last included UTXO and try excluding it instead
+                while index > 0 && selected.last() != Some(&(index - 1)) {
+                    index -= 1;
+                    remaining += values[index];
+                }
+                let Some(last) = selected.pop() else { break };
+                total -= values[last];
+                index = last + 1;
+                if best.as_ref().is_some_and(|(waste, _)| *waste == 0) {
+                    break;
+                }
+                continue;
+            }
+
+            if index == values.len() {
+                break;
+            }
+            // Include the next UTXO
+            remaining -= values[index];
+            total += values[index];
+            selected.push(index);
+            index += 1;
+        }
+
+        best.map(|(_, selection)| selection)
+    }
+}
+
+impl CoinSelection for BranchAndBound {
+    fn select(
+        &mut self,
+        candidates: &[Utxo],
+        target: Value,
+        fee_rate: Value,
+        cost_of_change: Value,
+    ) -> Result<Vec<Utxo>, Error> {
+        let mut sorted: Vec<&Utxo> = candidates
+            .iter()
+            .filter(|utxo| utxo.effective_value(fee_rate) > 0)
+            .collect();
+        sorted.sort_by_key(|utxo| Reverse(utxo.output.value));
+        let values: Vec<Value> = sorted.iter().map(|u| u.effective_value(fee_rate)).collect();
+
+        match self.search(&values, target, cost_of_change) {
+            Some(selection) => Ok(selection.into_iter().map(|i| sorted[i].clone()).collect()),
+            None => LargestFirst.select(candidates, target, fee_rate, cost_of_change),
+        }
+    }
+}
+
+/// Spend UTXOs in a random order. Makes it harder to link the outputs of a wallet.
+pub struct RandomSelection {
+    rng: StdRng,
+}
+
+impl RandomSelection {
+    /// Deterministic selection, for reproducible results
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Default for RandomSelection {
+    fn default() -> Self {
+        Self {
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl CoinSelection for RandomSelection {
+    fn select(
+        &mut self,
+        candidates: &[Utxo],
+        target: Value,
+        fee_rate: Value,
+        _cost_of_change: Value,
+    ) -> Result<Vec<Utxo>, Error> {
+        let mut shuffled: Vec<&Utxo> = candidates.iter().collect();
+        shuffled.shuffle(&mut self.rng);
+        accumulate(shuffled, target, fee_rate)
+    }
+}
+
+/// A transaction whose inputs are not signed yet, along with the outputs they spend
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Encode, Decode)]
+pub struct UnsignedTransaction {
+    /// The transaction, with zeroed sigscripts
+    pub transaction: Transaction,
+    /// Outputs spent by the inputs, in the same order
+    pub prevouts: Vec<TransactionOutput>,
+}
+
+impl UnsignedTransaction {
+    /// Native value paid to the block author
+    pub fn fee(&self) -> Value {
+        native_value(&self.prevouts).saturating_sub(native_value(&self.transaction.outputs))
+    }
+
+    /// The payload signed for every input, as checked by the utxo pallet
+    pub fn signing_payload(&self) -> Vec<u8> {
+        get_simple_transaction(&self.transaction)
+    }
+
+    /// Sign every input with the key owning the output it spends
+    pub fn sign(self, keys: &[sr25519::Pair]) -> Result<Transaction, Error> {
+        self.sign_with(|owner, payload| {
+            keys.iter()
+                .find(|pair| pair.public().as_slice() == owner.as_bytes())
+                .map(|pair| H512::from_slice(pair.sign(payload).as_ref()))
+        })
+    }
+
+    /// Sign every input with `signer`, which is given the owner of the spent output and
+    /// the payload, and returns `None` if it doesn't hold the owner's key
+    pub fn sign_with(
+        self,
+        mut signer: impl FnMut(&H256, &[u8]) -> Option<H512>,
+    ) -> Result<Transaction, Error> {
+        if self.prevouts.len() != self.transaction.inputs.len() {
+            return Err(Error::PrevoutsMismatch);
+        }
+
+        let payload = self.signing_payload();
+        let mut transaction = self.transaction;
+        for (input, prevout) in transaction.inputs.iter_mut().zip(self.prevouts.iter()) {
+            input.sigscript =
+                signer(&prevout.pubkey, &payload).ok_or(Error::MissingKey(prevout.pubkey))?;
+        }
+
+        Ok(transaction)
+    }
+}
+
+/// Assembles a transaction: funds the outputs from the given UTXOs, pays the fee
+/// and sends what is left back to a change key.
+///
+/// Coin selection only considers plain native UTXOs. UTXOs carrying other assets or
+/// non-fungible tokens can be spent with `add_input`, the remaining amount of each
+/// asset is sent back as change too.
+pub struct TransactionBuilder<S = LargestFirst> {
+    candidates: Vec<Utxo>,
+    inputs: Vec<Utxo>,
+    outputs: Vec<TransactionOutput>,
+    change: Option<H256>,
+    fee_rate: Value,
+    coin_selection: S,
+}
+
+impl Default for TransactionBuilder<LargestFirst> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TransactionBuilder<LargestFirst> {
+    pub fn new() -> Self {
+        Self {
+            candidates: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
+            change: None,
+            fee_rate: 1,
+            coin_selection: LargestFirst,
+        }
+    }
+}
+
+impl<S: CoinSelection> TransactionBuilder<S> {
+    /// Use another coin selection strategy
+    pub fn coin_selection<C: CoinSelection>(self, coin_selection: C) -> TransactionBuilder<C> {
+        TransactionBuilder {
+            candidates: self.candidates,
+            inputs: self.inputs,
+            outputs: self.outputs,
+            change: self.change,
+            fee_rate: self.fee_rate,
+            coin_selection,
+        }
+    }
+
+    /// UTXOs that coin selection may spend
+    pub fn utxos(mut self, utxos: impl IntoIterator<Item = Utxo>) -> Self {
+        self.candidates.extend(utxos);
+        self
+    }
+
+    /// Spend this UTXO whatever coin selection decides
+    pub fn add_input(mut self, utxo: Utxo) -> Self {
+        self.inputs.push(utxo);
+        self
+    }
+
+    /// Pay `value` of the native asset to `pubkey`
+    pub fn pay_to(self, pubkey: H256, value: Value) -> Self {
+        self.add_output(TransactionOutput {
+            value,
+            pubkey,
+            asset: NATIVE_ASSET,
+            nft: None,
+        })
+    }
+
+    pub fn add_output(mut self, output: TransactionOutput) -> Self {
+        self.outputs.push(output);
+        self
+    }
+
+    /// Key receiving the change
+    pub fn change_to(mut self, pubkey: H256) -> Self {
+        self.change = Some(pubkey);
+        self
+    }
+
+    /// Fee rate, in native value per encoded byte
+    pub fn fee_rate(mut self, fee_rate: Value) -> Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    pub fn build(mut self) -> Result<UnsignedTransaction, Error> {
+        if self.outputs.is_empty() {
+            return Err(Error::NoOutputs);
+        }
+
+        let mut outputs = self.outputs.clone();
+
+        // Non-native assets go back to the change key
+        let mut assets: Vec<(H256, Value)> = Vec::new();
+        for utxo in self
+            .inputs
+            .iter()
+            .filter(|u| u.output.asset != NATIVE_ASSET)
+        {
+            match assets
+                .iter_mut()
+                .find(|(asset, _)| *asset == utxo.output.asset)
+            {
+                Some((_, total)) => *total = total.saturating_add(utxo.output.value),
+                None => assets.push((utxo.output.asset, utxo.output.value)),
+            }
+        }
+        for (asset, available) in assets {
+            let needed: Value = outputs
+                .iter()
+                .filter(|output| output.asset == asset)
+                .map(|output| output.value)
+                .sum();
+            let change = available
+                .checked_sub(needed)
+                .ok_or(Error::InsufficientFunds { needed, available })?;
+            if change > 0 {
+                outputs.push(TransactionOutput {
+                    value: change,
+                    pubkey: self.change.ok_or(Error::NoChangeKey)?,
+                    asset,
+                    nft: None,
+                });
+            }
+        }
+
+        let native_out = native_value(&outputs);
+        let native_in = native_value(self.inputs.iter().map(|utxo| &utxo.output));
+
+        let mut transaction = Transaction {
+            inputs: self
+                .inputs
+                .iter()
+                .map(|utxo| TransactionInput {
+                    outpoint: utxo.outpoint,
+                    sigscript: H512::zero(),
+                })
+                .collect(),
+            outputs,
+        };
+
+        let change_output = TransactionOutput {
+            value: 1,
+            pubkey: self.change.unwrap_or_default(),
+            asset: NATIVE_ASSET,
+            nft: None,
+        };
+        let cost_of_change = self
+            .fee_rate
+            .saturating_mul(change_output.encoded_size() as Value);
+
+        // Fund whatever the explicit inputs don't cover
+        let fee = estimate_fee(&transaction, self.fee_rate);
+        let target = native_out.saturating_add(fee);
+        let mut selected_in: Value = 0;
+        if native_in < target || transaction.inputs.is_empty() {
+            let candidates: Vec<Utxo> = self
+                .candidates
+                .iter()
+                .filter(|utxo| {
+                    utxo.output.asset == NATIVE_ASSET
+                        && utxo.output.nft.is_none()
+                        && !self
+                            .inputs
+                            .iter()
+                            .any(|input| input.outpoint == utxo.outpoint)
+                })
+                .cloned()
+                .collect();
+            let selected = self.coin_selection.select(
+                &candidates,
+                target.saturating_sub(native_in).max(1),
+                self.fee_rate,
+                cost_of_change,
+            )?;
+            for utxo in selected {
+                selected_in = selected_in.saturating_add(utxo.output.value);
+                transaction.inputs.push(TransactionInput {
+                    outpoint: utxo.outpoint,
+                    sigscript: H512::zero(),
+                });
+                self.inputs.push(utxo);
+            }
+        }
+
+        // Whatever is left above the fee becomes change, if it is worth an output
+        let available = native_in.saturating_add(selected_in);
+        let fee = estimate_fee(&transaction, self.fee_rate);
+        let needed = native_out.saturating_add(fee);
+        if available < needed {
+            return Err(Error::InsufficientFunds { needed, available });
+        }
+        let change = available - needed;
+        if change > cost_of_change {
+            transaction.outputs.push(TransactionOutput {
+                value: change - cost_of_change,
+                pubkey: self.change.ok_or(Error::NoChangeKey)?,
+                asset: NATIVE_ASSET,
+                nft: None,
+            });
+        }
+
+        Ok(UnsignedTransaction {
+            transaction,
+            prevouts: self.inputs.into_iter().map(|utxo| utxo.output).collect(),
+        })
+    }
+}
+
+/// Gap limit used when none is configured: scanning stops after this many unused keys
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// The two branches of keys of an `HdWallet`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyChain {
+    /// Keys handed out to payers
+    Receive,
+    /// Keys receiving the change of our own transactions
+    Change,
+}
+
+impl KeyChain {
+    fn junction(self) -> DeriveJunction {
+        match self {
+            KeyChain::Receive => DeriveJunction::soft(0u32),
+            KeyChain::Change => DeriveJunction::soft(1u32),
+        }
+    }
+}
+
+/// A hierarchical deterministic wallet.
+///
+/// Every key is derived from an account key with soft junctions: `<account>/0/<index>` for
+/// receive keys and `<account>/1/<index>` for change keys. Soft derivation works on public
+/// keys too, so the wallet only stores the account public key and can hand out fresh keys
+/// and scan the chain without the secret. Signing derives the same paths from the account
+/// pair.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HdWallet {
+    /// Public key of the account all the wallet keys derive from
+    pub account: H256,
+    /// Number of consecutive unused keys after which scanning stops
+    pub gap_limit: u32,
+    /// Index of the next receive key to hand out
+    pub next_receive: u32,
+    /// Index of the next change key to hand out
+    pub next_change: u32,
+}
+
+impl HdWallet {
+    /// A wallet that hasn't handed out any key yet
+    pub fn new(account: H256, gap_limit: u32) -> Self {
+        Self {
+            account,
+            gap_limit,
+            next_receive: 0,
+            next_change: 0,
+        }
+    }
+
+    /// Public key at `index` of `chain`
+    pub fn public(&self, chain: KeyChain, index: u32) -> H256 {
+        sr25519::Public::from_h256(self.account)
+            .derive([chain.junction(), DeriveJunction::soft(index)].into_iter())
+            .expect("only soft junctions are used; qed")
+            .into()
+    }
+
+    /// Key pair at `index` of `chain`, derived from the account pair
+    pub fn pair(account: &sr25519::Pair, chain: KeyChain, index: u32) -> sr25519::Pair {
+        account
+            .derive(
+                [chain.junction(), DeriveJunction::soft(index)].into_iter(),
+                None,
+            )
+            .expect("sr25519 derivation never fails; qed")
+            .0
+    }
+
+    /// Hand out a fresh receive key
+    pub fn next_receive(&mut self) -> H256 {
+        self.next_receive += 1;
+        self.public(KeyChain::Receive, self.next_receive - 1)
+    }
+
+    /// Hand out a fresh change key
+    pub fn next_change(&mut self) -> H256 {
+        self.next_change += 1;
+        self.public(KeyChain::Change, self.next_change - 1)
+    }
+
+    /// Every key handed out so far, with its position
+    pub fn keys(&self) -> Vec<(KeyChain, u32, H256)> {
+        [
+            (KeyChain::Receive, self.next_receive),
+            (KeyChain::Change, self.next_change),
+        ]
+        .into_iter()
+        .flat_map(|(chain, next)| (0..next).map(move |index| (chain, index)))
+        .map(|(chain, index)| (chain, index, self.public(chain, index)))
+        .collect()
+    }
+
+    /// Key pairs of every key handed out so far, derived from the account pair
+    pub fn pairs(&self, account: &sr25519::Pair) -> Vec<sr25519::Pair> {
+        self.keys()
+            .into_iter()
+            .map(|(chain, index, _)| Self::pair(account, chain, index))
+            .collect()
+    }
+
+    /// The `gap_limit` keys following the last one handed out on `chain`, which a scan has
+    /// to check before giving up on finding more funds
+    pub fn lookahead(&self, chain: KeyChain) -> Vec<(u32, H256)> {
+        let next = match chain {
+            KeyChain::Receive => self.next_receive,
+            KeyChain::Change => self.next_change,
+        };
+        (next..next.saturating_add(self.gap_limit))
+            .map(|index| (index, self.public(chain, index)))
+            .collect()
+    }
+
+    /// Record that the key at `index` of `chain` was used, so it and all keys before it
+    /// count as handed out
+    pub fn mark_used(&mut self, chain: KeyChain, index: u32) {
+        let next = match chain {
+            KeyChain::Receive => &mut self.next_receive,
+            KeyChain::Change => &mut self.next_change,
+        };
+        *next = (*next).max(index.saturating_add(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxo(seed: u8, value: Value) -> Utxo {
+        Utxo {
+            outpoint: H256::repeat_byte(seed),
+            output: TransactionOutput {
+                value,
+                pubkey: H256::repeat_byte(0xaa),
+                asset: NATIVE_ASSET,
+                nft: None,
+            },
+        }
+    }
+
+    fn values(selection: &[Utxo]) -> Vec<Value> {
+        selection.iter().map(|utxo| utxo.output.value).collect()
+    }
+
+    #[test]
+    fn branch_and_bound_finds_an_exact_match() {
+        let candidates = [utxo(1, 5), utxo(2, 4), utxo(3, 3), utxo(4, 2)];
+
+        let selection = BranchAndBound::default()
+            .select(&candidates, 7, 0, 0)
+            .unwrap();
+        assert_eq!(values(&selection), vec![5, 2]);
+
+        // Largest first would have spent 5 and 4 and needed change
+        let selection = LargestFirst.select(&candidates, 7, 0, 0).unwrap();
+        assert_eq!(values(&selection), vec![5, 4]);
+    }
+
+    #[test]
+    fn branch_and_bound_accepts_waste_below_the_cost_of_change() {
+        let candidates = [utxo(1, 10), utxo(2, 6)];
+
+        // No exact match: 6 overshoots by less than a change output would cost
+        let selection = BranchAndBound::default()
+            .select(&candidates, 5, 0, 2)
+            .unwrap();
+        assert_eq!(values(&selection), vec![6]);
+
+        // Nothing within the cost of change either: falls back to largest first
+        let selection = BranchAndBound::default()
+            .select(&candidates, 5, 0, 0)
+            .unwrap();
+        assert_eq!(values(&selection), vec![10]);
+    }
+
+    #[test]
+    fn random_selection_is_reproducible_and_covers_the_target() {
+        let candidates: Vec<Utxo> = (1..=10).map(|seed| utxo(seed, 10)).collect();
+
+        let first = RandomSelection::with_seed(7)
+            .select(&candidates, 35, 0, 0)
+            .unwrap();
+        let second = RandomSelection::with_seed(7)
+            .select(&candidates, 35, 0, 0)
+            .unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first.len(), 4);
+    }
+
+    #[test]
+    fn selection_reports_insufficient_funds() {
+        let candidates = [utxo(1, 5), utxo(2, 4)];
+        let expected = Err(Error::InsufficientFunds {
+            needed: 10,
+            available: 9,
+        });
+
+        assert_eq!(LargestFirst.select(&candidates, 10, 0, 0), expected);
+        assert_eq!(
+            BranchAndBound::default().select(&candidates, 10, 0, 0),
+            expected
+        );
+        assert_eq!(
+            RandomSelection::with_seed(1).select(&candidates, 10, 0, 0),
+            expected
+        );
+
+        // At a fee rate of 1, each input costs its encoded size
+        assert_eq!(
+            LargestFirst.select(&[utxo(1, 200)], 150, 1, 0),
+            Err(Error::InsufficientFunds {
+                needed: 150,
+                available: 200 - INPUT_SIZE as Value,
+            })
+        );
+    }
+
+    #[test]
+    fn change_below_the_cost_of_change_goes_to_the_fee() {
+        let payee = H256::repeat_byte(0xbb);
+        let change_key = H256::repeat_byte(0xcc);
+        let output_size = TransactionOutput::default().encoded_size() as Value;
+        // One input and one output, at a fee rate of 1
+        let fee = Transaction {
+            inputs: vec![TransactionInput::default()],
+            outputs: vec![TransactionOutput::default()],
+        }
+        .encoded_size() as Value;
+
+        let build = |value: Value| {
+            TransactionBuilder::new()
+                .utxos([utxo(1, value)])
+                .pay_to(payee, 1_000)
+                .change_to(change_key)
+                .build()
+                .unwrap()
+        };
+
+        // Change worth less than the output it would need is left to the block author
+        let dust = build(1_000 + fee + output_size - 1);
+        assert_eq!(dust.transaction.outputs.len(), 1);
+        assert_eq!(dust.fee(), fee + output_size - 1);
+
+        // Above it, the change output pays for itself
+        let change = build(1_000 + fee + output_size + 10);
+        assert_eq!(change.transaction.outputs.len(), 2);
+        assert_eq!(change.transaction.outputs[1].pubkey, change_key);
+        assert_eq!(change.transaction.outputs[1].value, 10);
+        assert_eq!(change.fee(), fee + output_size);
+        assert_eq!(change.fee(), estimate_fee(&change.transaction, 1));
+    }
+
+    #[test]
+    fn change_needs_a_change_key() {
+        let unsigned = TransactionBuilder::new()
+            .utxos([utxo(1, 10_000)])
+            .pay_to(H256::repeat_byte(0xbb), 1_000)
+            .build();
+        assert_eq!(unsigned, Err(Error::NoChangeKey));
+    }
+}
@@ -16,7 +16,13 @@ async function main() {
             },
             TransactionOutput: {
                 value: 'u128',        // 16 bytes
-                pubkey: 'H256'        // Fixed 32 bytes
+                pubkey: 'H256',       // Fixed 32 bytes
+                asset: 'H256',        // Fixed 32 bytes, zero for the native asset
+                nft: 'Option<NonFungible>'
+            },
+            NonFungible: {
+                id: 'H256',           // Fixed 32 bytes
+                metadata: 'H256'      // Fixed 32 bytes
             },
             Transaction: {
                 inputs: 'Vec<TransactionInput>',  // Vec type
@@ -31,7 +37,7 @@ async function main() {
 
     const inputs = [{
         // the latest UTXO hash of the account want to spent
-        outpoint: "0xc670c5f69081da78af400552edcafa3f0f31e84db1b50dd70776e0f87477b3dc",
+        outpoint: "0x6237accd3ed6ad3be7ac0c328daa5c0f860686432fac3c5756446095b5b0a88c",
         // default simple sigscript
         sigscript: "0x00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000",
     }]
@@ -41,12 +47,18 @@ async function main() {
             value: "842624",
             // Bob pubkey
             pubkey: "0x8eaf04151687736326c9fea17e25fc5287613693c912909cb226aa4794f26a48",
+            // Native asset
+            asset: "0x0000000000000000000000000000000000000000000000000000000000000000",
+            nft: null,
         },
         {
             // Value to spend
             value: "1125899906000000",
             // Alice pubkey
             pubkey: "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d",
+            // Native asset
+            asset: "0x0000000000000000000000000000000000000000000000000000000000000000",
+            nft: null,
         },
     ]
     // Encode full transaction